@@ -1,4 +1,4 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::env;
 use std::fs::File;
 use std::io::BufWriter;
@@ -8,7 +8,17 @@ use std::sync::LazyLock;
 
 static DEFAULT_PINYIN_DATA: &str = include_str!("data/pinyin.txt");
 
-static DEFAULT_STOPWORD: &str = include_str!("data/stopword.txt");
+static DEFAULT_PHRASE_PINYIN_DATA: &str = include_str!("data/phrase_pinyin.txt");
+
+/// 内置停词表，语言代码和停词数据
+static DEFAULT_STOPWORDS: [(&str, &str); 2] = [
+    ("en", include_str!("data/stopword_en.txt")),
+    ("zh", include_str!("data/stopword_zh.txt")),
+];
+
+static DEFAULT_CONFUSABLES_DATA: &str = include_str!("data/confusables.txt");
+
+static DEFAULT_IGNORABLE_DATA: &str = include_str!("data/default_ignorable.txt");
 
 /// 带声调的韵母和和不带声调的韵母的映射
 static TONE_TO_PLAIN: LazyLock<HashMap<char, char>> = LazyLock::new(|| {
@@ -47,9 +57,9 @@ static TONE_TO_PLAIN: LazyLock<HashMap<char, char>> = LazyLock::new(|| {
     ])
 });
 
-/// 将拼音中带有声调的韵母转换为不带声调的韵母
-fn to_plain(input: &str) -> String {
-    let value = input
+/// 去掉拼音中的声调
+fn strip_tone(input: &str) -> String {
+    input
         .chars()
         .map(|ch| {
             if let Some(char) = TONE_TO_PLAIN.get(&ch) {
@@ -58,7 +68,12 @@ fn to_plain(input: &str) -> String {
                 ch
             }
         })
-        .collect::<String>();
+        .collect::<String>()
+}
+
+/// 将拼音中带有声调的韵母转换为不带声调的韵母，多个读音按照字母顺序排列
+fn to_plain(input: &str) -> String {
+    let value = strip_tone(input);
     let values = value.split(",").map(str::trim).collect::<BTreeSet<&str>>();
     let mut pinyin = "\"".to_owned();
     let len = values.len() - 1;
@@ -73,15 +88,33 @@ fn to_plain(input: &str) -> String {
     pinyin
 }
 
+/// 去掉声调后保持数据中读音的顺序，常用的读音在前，重复的读音只保留第一个
+fn to_plain_by_usage(input: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    for value in strip_tone(input).split(",").map(str::trim) {
+        if !values.iter().any(|v| v == value) {
+            values.push(value.to_owned());
+        }
+    }
+    values
+}
+
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=data/pinyin.txt");
-    println!("cargo:rerun-if-changed=data/stopword.txt");
+    println!("cargo:rerun-if-changed=data/phrase_pinyin.txt");
+    println!("cargo:rerun-if-changed=data/stopword_en.txt");
+    println!("cargo:rerun-if-changed=data/stopword_zh.txt");
+    println!("cargo:rerun-if-changed=data/confusables.txt");
+    println!("cargo:rerun-if-changed=data/default_ignorable.txt");
 
     // 借助汉字码点和拼音的映射表，构建一个 char 与拼音映射的全局字典
     let path = Path::new(&env::var("OUT_DIR").unwrap()).join("pinyin_data.rs");
     let mut file = BufWriter::new(File::create(&path).unwrap());
     let mut dirt = phf_codegen::Map::new();
+    // 多音字按照常用程度排列的读音，只用于 jieba 按词语确定读音
+    let mut usage = phf_codegen::Map::new();
+    let mut pinyin_chars = HashSet::new();
     for line in DEFAULT_PINYIN_DATA.split("\n") {
         if line.is_empty() || line.starts_with("#") {
             continue;
@@ -94,10 +127,15 @@ fn main() {
             char::default()
         };
         let pinyin = if let Some(pinyin) = codepoint_and_pinyin.next() {
+            let by_usage = to_plain_by_usage(pinyin);
+            if by_usage.len() > 1 {
+                usage.entry(codepoint, format!("\"{}\"", by_usage.join(",")));
+            }
             to_plain(pinyin)
         } else {
             String::default()
         };
+        pinyin_chars.insert(codepoint);
         dirt.entry(codepoint, pinyin);
     }
 
@@ -108,23 +146,111 @@ fn main() {
     )
     .unwrap();
     writeln!(&mut file, ";").unwrap();
+    write!(
+        &mut file,
+        "static PINYIN_BY_USAGE: phf::Map<char, &'static str> = {}",
+        usage.build()
+    )
+    .unwrap();
+    writeln!(&mut file, ";").unwrap();
+
+    // 构建词语与拼音的映射，拼音之间以空格分隔
+    let mut phrase = phf_codegen::Map::new();
+    for line in DEFAULT_PHRASE_PINYIN_DATA.split("\n") {
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+        let Some((word, pinyin)) = line.split_once(": ") else {
+            continue;
+        };
+        phrase.entry(word.trim(), format!("\"{}\"", strip_tone(pinyin.trim())));
+    }
+
+    write!(
+        &mut file,
+        "static PHRASE_PINYIN: phf::Map<&'static str, &'static str> = {}",
+        phrase.build()
+    )
+    .unwrap();
+    writeln!(&mut file, ";").unwrap();
 
-    // 构建停词表
+    // 按照语言分别构建停词表，例如 STOPWORD_EN、STOPWORD_ZH
     let path = Path::new(&env::var("OUT_DIR").unwrap()).join("stopword_data.rs");
     let mut file = BufWriter::new(File::create(&path).unwrap());
-    let mut stopword = phf_codegen::OrderedSet::new();
-    for line in DEFAULT_STOPWORD.split("\n") {
+    for (lang, data) in DEFAULT_STOPWORDS {
+        let mut stopword = phf_codegen::OrderedSet::new();
+        for line in data.split("\n") {
+            if line.is_empty() || line.starts_with("#") {
+                continue;
+            }
+            stopword.entry(line.trim());
+        }
+
+        write!(
+            &mut file,
+            "static STOPWORD_{}: phf::OrderedSet<&'static str> = {}",
+            lang.to_uppercase(),
+            stopword.build()
+        )
+        .unwrap();
+        writeln!(&mut file, ";").unwrap();
+    }
+
+    // 构建易混淆字符与原型的映射，ascii 字符和有拼音的汉字保持原样，避免改变正常的英文和中文文本
+    let path = Path::new(&env::var("OUT_DIR").unwrap()).join("confusables_data.rs");
+    let mut file = BufWriter::new(File::create(&path).unwrap());
+    let mut confusables = phf_codegen::Map::new();
+    for line in DEFAULT_CONFUSABLES_DATA.split("\n") {
         if line.is_empty() || line.starts_with("#") {
             continue;
         }
-        stopword.entry(line.trim());
+        let mut fields = line.split(';');
+        let (Some(source), Some(prototype)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let source = parse_codepoint(source);
+        if source.is_ascii() || pinyin_chars.contains(&source) {
+            continue;
+        }
+        let prototype = prototype
+            .split_whitespace()
+            .map(parse_codepoint)
+            .collect::<String>();
+        confusables.entry(source, format!("{prototype:?}"));
     }
 
     write!(
         &mut file,
-        "static STOPWORD: phf::OrderedSet<&'static str> = {}",
-        stopword.build()
+        "static CONFUSABLES: phf::Map<char, &'static str> = {}",
+        confusables.build()
     )
     .unwrap();
     writeln!(&mut file, ";").unwrap();
+
+    // 默认可忽略字符的范围，按照码点排列
+    let mut ignorable = Vec::new();
+    for line in DEFAULT_IGNORABLE_DATA.split("\n") {
+        if line.is_empty() || line.starts_with("#") {
+            continue;
+        }
+        let range = line.split(';').next().unwrap().trim();
+        let (start, end) = range.split_once("..").unwrap_or((range, range));
+        ignorable.push(format!(
+            "({:?}, {:?})",
+            parse_codepoint(start),
+            parse_codepoint(end)
+        ));
+    }
+    writeln!(
+        &mut file,
+        "static DEFAULT_IGNORABLE: [(char, char); {}] = [{}];",
+        ignorable.len(),
+        ignorable.join(", ")
+    )
+    .unwrap();
+}
+
+/// 解析十六进制表示的码点
+fn parse_codepoint(codepoint: &str) -> char {
+    char::from_u32(u32::from_str_radix(codepoint.trim(), 16).unwrap()).unwrap()
 }
@@ -5,6 +5,15 @@ use std::fmt::Debug;
 pub enum Error {
     RegisterTokenizerFailure(RegisterTokenizerError),
     SimpleQueryInputTypeIncorrect(String),
+    SimpleQueryLimitIncorrect(String),
+    FunctionArgumentIncorrect(String),
+    TokenizerNotFound(String),
+    CreateTokenizerFailure(String, std::ffi::c_int),
+    TokenizeFailure(std::ffi::c_int),
+    LoadDictFailure(String),
+    LoadStopWordsFailure(String),
+    LoadProtectedTermsFailure(String),
+    TokenizerArgumentIncorrect(String),
     Utf8Error(std::str::Utf8Error),
     RusqliteError(rusqlite::Error),
 }
@@ -34,6 +43,28 @@ impl std::fmt::Display for Error {
             Error::SimpleQueryInputTypeIncorrect(ty) => {
                 write!(f, "input data must be text, got {ty}")
             }
+            Error::SimpleQueryLimitIncorrect(value) => {
+                write!(f, "query limit must be a positive integer, got {value}")
+            }
+            Error::FunctionArgumentIncorrect(msg) => {
+                write!(f, "invalid function argument: {msg}")
+            }
+            Error::TokenizerNotFound(name) => write!(f, "no such tokenizer: {name}"),
+            Error::CreateTokenizerFailure(spec, rc) => {
+                write!(
+                    f,
+                    "failed to create tokenizer '{spec}', sqlite returned {rc}"
+                )
+            }
+            Error::TokenizeFailure(rc) => write!(f, "failed to tokenize, sqlite returned {rc}"),
+            Error::LoadDictFailure(msg) => write!(f, "failed to load jieba dictionary: {msg}"),
+            Error::LoadStopWordsFailure(msg) => write!(f, "failed to load stopwords: {msg}"),
+            Error::LoadProtectedTermsFailure(msg) => {
+                write!(f, "failed to load protected terms: {msg}")
+            }
+            Error::TokenizerArgumentIncorrect(msg) => {
+                write!(f, "invalid tokenizer argument: {msg}")
+            }
             Error::Utf8Error(err) => std::fmt::Display::fmt(&err, f),
             Error::RusqliteError(err) => std::fmt::Display::fmt(&err, f),
         }
@@ -3,6 +3,7 @@ mod create_extension;
 mod error;
 mod load_extension;
 mod pinyin;
+mod table_function;
 mod tokenizer;
 mod utils;
 
@@ -10,9 +11,13 @@ include!(concat!(env!("OUT_DIR"), "/stopword_data.rs"));
 
 pub use error::Error;
 use load_extension::create_scalar_functions;
+use load_extension::create_table_functions;
 use load_extension::load_fts5_extension;
 use log::LevelFilter;
 use rusqlite::Connection;
+pub use tokenizer::jieba_dict::{
+    DEFAULT_DICT_NAME, JiebaDict, JiebaDicts, jieba_dicts, jieba_warm_up,
+};
 use utils::init_logging;
 
 pub fn load(connection: &Connection) -> Result<(), Error> {
@@ -24,6 +29,8 @@ pub fn load_with_loglevel(connection: &Connection, log_level: LevelFilter) -> Re
     init_logging(log_level);
     // 加载拓展函数
     create_scalar_functions(connection)?;
+    // 加载表值函数
+    create_table_functions(connection)?;
     // 加载 fts5 拓展
     load_fts5_extension(connection)
 }
@@ -49,6 +56,193 @@ mod tests {
         assert_eq!(["(g+u+o* OR gu+o* OR guo*)"], vec.as_slice());
     }
 
+    #[test]
+    fn test_simple_query_with_english() {
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT simple_query('Running'), simple_query('the'), simple_query('32.3')")
+            .unwrap();
+        let (running, the, number) = stmt
+            .query_row([], |row| {
+                Ok((
+                    row.get::<_, String>(0).unwrap(),
+                    row.get::<_, String>(1).unwrap(),
+                    row.get::<_, String>(2).unwrap(),
+                ))
+            })
+            .unwrap();
+        // 可以作为拼音的单词，同时给出拼音拆分和归一化后的单词，词干由查询时表的分词器提取
+        assert_eq!(
+            r#"(r+u+n+n+i+n+g* OR run+nin+g* OR run+ning* OR running* OR "running")"#,
+            running
+        );
+        // 以 = 开头的单词只匹配原词
+        let original = conn
+            .query_row("SELECT simple_query('=Running')", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .unwrap();
+        assert_eq!(r#"("=running")"#, original);
+        // 停词只保留拼音的解释
+        assert_eq!("(t+h+e* OR the*)", the);
+        // 不可能是拼音的单词，只保留原词
+        assert_eq!(r#"("32.3")"#, number);
+    }
+
+    #[test]
+    fn test_simple_query_with_limits() {
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        // 和 有多个读音，限制只使用第一个读音
+        let result = conn
+            .query_row("SELECT simple_query('和', 1)", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .unwrap();
+        assert_eq!("(h+e* OR he*)", result);
+        // 限制拼音组合的个数
+        let result = conn
+            .query_row("SELECT simple_query('zhuang', 4, 1)", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .unwrap();
+        assert_eq!(r#"(z+h+u+a+n+g* OR zhuang* OR "zhuang")"#, result);
+        // 查询项过多时，退化为精确匹配
+        let result = conn
+            .query_row(
+                "SELECT simple_query('中华人民共和国国歌', 4, 16, 8)",
+                [],
+                |row| row.get::<_, String>(0),
+            )
+            .unwrap();
+        assert_eq!(
+            r#"("中") AND ("华") AND ("人") AND ("民") AND ("共") AND ("和") AND ("国") AND ("国") AND ("歌")"#,
+            result
+        );
+        // 只有查询项最多的单词退化，拼音串保留音节个数最少的组合
+        let result = conn
+            .query_row("SELECT simple_query('zhongguo人', 4, 16, 5)", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .unwrap();
+        assert_eq!(
+            r#"(zhong+guo* OR "zhongguo") AND (r+e+n* OR re+n* OR ren*)"#,
+            result
+        );
+        // 退化后的长拼音串仍然可以匹配
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'simple');
+             INSERT INTO t1(text) VALUES ('中国国歌'), ('中国');",
+        )
+        .unwrap();
+        let count: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM t1 WHERE t1 MATCH simple_query('zhongguoguoge', 4, 16, 4)",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(1, count);
+        let result = conn.query_row("SELECT simple_query('国', 0)", [], |row| {
+            row.get::<_, String>(0)
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_jieba_keywords() {
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        let text = "今天纽约的天气真好啊，京华大酒店的张尧经理吃了一只北京烤鸭。后天纽约的天气不好，昨天纽约的天气也不好，北京烤鸭真好吃";
+        let result = conn
+            .query_row("SELECT jieba_keywords(?1, 3)", [text], |row| {
+                row.get::<_, String>(0)
+            })
+            .unwrap();
+        assert_eq!("北京烤鸭,纽约,天气", result);
+        let result = conn
+            .query_row("SELECT jieba_keywords(?1, 2, 'textrank')", [text], |row| {
+                row.get::<_, String>(0)
+            })
+            .unwrap();
+        assert_eq!(2, result.split(',').count());
+        let result = conn.query_row("SELECT jieba_keywords(?1, 2, 'unknown')", [text], |row| {
+            row.get::<_, String>(0)
+        });
+        assert!(result.is_err());
+        // 使用指定的词典
+        conn.query_row(
+            "SELECT jieba_add_word('张尧经理', 100000, 'nr', 'keywords_dict')",
+            [],
+            |_| Ok(()),
+        )
+        .unwrap();
+        let keywords = |dict_name: &str| -> String {
+            conn.query_row(
+                "SELECT jieba_keywords(?1, 10, NULL, ?2)",
+                [text, dict_name],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert!(!keywords("default").contains("张尧经理"));
+        assert!(keywords("keywords_dict").contains("张尧经理"));
+        let result = conn.query_row(
+            "SELECT jieba_keywords(?1, 2, NULL, 'missing')",
+            [text],
+            |row| row.get::<_, String>(0),
+        );
+        assert!(result.is_err());
+        // 词典可以在运行时修改，结果不是确定的
+        let flags: i64 = conn
+            .query_row(
+                "SELECT flags FROM pragma_function_list WHERE name = 'jieba_keywords' AND narg = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(0, flags & rusqlite::ffi::SQLITE_DETERMINISTIC as i64);
+    }
+
+    #[test]
+    fn test_jieba_query() {
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'jieba enable_pinyin');
+             INSERT INTO t1(text) VALUES ('中华人民共和国国歌'), ('国家'), ('银行卡'), ('I''m learning English');",
+        )
+        .unwrap();
+        let query = |text: &str| -> Vec<String> {
+            let mut stmt = conn
+                .prepare("SELECT text FROM t1 WHERE text MATCH jieba_query(?1) ORDER BY rowid")
+                .unwrap();
+            stmt.query_map([text], |row| row.get::<_, String>(0))
+                .unwrap()
+                .map(Result::unwrap)
+                .collect()
+        };
+        assert_eq!(vec!["国家"], query("国家"));
+        assert_eq!(vec!["国家"], query("guojia"));
+        assert_eq!(vec!["国家"], query("guoj"));
+        assert_eq!(vec!["国家"], query("gj"));
+        assert_eq!(vec!["中华人民共和国国歌"], query("zhrmghg 国歌"));
+        // 拼音前缀不提取词干
+        assert_eq!(vec!["中华人民共和国国歌"], query("guoge"));
+        // 多音字按照词语确定读音
+        assert_eq!(vec!["银行卡"], query("yinhang"));
+        assert!(query("yinxing").is_empty());
+        assert_eq!(vec!["I'm learning English"], query("learned"));
+        assert_eq!(
+            "(\"guojia\"* OR \"guojia\") AND (\"国歌\")",
+            conn.query_row("SELECT jieba_query('guojia 国歌')", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .unwrap()
+        );
+    }
+
     #[test]
     fn test_load() {
         let conn = Connection::open_in_memory().unwrap();
@@ -77,5 +271,18 @@ mod tests {
             vec.push(row)
         }
         assert_eq!(["中华人民共和国国歌", "国家"], vec.as_slice());
+        // 英文单词按照写入时的方式做词干提取
+        let mut stmt = conn
+            .prepare("SELECT * FROM t1 WHERE text MATCH simple_query('learned');")
+            .unwrap();
+        let result = stmt
+            .query_map([], |row| Ok(row.get::<_, String>(0).unwrap()))
+            .unwrap();
+        let mut vec = Vec::new();
+        for row in result {
+            let row = row.unwrap();
+            vec.push(row)
+        }
+        assert_eq!(["I'm learning English"], vec.as_slice());
     }
 }
@@ -1,22 +1,144 @@
+use crate::table_function::fts5_tokens::Fts5Tokens;
+use crate::table_function::jieba_cut::JiebaCut;
+use crate::table_function::jieba_tables::{self, JiebaTables};
+use crate::table_function::jieba_tag::JiebaTag;
+use crate::table_function::register_table_function;
+use crate::table_function::simple_query_explain::SimpleQueryExplain;
+use crate::tokenizer::jieba_dict::{DEFAULT_DICT_NAME, JiebaDict, jieba_dicts, jieba_warm_up};
 use crate::tokenizer::jieba_tokenizer::JiebaTokenizer;
 use crate::tokenizer::register_tokenizer;
-use crate::tokenizer::simple_tokenizer::SimpleTokenizer;
+use crate::tokenizer::simple_tokenizer::{QueryLimits, SimpleTokenizer};
+use crate::tokenizer::stopwords::reload_word_lists;
 use crate::utils::to_rusqlite_error;
+use jieba_rs::{KeywordExtract, TextRank, TfIdf};
 use rusqlite::Connection;
 use rusqlite::functions::Context as FunctionContext;
 use rusqlite::functions::FunctionFlags;
 use rusqlite::types::{ToSqlOutput, Value, ValueRef};
+use std::sync::{Arc, LazyLock};
+
+/// 使用 TF-IDF 提取关键词
+static TFIDF: LazyLock<TfIdf> = LazyLock::new(TfIdf::default);
+/// 使用 TextRank 提取关键词
+static TEXTRANK: LazyLock<TextRank> = LazyLock::new(TextRank::default);
 
 pub fn create_scalar_functions(connection: &Connection) -> Result<(), crate::Error> {
     let deterministic = FunctionFlags::SQLITE_DETERMINISTIC | FunctionFlags::SQLITE_UTF8;
+    // 结果依赖可以在运行时修改的 jieba 词典，不能标记为 deterministic
+    let dict_dependent = FunctionFlags::SQLITE_UTF8;
+
+    // simple_query(text [, max_readings [, max_alternatives [, max_terms]]])
+    for n_arg in 1..=4 {
+        connection.create_scalar_function(
+            "simple_query",
+            n_arg,
+            deterministic,
+            |ctx: &FunctionContext| simple_query(ctx).map_err(to_rusqlite_error),
+        )?;
+    }
+
+    // jieba_keywords(text [, k [, method [, dict_name]]])
+    for n_arg in 1..=4 {
+        connection.create_scalar_function(
+            "jieba_keywords",
+            n_arg,
+            dict_dependent,
+            |ctx: &FunctionContext| jieba_keywords(ctx).map_err(to_rusqlite_error),
+        )?;
+    }
+
+    let direct_only = FunctionFlags::SQLITE_DIRECTONLY | FunctionFlags::SQLITE_UTF8;
+
+    // jieba_add_word(word [, freq [, tag [, dict_name]]])
+    for n_arg in 1..=4 {
+        connection.create_scalar_function(
+            "jieba_add_word",
+            n_arg,
+            direct_only,
+            |ctx: &FunctionContext| jieba_add_word(ctx).map_err(to_rusqlite_error),
+        )?;
+    }
+
+    for n_arg in 0..=1 {
+        // jieba_reload([dict_name])
+        connection.create_scalar_function(
+            "jieba_reload",
+            n_arg,
+            direct_only,
+            |ctx: &FunctionContext| {
+                let dict = get_jieba_dict(ctx, 0, false).map_err(to_rusqlite_error)?;
+                let connection = unsafe { ctx.get_connection() }?;
+                if ctx.is_empty() {
+                    // 省略 dict_name 时同时重新加载只通过 dict= 和 dict_table= 加载用户词典的私有词典
+                    jieba_dicts().reload_private(&connection);
+                }
+                Ok(dict.reload(&connection) as i64)
+            },
+        )?;
+
+        // jieba_dict_version([dict_name])
+        connection.create_scalar_function(
+            "jieba_dict_version",
+            n_arg,
+            FunctionFlags::SQLITE_UTF8,
+            |ctx: &FunctionContext| {
+                let dict = get_jieba_dict(ctx, 0, false).map_err(to_rusqlite_error)?;
+                Ok(dict.version() as i64)
+            },
+        )?;
+    }
 
+    // jieba_rebuild(table)，重建表的索引并记录使用的词典版本
     connection.create_scalar_function(
-        "simple_query",
+        "jieba_rebuild",
         1,
-        deterministic,
-        |ctx: &FunctionContext| simple_query(ctx).map_err(to_rusqlite_error),
+        direct_only,
+        |ctx: &FunctionContext| {
+            let table = ctx.get::<String>(0)?;
+            let connection = unsafe { ctx.get_connection() }?;
+            jieba_tables::rebuild(&connection, &table).map_err(to_rusqlite_error)
+        },
     )?;
 
+    // stopwords_reload()，清空已经加载的停词表和保护词表，之后创建的 tokenizer 重新读取文件和表
+    connection.create_scalar_function(
+        "stopwords_reload",
+        0,
+        direct_only,
+        |_ctx: &FunctionContext| Ok(reload_word_lists() as i64),
+    )?;
+
+    // jieba_warm_up()，在后台线程中加载 jieba 默认词典，立即返回
+    connection.create_scalar_function(
+        "jieba_warm_up",
+        0,
+        FunctionFlags::SQLITE_UTF8,
+        |_ctx: &FunctionContext| {
+            jieba_warm_up();
+            Ok(Value::Null)
+        },
+    )?;
+
+    // jieba_query(text [, dict_name])
+    for n_arg in 1..=2 {
+        connection.create_scalar_function(
+            "jieba_query",
+            n_arg,
+            dict_dependent,
+            |ctx: &FunctionContext| jieba_query(ctx).map_err(to_rusqlite_error),
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn create_table_functions(connection: &Connection) -> Result<(), crate::Error> {
+    register_table_function::<SimpleQueryExplain>(connection)?;
+    register_table_function::<Fts5Tokens>(connection)?;
+    register_table_function::<JiebaCut>(connection)?;
+    register_table_function::<JiebaTag>(connection)?;
+    register_table_function::<JiebaTables>(connection)?;
+
     Ok(())
 }
 
@@ -37,13 +159,192 @@ fn simple_query<'a>(ctx: &FunctionContext) -> Result<ToSqlOutput<'a>, crate::Err
         }
     };
 
-    if let Some(match_sql) = SimpleTokenizer::tokenize_query(text) {
+    // 后续的参数依次是每个汉字最多使用的读音个数、每个单词最多保留的拼音组合个数、最多包含的查询项个数
+    let limits = get_query_limits((1..ctx.len()).map(|index| ctx.get_raw(index)))?;
+
+    if let Some(match_sql) = SimpleTokenizer::tokenize_query(text, &limits) {
         return Ok(ToSqlOutput::Owned(Value::Text(match_sql)));
     };
 
     empty_output
 }
 
+/// 获取 `simple_query` 中用于限制的参数，要求是一个正整数，NULL 表示使用默认值
+pub(crate) fn get_query_limits<'a>(
+    values: impl IntoIterator<Item = ValueRef<'a>>,
+) -> Result<QueryLimits, crate::Error> {
+    let mut limits = QueryLimits::default();
+    let limit_args = [
+        &mut limits.max_readings,
+        &mut limits.max_alternatives,
+        &mut limits.max_terms,
+    ];
+    for (limit, value) in limit_args.into_iter().zip(values) {
+        match value {
+            ValueRef::Null => {}
+            ValueRef::Integer(value) if value > 0 => *limit = value as usize,
+            ValueRef::Integer(value) => {
+                return Err(crate::Error::SimpleQueryLimitIncorrect(value.to_string()));
+            }
+            value => {
+                let ty = value.data_type().to_string();
+                return Err(crate::Error::SimpleQueryLimitIncorrect(ty));
+            }
+        }
+    }
+    Ok(limits)
+}
+
+/// 使用 jieba 从文档中提取关键词，多个关键词以逗号连接
+///
+/// 第二个参数是提取的关键词个数，默认为 5；第三个参数是提取的方法，可以是 `tfidf`（默认）和 `textrank`；
+/// 第四个参数是词典名称，省略时使用默认词典
+fn jieba_keywords<'a>(ctx: &FunctionContext) -> Result<ToSqlOutput<'a>, crate::Error> {
+    /// 默认提取的关键词个数
+    const DEFAULT_TOP_K: usize = 5;
+
+    let text = match ctx.get_raw(0) {
+        ValueRef::Text(t) => str::from_utf8(t)?,
+        ValueRef::Null => return Ok(ToSqlOutput::Owned(Value::Null)),
+        value => {
+            return Err(crate::Error::FunctionArgumentIncorrect(format!(
+                "input data must be text, got {}",
+                value.data_type()
+            )));
+        }
+    };
+    let top_k = match ctx.len() {
+        1 => DEFAULT_TOP_K,
+        _ => match ctx.get_raw(1) {
+            ValueRef::Null => DEFAULT_TOP_K,
+            ValueRef::Integer(k) if k > 0 => k as usize,
+            value => {
+                return Err(crate::Error::FunctionArgumentIncorrect(format!(
+                    "k must be a positive integer, got {value:?}"
+                )));
+            }
+        },
+    };
+    let method = match ctx.len() {
+        3.. => match ctx.get_raw(2) {
+            ValueRef::Null => "tfidf",
+            ValueRef::Text(t) => str::from_utf8(t)?,
+            value => {
+                return Err(crate::Error::FunctionArgumentIncorrect(format!(
+                    "method must be text, got {}",
+                    value.data_type()
+                )));
+            }
+        },
+        _ => "tfidf",
+    };
+    let extractor: &dyn KeywordExtract = match method {
+        "tfidf" => &*TFIDF,
+        "textrank" => &*TEXTRANK,
+        _ => {
+            return Err(crate::Error::FunctionArgumentIncorrect(format!(
+                "unknown keyword extraction method: {method}"
+            )));
+        }
+    };
+    let dict = get_jieba_dict(ctx, 3, false)?;
+    let keywords = extractor
+        .extract_keywords(&dict.jieba(), text, top_k, Vec::new())
+        .into_iter()
+        .map(|keyword| keyword.keyword)
+        .collect::<Vec<String>>();
+    Ok(ToSqlOutput::Owned(Value::Text(keywords.join(","))))
+}
+
+/// 将要查询的文档使用 jieba 分词，汉语词语精确匹配，拼音对全拼和首字母做前缀匹配
+///
+/// 返回的一个 SQLite 支持的 match 子句，适用于启用了 `enable_pinyin` 的 jieba 分词器
+fn jieba_query<'a>(ctx: &FunctionContext) -> Result<ToSqlOutput<'a>, crate::Error> {
+    let text = match ctx.get_raw(0) {
+        ValueRef::Text(t) => str::from_utf8(t)?,
+        value => {
+            let ty = value.data_type().to_string();
+            return Err(crate::Error::SimpleQueryInputTypeIncorrect(ty));
+        }
+    };
+    let dict = get_jieba_dict(ctx, 1, false)?;
+    match JiebaTokenizer::tokenize_query(text, &dict) {
+        Some(match_sql) => Ok(ToSqlOutput::Owned(Value::Text(match_sql))),
+        None => Ok(ToSqlOutput::Owned(Value::Null)),
+    }
+}
+
+/// 往 jieba 词典中加入一个单词，返回这个单词的词频
+///
+/// 第二个参数是词频，省略或者为 NULL 时由 jieba 计算一个使这个单词能被切分出来的词频；第三个参数是词性；
+/// 第四个参数是词典名称，不存在时创建这个词典
+fn jieba_add_word<'a>(ctx: &FunctionContext) -> Result<ToSqlOutput<'a>, crate::Error> {
+    let word = match ctx.get_raw(0) {
+        ValueRef::Text(t) if !t.is_empty() => str::from_utf8(t)?,
+        value => {
+            return Err(crate::Error::FunctionArgumentIncorrect(format!(
+                "word must be non-empty text, got {value:?}"
+            )));
+        }
+    };
+    let freq = match ctx.len() {
+        1 => None,
+        _ => match ctx.get_raw(1) {
+            ValueRef::Null => None,
+            ValueRef::Integer(freq) if freq > 0 => Some(freq as usize),
+            value => {
+                return Err(crate::Error::FunctionArgumentIncorrect(format!(
+                    "freq must be a positive integer, got {value:?}"
+                )));
+            }
+        },
+    };
+    let tag = match ctx.len() {
+        3.. => match ctx.get_raw(2) {
+            ValueRef::Null => None,
+            ValueRef::Text(t) => Some(str::from_utf8(t)?),
+            value => {
+                return Err(crate::Error::FunctionArgumentIncorrect(format!(
+                    "tag must be text, got {}",
+                    value.data_type()
+                )));
+            }
+        },
+        _ => None,
+    };
+    let freq = get_jieba_dict(ctx, 3, true)?.add_word(word, freq, tag);
+    Ok(ToSqlOutput::Owned(Value::Integer(freq as i64)))
+}
+
+/// 获取第 `index` 个参数指定的 jieba 词典，省略或者为 NULL 时使用默认词典
+///
+/// 词典不存在时，如果 `create` 为 `true` 则创建这个词典，否则返回错误
+fn get_jieba_dict(
+    ctx: &FunctionContext,
+    index: usize,
+    create: bool,
+) -> Result<Arc<JiebaDict>, crate::Error> {
+    let name = if index < ctx.len() {
+        match ctx.get_raw(index) {
+            ValueRef::Null => DEFAULT_DICT_NAME,
+            ValueRef::Text(t) => str::from_utf8(t)?,
+            value => {
+                return Err(crate::Error::FunctionArgumentIncorrect(format!(
+                    "dict_name must be text, got {}",
+                    value.data_type()
+                )));
+            }
+        }
+    } else {
+        DEFAULT_DICT_NAME
+    };
+    let dicts = jieba_dicts();
+    if create {
+        return Ok(dicts.get_or_create(name));
+    }
+    dicts.find(name)
+}
+
 pub fn load_fts5_extension(connection: &Connection) -> Result<(), crate::Error> {
     // 注册 simple_tokenizer
     register_tokenizer::<SimpleTokenizer>(connection, ())?;
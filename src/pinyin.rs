@@ -9,7 +9,7 @@ pub fn has_pinyin(ch: &char) -> bool {
     PINYIN_DIRT.contains_key(ch)
 }
 
-/// 通过字符获取拼音
+/// 通过字符获取拼音，多个读音按照字母顺序排列
 pub fn get_pinyin(ch: &char) -> Option<Vec<String>> {
     let pinyin = PINYIN_DIRT.get(ch)?;
     let result = pinyin
@@ -19,20 +19,100 @@ pub fn get_pinyin(ch: &char) -> Option<Vec<String>> {
     Some(result)
 }
 
-/// 获取这个拼音字符串中全部拼音组合，包含原始输入、全部字母组合、全部合法拼音组合
+/// 通过字符获取拼音，多个读音按照常用程度排列，常用的读音在前
+fn get_pinyin_by_usage(ch: &char) -> Option<Vec<String>> {
+    match PINYIN_BY_USAGE.get(ch) {
+        Some(pinyin) => Some(pinyin.split(",").map(str::to_owned).collect()),
+        None => get_pinyin(ch),
+    }
+}
+
+/// 获取词语的拼音，每个组合依次是每个汉字的一个读音
+///
+/// 词语中包含在多音字词语表中的词语时，这些汉字只使用表中的读音，从左到右优先匹配最长的词语；
+/// 其余汉字按照读音的常用程度组合，最多返回 `max_readings` 个组合，第一个组合由每个汉字最常用的读音组成。
+/// 词语中有字符没有拼音时返回空
 ///
-/// 如果提供空串、一个字母的拼音串、超过 20 个字符的拼音串均不处理，原样返回
+/// 例如 `银行卡` 得到 `[["yin", "hang", "ka"], ["yin", "hang", "qia"]]`，
+/// `国家` 得到 `[["guo", "jia"], ["guo", "jie"], ["guo", "gu"]]`
+pub fn get_word_pinyin(word: &str, max_readings: usize) -> Vec<Vec<String>> {
+    let chars = word.char_indices().collect::<Vec<(usize, char)>>();
+    if chars.is_empty() {
+        return Vec::new();
+    }
+    // 每个汉字可以使用的读音
+    let mut char_readings = Vec::with_capacity(chars.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let start = chars[i].0;
+        let phrase = (i + 2..=chars.len()).rev().find_map(|j| {
+            let end = chars.get(j).map_or(word.len(), |(index, _)| *index);
+            PHRASE_PINYIN.get(&word[start..end])
+        });
+        if let Some(phrase) = phrase {
+            for pinyin in phrase.split(' ') {
+                char_readings.push(vec![pinyin.to_owned()]);
+                i += 1;
+            }
+            continue;
+        }
+        let Some(readings) = get_pinyin_by_usage(&chars[i].1) else {
+            return Vec::new();
+        };
+        char_readings.push(readings);
+        i += 1;
+    }
+    // 每个组合记录读音在各个汉字中的序号之和，序号越小越常用
+    let mut combinations = vec![(0, Vec::new())];
+    for readings in char_readings {
+        let mut next = Vec::with_capacity(combinations.len() * readings.len());
+        for (rank, combination) in &combinations {
+            for (index, reading) in readings.iter().enumerate() {
+                let mut combination: Vec<String> = combination.clone();
+                combination.push(reading.clone());
+                next.push((rank + index, combination));
+            }
+        }
+        // 只保留最常用的组合，由于序号之和只会增加，被丢弃的组合不会成为最终结果中更常用的组合
+        next.sort_by_key(|(rank, _)| *rank);
+        next.truncate(max_readings.max(1));
+        combinations = next;
+    }
+    combinations
+        .into_iter()
+        .map(|(_, combination)| combination)
+        .collect()
+}
+
+/// 拆分拼音串时，默认最多保留的拼音组合个数
+pub const DEFAULT_MAX_ALTERNATIVES: usize = 16;
+
+/// 最长的合法拼音的字母个数
+const MAX_SYLLABLE_LEN: usize = 6;
+
+/// 获取这个拼音字符串中的拼音组合，包含原始输入、全部字母组合、合法拼音组合
+///
+/// 合法拼音组合最多保留 `max_alternatives` 个，音节个数少的组合优先保留；
+/// 如果提供空串、一个字母的拼音串均不处理，原样返回
 ///
 /// 例如
 /// - `ba` 得到 `{"ba", "b+a"}`
 /// - `zhuang` 得到 `{"zhuang", "z+h+u+a+n+g", "zhu+ang", "zhu+an+g", "zhuan+g"}`
 /// - `zhangliangying` 得到  `{"zhangliangying", "zhang+li+ang+yin+g", "zhang+li+ang+ying", "zhang+liang+yin+g", "zhang+liang+ying", "z+h+a+n+g+l+i+a+n+g+y+i+n+g"}`
 /// - `zhangliangy` 得到  `{"zhangliangy", "zhang+li+ang+y", "zhang+liang+y", "z+h+a+n+g+l+i+a+n+g+y+i+n+g"}`
-pub fn split_pinyin(input: &str) -> BTreeSet<String> {
+pub fn split_pinyin(input: &str, max_alternatives: usize) -> BTreeSet<String> {
+    split_pinyin_with_limit(input, max_alternatives)
+        .into_iter()
+        .collect()
+}
+
+/// 获取这个拼音字符串中的拼音组合，最多保留 `max_alternatives` 个合法拼音组合
+///
+/// 返回的结果按照音节个数从少到多排列，第一个是原始输入，最后一个是全部字母组合
+pub fn split_pinyin_with_limit(input: &str, max_alternatives: usize) -> Vec<String> {
     let len = input.chars().count();
-    const MAX_LEN: usize = 20;
-    if len <= 1 || len > MAX_LEN {
-        return BTreeSet::from([input.to_owned()]);
+    if len <= 1 {
+        return vec![input.to_owned()];
     }
     let spaced = input
         .chars()
@@ -44,53 +124,77 @@ pub fn split_pinyin(input: &str) -> BTreeSet<String> {
         .chars()
         .skip(1)
         .collect::<String>();
-    if len > 2 {
-        let pinyin = split_pinyin_with_index(input, 0, len);
-        // 去重
-        let mut pinyin = pinyin.into_iter().collect::<BTreeSet<_>>();
-        pinyin.insert(spaced);
-        pinyin.insert(input.to_owned());
-        pinyin
-    } else {
-        BTreeSet::from([input.to_owned(), spaced])
+    let mut result = vec![input.to_owned()];
+    if len > 2 && input.is_ascii() {
+        for syllables in split_syllables(input, true, max_alternatives) {
+            let pinyin = syllables.join("+");
+            if pinyin != spaced && !result.contains(&pinyin) {
+                result.push(pinyin);
+            }
+        }
     }
+    result.push(spaced);
+    result
 }
 
-/// 用于获取这个拼音字符串中全部拼音组合
+/// 将连续的拼音串切分成音节个数最少的拼音组合，无法完整切分时返回 `None`
 ///
-/// 在列举拼音组合时，前面都需要考虑符合完整的拼音，最后一个字母可以只考虑是否是某个拼音的前缀。
-fn split_pinyin_with_index(input: &str, begin: usize, end: usize) -> Vec<String> {
-    if begin >= end {
-        return Vec::new();
+/// 如果 `allow_prefix` 为 `true`，最后一个音节可以只是某个拼音的前缀，例如 `zhongg` 得到 `["zhong", "g"]`；
+/// 音节个数相同时，优先选择前面的音节更长的组合，例如 `fangan` 得到 `["fang", "an"]`
+pub fn segment_pinyin(input: &str, allow_prefix: bool) -> Option<Vec<&str>> {
+    if input.is_empty() || !input.is_ascii() {
+        return None;
     }
-    if begin == end - 1 {
-        // 只有一个字符的时候，将这个字符返回
-        return vec![input[begin..end].to_owned()];
-    }
-    let mut result = Vec::<String>::new();
-    let full_str = &input[begin..end];
-    if PINYIN_PREFIX.contains(full_str) || PINYIN_VALID.contains(full_str) {
-        // 整个字符串是合法的拼音，那么保存到结果中
-        result.push(full_str.to_owned());
+    split_syllables(input, allow_prefix, 1).pop()
+}
+
+/// 使用动态规划将 ascii 拼音串切分成拼音组合，最多返回 `limit` 个
+///
+/// 除最后一个音节外都必须是合法拼音；如果 `allow_prefix` 为 `true`，最后一个音节可以是某个拼音的前缀或者单个字母。
+/// 返回的组合按照音节个数从少到多排列，音节个数相同时，前面的音节更长的组合优先
+fn split_syllables(input: &str, allow_prefix: bool, limit: usize) -> Vec<Vec<&str>> {
+    let len = input.len();
+    if len == 0 || limit == 0 {
+        return Vec::new();
     }
-    let mut start = begin + 1;
-    while start < end {
-        let first = &input[begin..start];
-        if !PINYIN_VALID.contains(first) {
-            // 当前子串不是合法拼音，调整索引，往当前子串添加一个新字符
-            start += 1;
-            continue;
-        }
-        // 找到第一个拼音，后将剩余的子串继续分割
-        let tmp = split_pinyin_with_index(input, start, end);
-        // 只有剩余的子串中有合法拼音，那么这个拼音有效
-        // 如果剩余的子串没有合法拼音，那么这个拼音和后续的字符才是一个比较完整的拼音，将跳过这个拼音
-        for s in tmp {
-            result.push(format!("{first}+{s}"));
+    // best[i] 保存从 i 开始的子串的最优的若干个切分，每个切分记录为 (音节个数, 第一个音节的结束位置, 后续子串切分的序号)
+    let mut best: Vec<Vec<(usize, usize, usize)>> = vec![Vec::new(); len + 1];
+    best[len].push((0, len, 0));
+    for begin in (0..len).rev() {
+        let mut candidates = Vec::new();
+        // 音节从长到短排列，保证音节个数相同时，前面的音节更长的组合排在前面
+        for end in (begin + 1..=len.min(begin + MAX_SYLLABLE_LEN)).rev() {
+            let syllable = &input[begin..end];
+            let is_valid = PINYIN_VALID.contains(syllable)
+                || (allow_prefix
+                    && end == len
+                    && (syllable.len() == 1 || PINYIN_PREFIX.contains(syllable)));
+            if !is_valid {
+                continue;
+            }
+            for (rank, (count, _, _)) in best[end].iter().enumerate() {
+                candidates.push((count + 1, end, rank));
+            }
         }
-        start += 1
+        // 排序是稳定的，音节个数相同的组合保持原有的先后顺序
+        candidates.sort_by_key(|(count, _, _)| *count);
+        candidates.truncate(limit);
+        best[begin] = candidates;
     }
-    result
+    best[0]
+        .iter()
+        .map(|&first| {
+            let mut syllables = Vec::new();
+            let (mut begin, mut node) = (0, first);
+            while begin < len {
+                let (_, end, rank) = node;
+                syllables.push(&input[begin..end]);
+                node = best[end][rank];
+                begin = end;
+            }
+            syllables
+        })
+        .collect()
 }
 
 /// 不是合法拼音，但可以是拼音前缀，只能出现在将拼音分割成拼音组合的结尾
@@ -148,7 +252,10 @@ static PINYIN_VALID: phf::Set<&'static str> = phf_set! {
 
 #[cfg(test)]
 mod tests {
-    use crate::pinyin::{PINYIN_DIRT, get_pinyin, split_pinyin};
+    use crate::pinyin::{
+        DEFAULT_MAX_ALTERNATIVES, PINYIN_DIRT, get_pinyin, get_word_pinyin, segment_pinyin,
+        split_pinyin, split_pinyin_with_limit,
+    };
     use std::collections::BTreeSet;
 
     #[test]
@@ -174,16 +281,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_word_pinyin() {
+        let pinyin = |word: &str, max_readings: usize| -> Vec<String> {
+            get_word_pinyin(word, max_readings)
+                .iter()
+                .map(|combination| combination.join("+"))
+                .collect()
+        };
+        assert_eq!(vec!["yin+hang"], pinyin("银行", 4));
+        assert_eq!(vec!["chong+qing"], pinyin("重庆", 4));
+        assert_eq!(vec!["yin+hang+ka", "yin+hang+qia"], pinyin("银行卡", 4));
+        assert_eq!(vec!["chong+qing+cheng+du"], pinyin("重庆成都", 4));
+        assert_eq!(vec!["guo+jia", "guo+jie", "guo+gu"], pinyin("国家", 4));
+        assert_eq!(vec!["guo+jia"], pinyin("国家", 1));
+        assert_eq!(vec!["zhong+hua"], pinyin("中华", 4));
+        // 按词语确定读音时，多音字按照常用程度排列
+        assert_eq!(vec!["shuo+hua"], pinyin("说话", 1));
+        assert!(pinyin("国a", 4).is_empty());
+        assert!(pinyin("", 4).is_empty());
+    }
+
     #[test]
     fn test_split_pinyin() {
         let input = "";
-        assert_eq!(BTreeSet::from(["".to_owned()]), split_pinyin(input));
+        assert_eq!(
+            BTreeSet::from(["".to_owned()]),
+            split_pinyin(input, DEFAULT_MAX_ALTERNATIVES)
+        );
         let input = "a";
-        assert_eq!(BTreeSet::from(["a".to_owned()]), split_pinyin(input));
+        assert_eq!(
+            BTreeSet::from(["a".to_owned()]),
+            split_pinyin(input, DEFAULT_MAX_ALTERNATIVES)
+        );
         let input = "ba";
         assert_eq!(
             BTreeSet::from(["ba".to_owned(), "b+a".to_owned()]),
-            split_pinyin(input)
+            split_pinyin(input, DEFAULT_MAX_ALTERNATIVES)
         );
         let input = "zhuang";
         assert_eq!(
@@ -194,7 +328,7 @@ mod tests {
                 "zhu+ang".to_owned(),
                 "zhu+an+g".to_owned(),
             ]),
-            split_pinyin(input)
+            split_pinyin(input, DEFAULT_MAX_ALTERNATIVES)
         );
         let input = "zhangliangy";
         assert_eq!(
@@ -204,7 +338,7 @@ mod tests {
                 "zhang+li+ang+y".to_owned(),
                 "zhang+liang+y".to_owned(),
             ]),
-            split_pinyin(input)
+            split_pinyin(input, DEFAULT_MAX_ALTERNATIVES)
         );
         let input = "zhangliangying";
         assert_eq!(
@@ -216,12 +350,63 @@ mod tests {
                 "zhang+liang+yin+g".to_owned(),
                 "z+h+a+n+g+l+i+a+n+g+y+i+n+g".to_owned()
             ]),
-            split_pinyin(input)
+            split_pinyin(input, DEFAULT_MAX_ALTERNATIVES)
         );
         let input = "zhangliangyingzhangliangying";
         assert_eq!(
-            BTreeSet::from(["zhangliangyingzhangliangying".to_owned()]),
-            split_pinyin(input)
+            BTreeSet::from([
+                "zhangliangyingzhangliangying".to_owned(),
+                "zhang+liang+ying+zhang+liang+ying".to_owned(),
+                "zhang+liang+ying+zhang+liang+yin+g".to_owned(),
+                "zhang+liang+ying+zhang+li+ang+ying".to_owned(),
+                "zhang+liang+ying+zhang+li+ang+yin+g".to_owned(),
+                "zhang+li+ang+ying+zhang+liang+ying".to_owned(),
+                "zhang+li+ang+ying+zhang+liang+yin+g".to_owned(),
+                "zhang+li+ang+ying+zhang+li+ang+ying".to_owned(),
+                "zhang+li+ang+ying+zhang+li+ang+yin+g".to_owned(),
+                "z+h+a+n+g+l+i+a+n+g+y+i+n+g+z+h+a+n+g+l+i+a+n+g+y+i+n+g".to_owned(),
+            ]),
+            split_pinyin(input, DEFAULT_MAX_ALTERNATIVES)
+        );
+    }
+
+    #[test]
+    fn test_split_pinyin_with_limit() {
+        // 音节个数少的组合优先保留
+        assert_eq!(
+            vec![
+                "zhangliangying".to_owned(),
+                "zhang+liang+ying".to_owned(),
+                "zhang+liang+yin+g".to_owned(),
+                "z+h+a+n+g+l+i+a+n+g+y+i+n+g".to_owned(),
+            ],
+            split_pinyin_with_limit("zhangliangying", 2)
+        );
+        // 较长的拼音串也可以被拆分
+        let input = "zhonghuarenmingongheguozhongyangrenminzhengfu";
+        let result = split_pinyin_with_limit(input, DEFAULT_MAX_ALTERNATIVES);
+        assert_eq!(
+            "zhong+hua+ren+min+gong+he+guo+zhong+yang+ren+min+zheng+fu",
+            result[1]
+        );
+        assert!(result.len() <= DEFAULT_MAX_ALTERNATIVES + 2);
+    }
+
+    #[test]
+    fn test_segment_pinyin() {
+        assert_eq!(None, segment_pinyin("", false));
+        assert_eq!(
+            Some(vec!["zhong", "guo"]),
+            segment_pinyin("zhongguo", false)
+        );
+        assert_eq!(Some(vec!["xian"]), segment_pinyin("xian", false));
+        assert_eq!(Some(vec!["fang", "an"]), segment_pinyin("fangan", false));
+        assert_eq!(None, segment_pinyin("zhongg", false));
+        assert_eq!(Some(vec!["zhong", "g"]), segment_pinyin("zhongg", true));
+        assert_eq!(
+            Some(vec!["zhong", "hua", "ren", "min"]),
+            segment_pinyin("zhonghuarenmin", false)
         );
+        assert_eq!(None, segment_pinyin("tokenizer", false));
     }
 }
@@ -0,0 +1,246 @@
+use crate::table_function::TableFunction;
+use crate::tokenizer::{TokenizeReason, tokenize_with_fts5};
+use rusqlite::Connection;
+use rusqlite::types::Value;
+use std::ffi::CStr;
+
+/// 使用已经注册到 FTS5 中的 tokenizer 对文本分词，每个 token 输出一行
+///
+/// `SELECT * FROM fts5_tokens('simple disable_stopword', '我将点燃星海 stars')`
+///
+/// 第一个参数与 FTS5 表的 `tokenize` 选项写法一致，可选的第三个参数是分词的原因，
+/// 可以是 `document`（默认）、`query`、`prefix` 和 `aux`；可选的第四个参数是 locale，与 `fts5_locale()` 一致
+pub struct Fts5Tokens;
+
+impl TableFunction for Fts5Tokens {
+    fn name() -> &'static CStr {
+        c"fts5_tokens"
+    }
+
+    fn columns() -> &'static [&'static str] {
+        &["token", "start", "end", "colocated"]
+    }
+
+    fn arguments() -> &'static [&'static str] {
+        &["tokenizer", "input", "reason", "locale"]
+    }
+
+    fn required_arguments() -> usize {
+        2
+    }
+
+    fn rows(connection: &Connection, args: &[Value]) -> Result<Vec<Vec<Value>>, crate::Error> {
+        let (Value::Text(tokenizer), Value::Text(text)) = (&args[0], &args[1]) else {
+            return Err(crate::Error::FunctionArgumentIncorrect(
+                "tokenizer and input must be text".to_owned(),
+            ));
+        };
+        let reason = match &args[2] {
+            Value::Null => TokenizeReason::Document,
+            Value::Text(reason) => match reason.as_str() {
+                "document" => TokenizeReason::Document,
+                "query" => TokenizeReason::Query { prefix: false },
+                "prefix" => TokenizeReason::Query { prefix: true },
+                "aux" => TokenizeReason::Aux,
+                _ => {
+                    return Err(crate::Error::FunctionArgumentIncorrect(format!(
+                        "unknown tokenize reason: {reason}"
+                    )));
+                }
+            },
+            value => {
+                return Err(crate::Error::FunctionArgumentIncorrect(format!(
+                    "tokenize reason must be text, got {}",
+                    value.data_type()
+                )));
+            }
+        };
+        let locale = match &args[3] {
+            Value::Null => None,
+            Value::Text(locale) => Some(locale.as_str()),
+            value => {
+                return Err(crate::Error::FunctionArgumentIncorrect(format!(
+                    "locale must be text, got {}",
+                    value.data_type()
+                )));
+            }
+        };
+        let args = parse_tokenizer_args(tokenizer);
+        let tokens = tokenize_with_fts5(connection, &args, reason, text, locale)?;
+        let rows = tokens
+            .into_iter()
+            .map(|token| {
+                vec![
+                    Value::Text(token.token),
+                    Value::Integer(token.start as i64),
+                    Value::Integer(token.end as i64),
+                    Value::Integer(token.colocated as i64),
+                ]
+            })
+            .collect();
+        Ok(rows)
+    }
+}
+
+/// 按照 FTS5 解析 `tokenize` 选项的方式拆分参数
+///
+/// 参数之间以空白字符分隔，参数可以使用单引号、双引号、反引号或者方括号包裹，引号内连续的两个引号表示引号本身
+pub(crate) fn parse_tokenizer_args(spec: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut chars = spec.chars().peekable();
+    while let Some(&ch) = chars.peek() {
+        if ch.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut arg = String::new();
+        let quote = match ch {
+            '\'' | '"' | '`' => Some(ch),
+            '[' => Some(']'),
+            _ => None,
+        };
+        if let Some(quote) = quote {
+            chars.next();
+            while let Some(ch) = chars.next() {
+                if ch == quote {
+                    if quote != ']' && chars.peek() == Some(&quote) {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                arg.push(ch);
+            }
+        } else {
+            while let Some(&ch) = chars.peek() {
+                if ch.is_whitespace() {
+                    break;
+                }
+                arg.push(ch);
+                chars.next();
+            }
+        }
+        args.push(arg);
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_tokenizer_args;
+    use crate::load;
+    use crate::tokenizer::TokenizeReason;
+    use crate::tokenizer::test_utils::tokenize;
+    use rusqlite::Connection;
+
+    fn query_tokens(conn: &Connection, sql: &str) -> Vec<(String, i64, i64, bool)> {
+        let mut stmt = conn.prepare(sql).unwrap();
+        let result = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0).unwrap(),
+                    row.get::<_, i64>(1).unwrap(),
+                    row.get::<_, i64>(2).unwrap(),
+                    row.get::<_, bool>(3).unwrap(),
+                ))
+            })
+            .unwrap();
+        let mut vec = Vec::new();
+        for row in result {
+            let row = row.unwrap();
+            vec.push(row)
+        }
+        vec
+    }
+
+    #[test]
+    fn test_parse_tokenizer_args() {
+        assert_eq!(
+            vec!["unicode61", "tokenchars", "-_", "remove_diacritics", "2"],
+            parse_tokenizer_args("unicode61 tokenchars '-_' remove_diacritics 2")
+        );
+        assert_eq!(
+            vec!["simple", "it's"],
+            parse_tokenizer_args(r#"  simple "it's"  "#)
+        );
+    }
+
+    #[test]
+    fn test_fts5_tokens() {
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        let tokens = query_tokens(
+            &conn,
+            "SELECT * FROM fts5_tokens('simple disable_stopword', '说 stars')",
+        );
+        assert_eq!(
+            vec![
+                ("shui".to_owned(), 0, 3, false),
+                ("shuo".to_owned(), 0, 3, false),
+                ("yue".to_owned(), 0, 3, false),
+                ("star".to_owned(), 4, 9, false),
+            ],
+            tokens
+        );
+        let tokens = query_tokens(&conn, "SELECT * FROM fts5_tokens('jieba', '我将点燃星海')");
+        assert_eq!(
+            vec![
+                ("点燃".to_owned(), 6, 12, false),
+                ("星海".to_owned(), 12, 18, false),
+            ],
+            tokens
+        );
+        // 内置的 tokenizer
+        let tokens = query_tokens(
+            &conn,
+            "SELECT * FROM fts5_tokens('unicode61', 'Hello World')",
+        );
+        assert_eq!(
+            vec![
+                ("hello".to_owned(), 0, 5, false),
+                ("world".to_owned(), 6, 11, false),
+            ],
+            tokens
+        );
+        let tokens = query_tokens(&conn, "SELECT * FROM fts5_tokens('trigram', 'abcd')");
+        assert_eq!(
+            vec![
+                ("abc".to_owned(), 0, 3, false),
+                ("bcd".to_owned(), 1, 4, false),
+            ],
+            tokens
+        );
+        // 查询时的分词
+        let tokens = query_tokens(
+            &conn,
+            "SELECT * FROM fts5_tokens('simple enable_pinyin_query', 'zhongguo', 'query')",
+        );
+        assert_eq!(
+            vec![
+                ("zhong".to_owned(), 0, 8, false),
+                ("guo".to_owned(), 0, 8, false),
+            ],
+            tokens
+        );
+        let result = conn.query_row(
+            "SELECT * FROM fts5_tokens('no_such_tokenizer', 'abcd')",
+            [],
+            |row| row.get::<_, String>(0),
+        );
+        assert!(result.is_err());
+        // 参数中的 NUL 字节是参数错误，而不是找不到 tokenizer
+        assert!(matches!(
+            tokenize(
+                &conn,
+                &["simple", "stopwords+=a\0b"],
+                TokenizeReason::Document,
+                "x"
+            ),
+            Err(crate::Error::TokenizerArgumentIncorrect(_))
+        ));
+        assert!(matches!(
+            tokenize(&conn, &["simple\0"], TokenizeReason::Document, "x"),
+            Err(crate::Error::TokenizerNotFound(_))
+        ));
+    }
+}
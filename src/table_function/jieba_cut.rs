@@ -0,0 +1,159 @@
+use crate::table_function::{TableFunction, get_hmm, get_jieba_dict};
+use crate::tokenizer::jieba_tokenizer::CutMode;
+use crate::utils::offset_in;
+use rusqlite::Connection;
+use rusqlite::types::Value;
+use std::ffi::CStr;
+
+/// 使用 jieba 对文本分词，每个词输出一行
+///
+/// `SELECT * FROM jieba_cut('中华人民共和国', 'search', 1)`
+///
+/// 第二个参数是分词模式，可以是 `precise`（默认）、`all` 和 `search`；第三个参数表示是否启用 HMM，默认启用；
+/// 第四个参数是词典名称，默认使用默认词典
+pub struct JiebaCut;
+
+impl TableFunction for JiebaCut {
+    fn name() -> &'static CStr {
+        c"jieba_cut"
+    }
+
+    fn columns() -> &'static [&'static str] {
+        &["word", "start", "end"]
+    }
+
+    fn arguments() -> &'static [&'static str] {
+        &["input", "mode", "hmm", "dict_name"]
+    }
+
+    fn required_arguments() -> usize {
+        1
+    }
+
+    fn rows(_connection: &Connection, args: &[Value]) -> Result<Vec<Vec<Value>>, crate::Error> {
+        let Value::Text(text) = &args[0] else {
+            return Err(crate::Error::FunctionArgumentIncorrect(
+                "input must be text".to_owned(),
+            ));
+        };
+        let mode = match &args[1] {
+            Value::Null => CutMode::default(),
+            Value::Text(mode) => CutMode::from_name(mode).ok_or_else(|| {
+                crate::Error::FunctionArgumentIncorrect(format!("unknown cut mode: {mode}"))
+            })?,
+            value => {
+                return Err(crate::Error::FunctionArgumentIncorrect(format!(
+                    "cut mode must be text, got {}",
+                    value.data_type()
+                )));
+            }
+        };
+        let hmm = get_hmm(&args[2])?;
+        let dict = get_jieba_dict(&args[3])?;
+        let rows = mode
+            .cut(&dict.jieba(), text, hmm)
+            .into_iter()
+            .map(|word| {
+                let start = offset_in(text, word);
+                vec![
+                    Value::Text(word.to_owned()),
+                    Value::Integer(start as i64),
+                    Value::Integer((start + word.len()) as i64),
+                ]
+            })
+            .collect();
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::load;
+    use rusqlite::Connection;
+
+    fn query_words(conn: &Connection, sql: &str) -> Vec<(String, i64, i64)> {
+        let mut stmt = conn.prepare(sql).unwrap();
+        let result = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0).unwrap(),
+                    row.get::<_, i64>(1).unwrap(),
+                    row.get::<_, i64>(2).unwrap(),
+                ))
+            })
+            .unwrap();
+        let mut vec = Vec::new();
+        for row in result {
+            let row = row.unwrap();
+            vec.push(row)
+        }
+        vec
+    }
+
+    #[test]
+    fn test_jieba_cut() {
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        let words = query_words(&conn, "SELECT * FROM jieba_cut('我将点燃星海')");
+        assert_eq!(
+            vec![
+                ("我".to_owned(), 0, 3),
+                ("将".to_owned(), 3, 6),
+                ("点燃".to_owned(), 6, 12),
+                ("星海".to_owned(), 12, 18),
+            ],
+            words
+        );
+        let words = query_words(&conn, "SELECT * FROM jieba_cut('中华人民共和国', 'search')")
+            .into_iter()
+            .map(|(word, _, _)| word)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            vec!["中华", "华人", "人民", "共和", "共和国", "中华人民共和国"],
+            words
+        );
+        let words = query_words(&conn, "SELECT * FROM jieba_cut('中华人民', 'all', 0)");
+        assert_eq!(
+            vec![
+                ("中".to_owned(), 0, 3),
+                ("中华".to_owned(), 0, 6),
+                ("中华人民".to_owned(), 0, 12),
+                ("华".to_owned(), 3, 6),
+                ("华人".to_owned(), 3, 9),
+                ("人".to_owned(), 6, 9),
+                ("人民".to_owned(), 6, 12),
+                ("民".to_owned(), 9, 12),
+            ],
+            words
+        );
+        let result = conn.query_row("SELECT * FROM jieba_cut('中华', 'unknown')", [], |row| {
+            row.get::<_, String>(0)
+        });
+        assert!(result.is_err());
+        // 使用指定的词典
+        conn.query_row(
+            "SELECT jieba_add_word('点燃星海', NULL, NULL, 'cut_dict')",
+            [],
+            |_| Ok(()),
+        )
+        .unwrap();
+        let words = query_words(
+            &conn,
+            "SELECT * FROM jieba_cut('我将点燃星海', NULL, NULL, 'cut_dict')",
+        );
+        assert_eq!(
+            vec![
+                ("我".to_owned(), 0, 3),
+                ("将".to_owned(), 3, 6),
+                ("点燃星海".to_owned(), 6, 18)
+            ],
+            words
+        );
+        let result = conn.query_row(
+            "SELECT * FROM jieba_cut('中华', NULL, NULL, 'missing')",
+            [],
+            |row| row.get::<_, String>(0),
+        );
+        assert!(result.is_err());
+    }
+}
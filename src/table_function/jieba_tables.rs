@@ -0,0 +1,257 @@
+use crate::table_function::TableFunction;
+use crate::table_function::fts5_tokens::parse_tokenizer_args;
+use crate::tokenizer::Tokenizer;
+use crate::tokenizer::jieba_dict::JiebaDict;
+use crate::tokenizer::jieba_tokenizer::JiebaTokenizer;
+use rusqlite::types::Value;
+use rusqlite::{Connection, OptionalExtension};
+use std::ffi::CStr;
+use std::sync::Arc;
+
+/// 记录每个表重建索引时使用的词典指纹的表，由 `jieba_rebuild` 创建
+const INDEX_VERSIONS: &str = "jieba_index_versions";
+
+/// 列出 main 数据库中使用 jieba 分词的 FTS5 表，每个表输出一行
+///
+/// `SELECT * FROM jieba_tables`
+///
+/// 修改 jieba 词典后，这些表中已经写入的文档仍然使用旧的分词结果，需要重建索引。
+/// `dict_version` 是表当前使用的词典的指纹，`indexed_version` 是最近一次通过 `jieba_rebuild`
+/// 重建索引时的指纹，两者不同时 `stale` 为 1；没有通过 `jieba_rebuild` 重建过的表 `stale` 为 NULL
+pub struct JiebaTables;
+
+impl TableFunction for JiebaTables {
+    fn name() -> &'static CStr {
+        c"jieba_tables"
+    }
+
+    fn columns() -> &'static [&'static str] {
+        &[
+            "name",
+            "tokenize",
+            "dict_version",
+            "indexed_version",
+            "stale",
+        ]
+    }
+
+    fn arguments() -> &'static [&'static str] {
+        &[]
+    }
+
+    fn rows(connection: &Connection, _args: &[Value]) -> Result<Vec<Vec<Value>>, crate::Error> {
+        let mut rows = Vec::new();
+        for (name, tokenize) in jieba_tables(connection)? {
+            // 表的分词器无法创建时，例如用户词典表已经被删除，词典的指纹为 NULL
+            let dict_version = table_dict(connection, &tokenize)
+                .ok()
+                .map(|dict| dict.fingerprint());
+            let indexed_version = indexed_version(connection, &name)?;
+            let stale = match (&dict_version, &indexed_version) {
+                (Some(current), Some(indexed)) => Value::Integer(i64::from(current != indexed)),
+                _ => Value::Null,
+            };
+            rows.push(vec![
+                Value::Text(name),
+                Value::Text(tokenize),
+                dict_version.map_or(Value::Null, Value::Text),
+                indexed_version.map_or(Value::Null, Value::Text),
+                stale,
+            ]);
+        }
+        Ok(rows)
+    }
+}
+
+/// 重建使用 jieba 分词的 FTS5 表 `name` 的索引，并记录重建时使用的词典指纹，返回这个指纹
+///
+/// 指纹保存在 main 数据库的 `jieba_index_versions` 表中，不存在时创建这个表
+pub(crate) fn rebuild(connection: &Connection, name: &str) -> Result<String, crate::Error> {
+    let Some((name, tokenize)) = jieba_tables(connection)?
+        .into_iter()
+        .find(|(table, _)| table.eq_ignore_ascii_case(name))
+    else {
+        return Err(crate::Error::FunctionArgumentIncorrect(format!(
+            "no such jieba FTS5 table: {name}"
+        )));
+    };
+    let quoted = format!("\"{}\"", rusqlite::vtab::escape_double_quote(&name));
+    connection.execute(
+        &format!("INSERT INTO main.{quoted}({quoted}) VALUES ('rebuild')"),
+        [],
+    )?;
+    let fingerprint = table_dict(connection, &tokenize)?.fingerprint();
+    connection.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS main.{INDEX_VERSIONS}(name TEXT PRIMARY KEY, version TEXT NOT NULL)"
+    ))?;
+    connection.execute(
+        &format!("INSERT OR REPLACE INTO main.{INDEX_VERSIONS}(name, version) VALUES (?1, ?2)"),
+        [&name, &fingerprint],
+    )?;
+    Ok(fingerprint)
+}
+
+/// main 数据库中使用 jieba 分词的 FTS5 表的名称和 `tokenize` 选项
+fn jieba_tables(connection: &Connection) -> Result<Vec<(String, String)>, crate::Error> {
+    let jieba = JiebaTokenizer::name().to_str()?;
+    let mut stmt = connection.prepare(
+        "SELECT name, sql FROM main.sqlite_schema \
+         WHERE type = 'table' AND sql LIKE 'CREATE VIRTUAL TABLE%' ORDER BY name",
+    )?;
+    let mut result = Vec::new();
+    let mut tables = stmt.query([])?;
+    while let Some(table) = tables.next()? {
+        let name = table.get::<_, String>(0)?;
+        let sql = table.get::<_, String>(1)?;
+        let Some(tokenize) = fts5_tokenize_option(&sql) else {
+            continue;
+        };
+        if parse_tokenizer_args(&tokenize).first().map(String::as_str) == Some(jieba) {
+            result.push((name, tokenize));
+        }
+    }
+    Ok(result)
+}
+
+/// 按照 `tokenize` 选项创建一个 jieba 分词器，得到表使用的词典
+fn table_dict(connection: &Connection, tokenize: &str) -> Result<Arc<JiebaDict>, crate::Error> {
+    let args = parse_tokenizer_args(tokenize).into_iter().skip(1).collect();
+    let tokenizer = JiebaTokenizer::new_with_connection(&(), connection, args)?;
+    Ok(tokenizer.dict().clone())
+}
+
+/// 表 `name` 最近一次通过 `jieba_rebuild` 重建索引时的词典指纹
+fn indexed_version(connection: &Connection, name: &str) -> Result<Option<String>, crate::Error> {
+    let exists = connection
+        .query_row(
+            "SELECT 1 FROM main.sqlite_schema WHERE type = 'table' AND name = ?1",
+            [INDEX_VERSIONS],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+    if !exists {
+        return Ok(None);
+    }
+    Ok(connection
+        .query_row(
+            &format!("SELECT version FROM main.{INDEX_VERSIONS} WHERE name = ?1"),
+            [name],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?)
+}
+
+/// 从 `CREATE VIRTUAL TABLE ... USING fts5(...)` 语句中取出 `tokenize` 选项的值
+///
+/// 不是 FTS5 表或者没有 `tokenize` 选项时返回 `None`
+fn fts5_tokenize_option(sql: &str) -> Option<String> {
+    let open = sql.find('(')?;
+    let module = sql[..open].trim_end().rsplit(char::is_whitespace).next()?;
+    if !module
+        .trim_matches(['"', '\'', '`'])
+        .eq_ignore_ascii_case("fts5")
+    {
+        return None;
+    }
+    let body = sql[open + 1..].trim_end().strip_suffix(')')?;
+    split_module_args(body).into_iter().find_map(|arg| {
+        let (key, value) = arg.split_once('=')?;
+        if !key.trim().eq_ignore_ascii_case("tokenize") {
+            return None;
+        }
+        Some(unquote(value.trim()))
+    })
+}
+
+/// 按照逗号拆分虚拟表的参数，引号内的逗号不作为分隔符
+fn split_module_args(body: &str) -> Vec<&str> {
+    let mut args = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (index, ch) in body.char_indices() {
+        match quote {
+            Some(q) if ch == q => quote = None,
+            Some(_) => {}
+            None => match ch {
+                '\'' | '"' | '`' => quote = Some(ch),
+                '[' => quote = Some(']'),
+                ',' => {
+                    args.push(&body[start..index]);
+                    start = index + 1;
+                }
+                _ => {}
+            },
+        }
+    }
+    args.push(&body[start..]);
+    args
+}
+
+/// 去掉字符串两端的引号，引号内连续的两个引号表示引号本身
+fn unquote(value: &str) -> String {
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(quote @ ('\'' | '"' | '`')) if value.len() > 1 && value.ends_with(quote) => {
+            let double = format!("{quote}{quote}");
+            value[1..value.len() - 1].replace(&double, &quote.to_string())
+        }
+        _ => value.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fts5_tokenize_option;
+    use crate::load;
+    use rusqlite::Connection;
+
+    #[test]
+    fn test_fts5_tokenize_option() {
+        assert_eq!(
+            fts5_tokenize_option(
+                "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'jieba dict=''a,b.dict''')"
+            ),
+            Some("jieba dict='a,b.dict'".to_owned())
+        );
+        assert_eq!(
+            fts5_tokenize_option("CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize=jieba)"),
+            Some("jieba".to_owned())
+        );
+        assert_eq!(
+            fts5_tokenize_option("CREATE VIRTUAL TABLE t3 USING fts5(text)"),
+            None
+        );
+        assert_eq!(
+            fts5_tokenize_option("CREATE VIRTUAL TABLE t4 USING rtree(id, tokenize)"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_jieba_tables() {
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'simple');
+             CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize = 'jieba disable_stopword');
+             CREATE VIRTUAL TABLE t3 USING fts5(text, tokenize = \"jieba\");",
+        )
+        .unwrap();
+        let mut stmt = conn.prepare("SELECT * FROM jieba_tables").unwrap();
+        let tables = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            tables,
+            vec![
+                ("t2".to_owned(), "jieba disable_stopword".to_owned()),
+                ("t3".to_owned(), "jieba".to_owned()),
+            ]
+        );
+    }
+}
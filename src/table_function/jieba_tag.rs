@@ -0,0 +1,111 @@
+use crate::table_function::{TableFunction, get_hmm, get_jieba_dict};
+use crate::utils::offset_in;
+use rusqlite::Connection;
+use rusqlite::types::Value;
+use std::ffi::CStr;
+
+/// 使用 jieba 对文本分词并标注词性，每个词输出一行
+///
+/// `SELECT * FROM jieba_tag('我将点燃星海')`
+///
+/// 第二个参数表示是否启用 HMM，默认启用；第三个参数是词典名称，默认使用默认词典
+pub struct JiebaTag;
+
+impl TableFunction for JiebaTag {
+    fn name() -> &'static CStr {
+        c"jieba_tag"
+    }
+
+    fn columns() -> &'static [&'static str] {
+        &["word", "tag", "start", "end"]
+    }
+
+    fn arguments() -> &'static [&'static str] {
+        &["input", "hmm", "dict_name"]
+    }
+
+    fn required_arguments() -> usize {
+        1
+    }
+
+    fn rows(_connection: &Connection, args: &[Value]) -> Result<Vec<Vec<Value>>, crate::Error> {
+        let Value::Text(text) = &args[0] else {
+            return Err(crate::Error::FunctionArgumentIncorrect(
+                "input must be text".to_owned(),
+            ));
+        };
+        let hmm = get_hmm(&args[1])?;
+        let dict = get_jieba_dict(&args[2])?;
+        let rows = dict
+            .jieba()
+            .tag(text, hmm)
+            .into_iter()
+            .map(|tag| {
+                let start = offset_in(text, tag.word);
+                vec![
+                    Value::Text(tag.word.to_owned()),
+                    Value::Text(tag.tag.to_owned()),
+                    Value::Integer(start as i64),
+                    Value::Integer((start + tag.word.len()) as i64),
+                ]
+            })
+            .collect();
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::load;
+    use rusqlite::Connection;
+
+    #[test]
+    fn test_jieba_tag() {
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT word, tag, start, end FROM jieba_tag('我将点燃星海 stars')")
+            .unwrap();
+        let result = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0).unwrap(),
+                    row.get::<_, String>(1).unwrap(),
+                    row.get::<_, i64>(2).unwrap(),
+                    row.get::<_, i64>(3).unwrap(),
+                ))
+            })
+            .unwrap();
+        let mut vec = Vec::new();
+        for row in result {
+            let row = row.unwrap();
+            vec.push(row)
+        }
+        assert_eq!(
+            vec![
+                ("我".to_owned(), "r".to_owned(), 0, 3),
+                ("将".to_owned(), "d".to_owned(), 3, 6),
+                ("点燃".to_owned(), "v".to_owned(), 6, 12),
+                ("星海".to_owned(), "ns".to_owned(), 12, 18),
+                (" ".to_owned(), "x".to_owned(), 18, 19),
+                ("stars".to_owned(), "eng".to_owned(), 19, 24),
+            ],
+            vec
+        );
+        // 使用指定的词典
+        conn.query_row(
+            "SELECT jieba_add_word('点燃星海', NULL, 'nz', 'tag_dict')",
+            [],
+            |_| Ok(()),
+        )
+        .unwrap();
+        let tag = conn
+            .query_row(
+                "SELECT tag FROM jieba_tag('我将点燃星海', NULL, 'tag_dict') WHERE word = '点燃星海'",
+                [],
+                |row| row.get::<_, String>(0),
+            )
+            .unwrap();
+        assert_eq!("nz", tag);
+    }
+}
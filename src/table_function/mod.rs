@@ -0,0 +1,222 @@
+//! 基于 SQLite 虚拟表实现的表值函数
+//!
+//! 表值函数的参数作为虚拟表的 hidden 列，在 `best_index` 中要求这些参数以等值约束的形式提供
+
+pub mod fts5_tokens;
+pub mod jieba_cut;
+pub mod jieba_tables;
+pub mod jieba_tag;
+pub mod simple_query_explain;
+
+use crate::tokenizer::jieba_dict::{DEFAULT_DICT_NAME, JiebaDict, jieba_dicts};
+use rusqlite::types::Value;
+use rusqlite::vtab::{
+    Context, Filters, IndexConstraintOp, IndexInfo, VTab, VTabConfig, VTabConnection, VTabCursor,
+    eponymous_only_module,
+};
+use rusqlite::{Connection, ffi};
+use std::ffi::{CStr, c_int};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// 表值函数
+pub trait TableFunction: Sized + 'static {
+    /// 提供一个表值函数名称
+    fn name() -> &'static CStr;
+    /// 结果的列名
+    fn columns() -> &'static [&'static str];
+    /// 参数名，参数在结果中作为 hidden 列
+    fn arguments() -> &'static [&'static str];
+    /// 必须提供的参数个数，其余参数可以省略，省略的参数为 NULL
+    fn required_arguments() -> usize {
+        Self::arguments().len()
+    }
+    /// 根据参数生成全部结果行，每一行的列与 `columns` 一一对应
+    fn rows(connection: &Connection, args: &[Value]) -> Result<Vec<Vec<Value>>, crate::Error>;
+}
+
+/// 注册表值函数
+pub fn register_table_function<F: TableFunction>(
+    connection: &Connection,
+) -> Result<(), crate::Error> {
+    connection.create_module(
+        F::name(),
+        eponymous_only_module::<TableFunctionTab<F>>(),
+        None,
+    )?;
+    Ok(())
+}
+
+#[repr(C)]
+struct TableFunctionTab<F: TableFunction> {
+    /// Base class. Must be first
+    base: ffi::sqlite3_vtab,
+    /// 虚拟表所在的数据库连接
+    db: *mut ffi::sqlite3,
+    phantom: PhantomData<F>,
+}
+
+unsafe impl<'vtab, F: TableFunction> VTab<'vtab> for TableFunctionTab<F> {
+    type Aux = ();
+    type Cursor = TableFunctionCursor<'vtab, F>;
+
+    fn connect(
+        db: &mut VTabConnection,
+        _aux: Option<&()>,
+        _args: &[&[u8]],
+    ) -> rusqlite::Result<(String, Self)> {
+        db.config(VTabConfig::Innocuous)?;
+        let columns = F::columns()
+            .iter()
+            .map(|column| column.to_string())
+            .chain(F::arguments().iter().map(|arg| format!("{arg} HIDDEN")))
+            .collect::<Vec<String>>();
+        let vtab = Self {
+            base: ffi::sqlite3_vtab::default(),
+            db: unsafe { db.handle() },
+            phantom: PhantomData,
+        };
+        Ok((format!("CREATE TABLE x({})", columns.join(",")), vtab))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> rusqlite::Result<()> {
+        let first_argument = F::columns().len() as c_int;
+        // 每个参数对应的约束的序号
+        let mut constraints = vec![None; F::arguments().len()];
+        let mut unusable = false;
+        for (i, constraint) in info.constraints().enumerate() {
+            if constraint.column() < first_argument {
+                continue;
+            }
+            let argument = (constraint.column() - first_argument) as usize;
+            if !constraint.is_usable() {
+                unusable = true;
+            } else if constraint.operator() == IndexConstraintOp::SQLITE_INDEX_CONSTRAINT_EQ {
+                constraints[argument] = Some(i);
+            }
+        }
+        // 缺少必须提供的参数，或者参数不可用时，要求 SQLite 选择其他的查询计划
+        if unusable
+            || constraints[..F::required_arguments()]
+                .iter()
+                .any(Option::is_none)
+        {
+            return Err(rusqlite::Error::SqliteFailure(
+                ffi::Error::new(ffi::SQLITE_CONSTRAINT),
+                None,
+            ));
+        }
+        // idx_num 的每一位表示对应的参数是否提供
+        let mut idx_num = 0;
+        let mut argv_index = 0;
+        for (argument, constraint) in constraints.into_iter().enumerate() {
+            if let Some(constraint) = constraint {
+                argv_index += 1;
+                idx_num |= 1 << argument;
+                let mut usage = info.constraint_usage(constraint);
+                usage.set_argv_index(argv_index);
+                usage.set_omit(true);
+            }
+        }
+        info.set_idx_num(idx_num);
+        info.set_estimated_cost(1.0);
+        Ok(())
+    }
+
+    fn open(&'vtab mut self) -> rusqlite::Result<TableFunctionCursor<'vtab, F>> {
+        Ok(TableFunctionCursor {
+            base: ffi::sqlite3_vtab_cursor::default(),
+            tab: self,
+            args: Vec::new(),
+            rows: Vec::new(),
+            row_id: 0,
+        })
+    }
+}
+
+#[repr(C)]
+struct TableFunctionCursor<'vtab, F: TableFunction> {
+    /// Base class. Must be first
+    base: ffi::sqlite3_vtab_cursor,
+    /// 游标所属的虚拟表
+    tab: &'vtab TableFunctionTab<F>,
+    /// 表值函数的参数
+    args: Vec<Value>,
+    /// 表值函数的结果
+    rows: Vec<Vec<Value>>,
+    /// 当前行的序号
+    row_id: usize,
+}
+
+unsafe impl<F: TableFunction> VTabCursor for TableFunctionCursor<'_, F> {
+    fn filter(
+        &mut self,
+        idx_num: c_int,
+        _idx_str: Option<&str>,
+        args: &Filters<'_>,
+    ) -> rusqlite::Result<()> {
+        let mut values = args.iter();
+        self.args = (0..F::arguments().len())
+            .map(|argument| {
+                if idx_num & (1 << argument) != 0 {
+                    values.next().map(Value::from).unwrap_or(Value::Null)
+                } else {
+                    Value::Null
+                }
+            })
+            .collect();
+        // 借用虚拟表所在的连接，这个连接在 drop 时不会被关闭
+        let connection = unsafe { Connection::from_handle(self.tab.db) }?;
+        self.rows = F::rows(&connection, &self.args)
+            .map_err(|error| rusqlite::Error::ModuleError(error.to_string()))?;
+        self.row_id = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> rusqlite::Result<()> {
+        self.row_id += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.row_id >= self.rows.len()
+    }
+
+    fn column(&self, ctx: &mut Context, i: c_int) -> rusqlite::Result<()> {
+        let i = i as usize;
+        let columns = F::columns().len();
+        if i < columns {
+            ctx.set_result(&self.rows[self.row_id][i])
+        } else {
+            ctx.set_result(&self.args[i - columns])
+        }
+    }
+
+    fn rowid(&self) -> rusqlite::Result<i64> {
+        Ok(self.row_id as i64 + 1)
+    }
+}
+
+/// 获取 `dict_name` 参数指定的 jieba 词典，NULL 表示默认词典
+fn get_jieba_dict(value: &Value) -> Result<Arc<JiebaDict>, crate::Error> {
+    match value {
+        Value::Null => jieba_dicts().find(DEFAULT_DICT_NAME),
+        Value::Text(name) => jieba_dicts().find(name),
+        value => Err(crate::Error::FunctionArgumentIncorrect(format!(
+            "dict_name must be text, got {}",
+            value.data_type()
+        ))),
+    }
+}
+
+/// 获取表示是否启用 HMM 的参数，NULL 表示使用默认值，即启用 HMM
+fn get_hmm(value: &Value) -> Result<bool, crate::Error> {
+    match value {
+        Value::Null => Ok(true),
+        Value::Integer(value) => Ok(*value != 0),
+        value => Err(crate::Error::FunctionArgumentIncorrect(format!(
+            "hmm must be an integer, got {}",
+            value.data_type()
+        ))),
+    }
+}
@@ -0,0 +1,139 @@
+use crate::load_extension::get_query_limits;
+use crate::table_function::TableFunction;
+use crate::tokenizer::simple_tokenizer::SimpleTokenizer;
+use rusqlite::Connection;
+use rusqlite::types::{Value, ValueRef};
+use std::ffi::CStr;
+
+/// 解释 `simple_query` 如何处理查询文档，每个单词输出一行
+///
+/// `SELECT * FROM simple_query_explain('zhongguo人')`
+pub struct SimpleQueryExplain;
+
+impl TableFunction for SimpleQueryExplain {
+    fn name() -> &'static CStr {
+        c"simple_query_explain"
+    }
+
+    fn columns() -> &'static [&'static str] {
+        &["word", "kind", "readings", "alternatives", "fragment"]
+    }
+
+    fn arguments() -> &'static [&'static str] {
+        &["input", "max_readings", "max_alternatives", "max_terms"]
+    }
+
+    fn required_arguments() -> usize {
+        1
+    }
+
+    fn rows(_connection: &Connection, args: &[Value]) -> Result<Vec<Vec<Value>>, crate::Error> {
+        let text = match &args[0] {
+            Value::Text(text) => text,
+            value => {
+                let ty = value.data_type().to_string();
+                return Err(crate::Error::SimpleQueryInputTypeIncorrect(ty));
+            }
+        };
+        let limits = get_query_limits(args[1..].iter().map(ValueRef::from))?;
+        let explain = SimpleTokenizer::explain_query(text, &limits);
+        let rows = explain
+            .words
+            .iter()
+            .map(|word| {
+                vec![
+                    Value::Text(word.word.clone()),
+                    Value::Text(word.kind.as_str().to_owned()),
+                    join_or_null(&word.readings),
+                    join_or_null(&word.alternatives),
+                    word.fragment().map_or(Value::Null, Value::Text),
+                ]
+            })
+            .collect();
+        Ok(rows)
+    }
+}
+
+/// 以逗号连接多个值，没有值时返回 NULL
+fn join_or_null(values: &[String]) -> Value {
+    if values.is_empty() {
+        Value::Null
+    } else {
+        Value::Text(values.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::load;
+    use rusqlite::Connection;
+
+    #[test]
+    fn test_simple_query_explain() {
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT * FROM simple_query_explain('zhongguo人 the 32.3');")
+            .unwrap();
+        let result = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0).unwrap(),
+                    row.get::<_, String>(1).unwrap(),
+                    row.get::<_, Option<String>>(2).unwrap(),
+                    row.get::<_, Option<String>>(3).unwrap(),
+                    row.get::<_, Option<String>>(4).unwrap(),
+                ))
+            })
+            .unwrap();
+        let mut vec = Vec::new();
+        for row in result {
+            let row = row.unwrap();
+            vec.push(row)
+        }
+        assert_eq!(
+            vec![
+                (
+                    "zhongguo".to_owned(),
+                    "pinyin".to_owned(),
+                    None,
+                    Some("z+h+o+n+g+g+u+o,zhong+gu+o,zhong+guo,zhongguo".to_owned()),
+                    Some(
+                        r#"(z+h+o+n+g+g+u+o* OR zhong+gu+o* OR zhong+guo* OR zhongguo* OR "zhongguo")"#.to_owned()
+                    ),
+                ),
+                (
+                    "人".to_owned(),
+                    "hanzi".to_owned(),
+                    Some("ren".to_owned()),
+                    Some("r+e+n,re+n,ren".to_owned()),
+                    Some("(r+e+n* OR re+n* OR ren*)".to_owned()),
+                ),
+                (
+                    "the".to_owned(),
+                    "english".to_owned(),
+                    None,
+                    Some("t+h+e,the".to_owned()),
+                    Some("(t+h+e* OR the*)".to_owned()),
+                ),
+                (
+                    "32.3".to_owned(),
+                    "english".to_owned(),
+                    None,
+                    None,
+                    Some(r#"("32.3")"#.to_owned()),
+                ),
+            ],
+            vec
+        );
+        // 单词的个数超过限制时，使用精确匹配
+        let fragment = conn
+            .query_row(
+                "SELECT fragment FROM simple_query_explain('zhongguo人', 4, 16, 2) WHERE word = '人'",
+                [],
+                |row| row.get::<_, String>(0),
+            )
+            .unwrap();
+        assert_eq!(r#"("人")"#, fragment);
+    }
+}
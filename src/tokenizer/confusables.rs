@@ -0,0 +1,212 @@
+//! 易混淆字符和不可见字符的折叠
+//!
+//! 去掉零宽字符、软连字符等默认可忽略字符（Default_Ignorable_Code_Point），并按照 Unicode UTS #39
+//! 的 confusables 数据将易混淆字符替换成原型，例如西里尔字母 `а` 替换成拉丁字母 `a`。
+//! ascii 字符和有拼音的汉字保持原样，数据由 `build.rs` 从 `data/confusables.txt` 和
+//! `data/default_ignorable.txt` 生成
+
+use std::cmp::Ordering;
+use std::ops::Range;
+use unicode_normalization::UnicodeNormalization;
+
+include!(concat!(env!("OUT_DIR"), "/confusables_data.rs"));
+
+/// 判断是否是默认可忽略字符
+pub fn is_default_ignorable(ch: char) -> bool {
+    DEFAULT_IGNORABLE
+        .binary_search_by(|&(start, end)| {
+            if end < ch {
+                Ordering::Less
+            } else if start > ch {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// 折叠一个字符，结果追加到 `buf`
+pub fn fold_char(ch: char, buf: &mut String) {
+    if ch.is_ascii() {
+        buf.push(ch);
+        return;
+    }
+    if is_default_ignorable(ch) {
+        return;
+    }
+    // 全角 ascii 字符转换成半角，使得分词器可以按照 ascii 字符切分单词
+    if ('\u{ff01}'..='\u{ff5e}').contains(&ch) {
+        buf.push(char::from_u32(ch as u32 - 0xfee0).unwrap_or(ch));
+        return;
+    }
+    if let Some(prototype) = CONFUSABLES.get(&ch) {
+        buf.push_str(prototype);
+        return;
+    }
+    // 带有附加符号的字符按照分解后的基本字符折叠，例如西里尔字母 ё
+    let mut decomposed = std::iter::once(ch).nfd();
+    if let Some(base) = decomposed.next()
+        && base != ch
+        && let Some(prototype) = CONFUSABLES.get(&base)
+    {
+        buf.push_str(prototype);
+        buf.extend(decomposed);
+        return;
+    }
+    buf.push(ch);
+}
+
+/// 折叠文本中的全部字符
+pub fn fold_confusables(text: &str) -> String {
+    let mut buf = String::with_capacity(text.len());
+    for ch in text.chars() {
+        fold_char(ch, &mut buf);
+    }
+    buf
+}
+
+/// 折叠后的文本，分词器对折叠后的文本分词，再将 token 的位置转换成原文中的位置
+pub struct FoldedText {
+    text: String,
+    /// 折叠后文本的每个字节所属的原文字符在原文中的范围
+    sources: Vec<Range<usize>>,
+}
+
+impl FoldedText {
+    pub fn new(text: &str) -> Self {
+        let mut folded = String::with_capacity(text.len());
+        let mut sources = Vec::with_capacity(text.len());
+        for (index, ch) in text.char_indices() {
+            fold_char(ch, &mut folded);
+            sources.resize(folded.len(), index..index + ch.len_utf8());
+        }
+        Self {
+            text: folded,
+            sources,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// 将折叠后文本中的范围转换成原文中的范围
+    pub fn original_range(&self, range: Range<usize>) -> Range<usize> {
+        if range.is_empty() {
+            let start = self.sources.get(range.start).map_or_else(
+                || self.sources.last().map_or(0, |last| last.end),
+                |source| source.start,
+            );
+            return start..start;
+        }
+        self.sources[range.start].start..self.sources[range.end - 1].end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::TokenizeReason;
+    use crate::tokenizer::test_utils::{count, open, tokenize};
+
+    #[test]
+    fn test_fold_confusables() {
+        // 西里尔字母、数学字母和零宽空格
+        assert_eq!("paypal", fold_confusables("pаypаl"));
+        assert_eq!("spam", fold_confusables("s\u{200b}p\u{ad}a\u{200d}m"));
+        assert_eq!("abc", fold_confusables("𝐚𝐛𝐜"));
+        assert_eq!("Coin!", fold_confusables("Ｃｏｉｎ！"));
+        assert_eq!("e\u{308}", fold_confusables("ё"));
+        // ascii 字符和汉字保持原样
+        assert_eq!("m0Il 一士", fold_confusables("m0Il 一士"));
+
+        let folded = FoldedText::new("pаy\u{200b}pal x");
+        assert_eq!("paypal x", folded.as_str());
+        assert_eq!(0..10, folded.original_range(0..6));
+        assert_eq!(11..12, folded.original_range(7..8));
+        assert_eq!(12..12, folded.original_range(8..8));
+    }
+
+    #[test]
+    fn test_register_tokenizer_with_fold_confusables() {
+        let conn = open();
+        let tokens =
+            |args: &[&str], reason: TokenizeReason, text: &str| -> Vec<(String, usize, usize)> {
+                tokenize(&conn, args, reason, text)
+                    .unwrap()
+                    .into_iter()
+                    .filter(|token| !token.colocated)
+                    .map(|token| (token.token, token.start, token.end))
+                    .collect()
+            };
+        let token = |token: &str, start: usize, end: usize| (token.to_owned(), start, end);
+        // 西里尔字母 а、软连字符、零宽空格和全角字母，token 的位置是原文中的位置
+        let text = "pаypаl bit\u{ad}coin w\u{200b}allet ｃｏｉｎ 银行";
+        assert_eq!(
+            vec![
+                token("paypal", 0, 8),
+                token("bitcoin", 9, 18),
+                token("wallet", 19, 28),
+                token("coin", 29, 41),
+                token("yin", 42, 45),
+                token("hang", 45, 48),
+                token("heng", 45, 48),
+                token("xing", 45, 48),
+            ],
+            tokens(
+                &["simple", "fold_confusables"],
+                TokenizeReason::Document,
+                text
+            )
+        );
+        assert_eq!(
+            vec![
+                token("paypal", 0, 8),
+                token("bitcoin", 9, 18),
+                token("wallet", 19, 28),
+                token("coin", 29, 41),
+                token("银行", 42, 48),
+            ],
+            tokens(
+                &["jieba", "fold_confusables"],
+                TokenizeReason::Document,
+                text
+            )
+        );
+        // 过滤器只处理切分后的单词，零宽空格仍然会分开单词
+        assert_eq!(
+            vec![
+                token("paypal", 0, 8),
+                token("bitcoin", 9, 18),
+                token("w", 19, 20),
+                token("allet", 23, 28),
+                token("coin", 29, 41),
+            ],
+            tokens(
+                &["simple", "filters=confusables,nfkc,lower"],
+                TokenizeReason::Document,
+                "pаypаl bit\u{ad}coin w\u{200b}allet ｃｏｉｎ"
+            )
+        );
+
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'simple fold_confusables');
+             CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize = 'jieba fold_confusables');
+             CREATE VIRTUAL TABLE t3 USING fts5(text, tokenize = 'simple');",
+        )
+        .unwrap();
+        for table in ["t1", "t2", "t3"] {
+            conn.execute(&format!("INSERT INTO {table}(text) VALUES (?1)"), [text])
+                .unwrap();
+        }
+        for table in ["t1", "t2"] {
+            assert_eq!(1, count(&conn, table, "paypal"));
+            assert_eq!(1, count(&conn, table, "wallet"));
+            // 查询同样折叠
+            assert_eq!(1, count(&conn, table, "\"bit\u{200d}cоin\""));
+        }
+        assert_eq!(0, count(&conn, "t3", "paypal"));
+        assert_eq!(0, count(&conn, "t3", "wallet"));
+    }
+}
@@ -0,0 +1,631 @@
+//! token 过滤器，通过 `filters=` 参数组成处理单词的流水线，例如 `filters=nfkc,lower,stop:en,stem:en,pinyin`
+//!
+//! 分词器只负责切分单词，每个单词依次经过流水线中的过滤器，得到写入 FTS5 的 token，
+//! 过滤器可以调整顺序或者省略。没有指定 `filters=` 时，分词器按照自己的参数构建默认的流水线，
+//! 见 [`FilterPipeline::builtin`]
+
+use crate::pinyin::{get_pinyin, segment_pinyin};
+use crate::tokenizer::TokenizeReason;
+use crate::tokenizer::confusables::fold_confusables;
+use crate::tokenizer::pattern::PatternFilter;
+use crate::tokenizer::stopwords::{StopWordLang, StopWords};
+use crate::tokenizer::unicode61::{
+    Normalization, Normalizer, RemoveDiacritics, is_legacy_diacritic,
+};
+use crate::tokenizer::utils::{
+    WordForm, is_pinyin_like, need_pinyin, need_stem, parse_stemmer, stem_tokens,
+    word_pinyin_tokens,
+};
+use rusqlite::Error;
+use rust_stemmers::Algorithm;
+use std::ops::{ControlFlow, Range};
+use unicode_normalization::UnicodeNormalization;
+
+/// 分词结果中的一个位置，`colocated` 中的 token 与 `token` 处于同一位置
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Position {
+    pub token: String,
+    pub colocated: Vec<String>,
+    /// 单词在文本中的位置
+    pub range: Range<usize>,
+    /// 是否是关键词，关键词不做停词过滤和词干提取，例如受保护的词语、汉字的读音和切分出的拼音音节
+    pub keyword: bool,
+}
+
+impl Position {
+    pub fn new(token: &str, range: Range<usize>) -> Self {
+        Self {
+            token: token.to_owned(),
+            colocated: Vec::new(),
+            range,
+            keyword: false,
+        }
+    }
+
+    /// 对这个位置的每个 token 做转换
+    fn map_tokens<F>(&mut self, map: F)
+    where
+        F: Fn(&str) -> String,
+    {
+        self.token = map(&self.token);
+        for token in self.colocated.iter_mut() {
+            *token = map(token);
+        }
+    }
+
+    /// 写入这个位置的 token，`tokendata` 不为 `None` 时作为每个 token 的 tokendata 写入
+    ///
+    /// `colocated` 为 `true` 时全部 token 与上一个位置处于同一位置；`push_token` 返回
+    /// [`ControlFlow::Break`] 时不再写入其余的 token
+    pub(crate) fn push<TKF>(
+        &self,
+        colocated: bool,
+        tokendata: Option<&str>,
+        push_token: &mut TKF,
+    ) -> Result<ControlFlow<()>, Error>
+    where
+        TKF: FnMut(&[u8], Range<usize>, bool) -> Result<ControlFlow<()>, Error> + ?Sized,
+    {
+        let tokens = std::iter::once(&self.token).chain(self.colocated.iter());
+        for (index, token) in tokens.enumerate() {
+            let colocated = colocated || index > 0;
+            let flow = match tokendata {
+                Some(tokendata) => {
+                    let token = format!("{token}\0{tokendata}");
+                    (push_token)(token.as_bytes(), self.range.clone(), colocated)?
+                }
+                None => (push_token)(token.as_bytes(), self.range.clone(), colocated)?,
+            };
+            if flow.is_break() {
+                return Ok(flow);
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+/// 过滤器处理单词时的上下文
+pub struct FilterContext<'a> {
+    /// 分词的原因
+    pub reason: &'a TokenizeReason,
+    /// 分词的文本
+    pub text: &'a str,
+    /// 这个单词是否是文本中的最后一个单词，前缀查询只对最后一个单词做前缀匹配
+    pub is_last: bool,
+    /// 按照 locale 或者 `stemmer=` 参数确定的词干提取算法
+    pub stemmer: Option<Algorithm>,
+}
+
+impl FilterContext<'_> {
+    fn word_form(&self, index_original: bool, position: &Position) -> WordForm {
+        WordForm::new(
+            index_original,
+            self.reason,
+            self.is_last,
+            self.text,
+            position.range.start,
+        )
+    }
+}
+
+/// token 过滤器
+pub trait TokenFilter: Send {
+    /// 处理一个位置，处理结果写入 `output`，可以丢弃这个位置，也可以写入多个位置
+    fn filter(&self, position: Position, context: &FilterContext, output: &mut Vec<Position>);
+}
+
+/// `nfkc`：按照 NFKC 做归一化，并去掉组合用的附加符号
+pub struct NfkcFilter;
+
+impl TokenFilter for NfkcFilter {
+    fn filter(&self, mut position: Position, _: &FilterContext, output: &mut Vec<Position>) {
+        position.map_tokens(|token| {
+            token
+                .nfkc()
+                .filter(|ch| !is_legacy_diacritic(*ch))
+                .collect()
+        });
+        output.push(position);
+    }
+}
+
+/// `nfc` 和 `remove_diacritics:<0|1|2>`：按照指定的方式做归一化，不转换大小写
+pub struct NormalizeFilter(pub Normalizer);
+
+impl TokenFilter for NormalizeFilter {
+    fn filter(&self, mut position: Position, _: &FilterContext, output: &mut Vec<Position>) {
+        position.map_tokens(|token| {
+            let mut buf = String::new();
+            self.0.normalize(token, &mut buf);
+            buf
+        });
+        output.push(position);
+    }
+}
+
+/// `confusables`：去掉不可见字符并折叠易混淆字符，折叠后为空的位置被丢弃
+///
+/// 过滤器只处理切分后的单词，被不可见字符分开的单词需要使用分词器的 `fold_confusables` 参数
+pub struct ConfusablesFilter;
+
+impl TokenFilter for ConfusablesFilter {
+    fn filter(&self, mut position: Position, _: &FilterContext, output: &mut Vec<Position>) {
+        position.map_tokens(fold_confusables);
+        if !position.token.is_empty() {
+            output.push(position);
+        }
+    }
+}
+
+/// `lower`：转换成小写
+pub struct LowercaseFilter;
+
+impl TokenFilter for LowercaseFilter {
+    fn filter(&self, mut position: Position, _: &FilterContext, output: &mut Vec<Position>) {
+        position.map_tokens(str::to_lowercase);
+        output.push(position);
+    }
+}
+
+/// `stop` 或者 `stop:<lang>`：去掉停词
+///
+/// `stop` 使用 `stopwords=` 等参数配置的停词表，`stop:en` 和 `stop:zh` 只使用这个语言的内置停词表。
+/// 前缀查询的最后一个单词可能是更长单词的前缀，与关键词一样不做停词过滤
+pub struct StopFilter {
+    stopwords: StopWords,
+    index_original: bool,
+}
+
+impl TokenFilter for StopFilter {
+    fn filter(&self, position: Position, context: &FilterContext, output: &mut Vec<Position>) {
+        let form = context.word_form(self.index_original, &position);
+        if position.keyword || form.is_prefix() || !self.stopwords.contains(&position.token) {
+            output.push(position);
+        }
+    }
+}
+
+/// `stem` 或者 `stem:<lang>`：提取词干
+///
+/// `stem` 使用 locale 或者 `stemmer=` 参数确定的语言，`stem:<lang>` 使用指定的语言。
+/// 支持 `index_original` 和前缀查询，关键词不提取词干
+pub struct StemFilter {
+    /// 指定的词干提取算法，`None` 表示使用上下文中的算法
+    stemmer: Option<Option<Algorithm>>,
+    index_original: bool,
+}
+
+impl TokenFilter for StemFilter {
+    fn filter(&self, mut position: Position, context: &FilterContext, output: &mut Vec<Position>) {
+        if !position.keyword && need_stem(&position.token) {
+            let stemmer = self.stemmer.unwrap_or(context.stemmer);
+            let form = context.word_form(self.index_original, &position);
+            let (token, colocated) = stem_tokens(&position.token, stemmer, form);
+            position.token = token;
+            position.colocated.extend(colocated);
+        }
+        output.push(position);
+    }
+}
+
+/// `pinyin`：处理汉字和拼音
+///
+/// 单个汉字替换成它的读音，多音字的每个读音各是一个位置，启用 `colocate_readings` 时其他读音作为 colocated token；写入文档时，
+/// 多个汉字组成的词语的全拼和首字母作为 colocated token。启用 `enable_pinyin_query` 时，
+/// 查询中的拼音串按照音节个数最少的一种切分方式切分成多个音节，每个音节是一个位置。
+/// 不同的切分方式不作为 colocated token，否则每个位置上的音节可以任意组合，例如 `fangan` 会匹配 fang gan；
+/// 以 `=` 开头只匹配原词的单词不切分。读音和音节作为关键词
+pub struct PinyinFilter {
+    split_query: bool,
+    colocate_readings: bool,
+    index_original: bool,
+}
+
+impl PinyinFilter {
+    pub(crate) fn new(options: &FilterOptions) -> Self {
+        Self {
+            split_query: options.pinyin_query,
+            colocate_readings: options.colocate_readings,
+            index_original: options.index_original,
+        }
+    }
+}
+
+impl TokenFilter for PinyinFilter {
+    fn filter(&self, mut position: Position, context: &FilterContext, output: &mut Vec<Position>) {
+        let (is_query, prefix) = match context.reason {
+            TokenizeReason::Query { prefix } => (true, *prefix && context.is_last),
+            _ => (false, false),
+        };
+        if need_pinyin(&position.token) {
+            if let Some(ch) = position.token.chars().next()
+                && let Some(readings) = get_pinyin(&ch)
+                && let Some((first, others)) = readings.split_first()
+            {
+                if self.colocate_readings {
+                    position.token = first.to_owned();
+                    position.colocated.splice(0..0, others.iter().cloned());
+                    position.keyword = true;
+                    output.push(position);
+                    return;
+                }
+                for reading in readings {
+                    let mut reading = Position::new(&reading, position.range.clone());
+                    reading.keyword = true;
+                    output.push(reading);
+                }
+                return;
+            }
+        } else if is_query {
+            if self.split_query
+                && context.word_form(self.index_original, &position) != WordForm::Original
+                && is_pinyin_like(&position.token)
+                && let Some(syllables) = segment_pinyin(&position.token, prefix)
+                && syllables.len() > 1
+            {
+                for syllable in syllables {
+                    let mut syllable = Position::new(syllable, position.range.clone());
+                    syllable.keyword = true;
+                    output.push(syllable);
+                }
+                return;
+            }
+        } else {
+            let mut pinyin = Vec::new();
+            for token in std::iter::once(&position.token).chain(position.colocated.iter()) {
+                if token.chars().nth(1).is_some() {
+                    pinyin.extend(word_pinyin_tokens(token));
+                }
+            }
+            for token in pinyin {
+                if token != position.token && !position.colocated.contains(&token) {
+                    position.colocated.push(token);
+                }
+            }
+        }
+        output.push(position);
+    }
+}
+
+/// `word_pinyin`：写入文档时，将词语的全拼和首字母作为 colocated token，词语中有字符没有拼音时不处理
+///
+/// 与 `pinyin` 不同，汉字不会被替换成读音，适用于 jieba 切分出的词语
+pub struct WordPinyinFilter;
+
+impl TokenFilter for WordPinyinFilter {
+    fn filter(&self, mut position: Position, context: &FilterContext, output: &mut Vec<Position>) {
+        if !matches!(context.reason, TokenizeReason::Query { .. }) {
+            for token in word_pinyin_tokens(&position.token) {
+                if token != position.token && !position.colocated.contains(&token) {
+                    position.colocated.push(token);
+                }
+            }
+        }
+        output.push(position);
+    }
+}
+
+/// 创建过滤器时使用的分词器配置
+pub(crate) struct FilterOptions<'a> {
+    /// `stopwords=` 等参数配置的停词表
+    pub stopwords: &'a StopWords,
+    /// 是否启用 `index_original`
+    pub index_original: bool,
+    /// 是否启用 `enable_pinyin_query`
+    pub pinyin_query: bool,
+    /// 是否启用 `colocate_readings`
+    pub colocate_readings: bool,
+    /// `keep=` 和 `drop=` 正则过滤器，指定时作为流水线的第一个过滤器
+    pub pattern_filter: &'a PatternFilter,
+}
+
+/// 分词器的参数 `arg` 由默认的流水线实现，与 `filters=` 同时使用时返回的错误
+///
+/// 例如 `disable_pinyin` 对应省略 `pinyin` 过滤器，`remove_diacritics 2` 对应 `remove_diacritics:2` 过滤器
+pub(crate) fn conflicting_filters_arg(arg: &str) -> crate::Error {
+    crate::Error::TokenizerArgumentIncorrect(format!(
+        "{arg} cannot be used with filters=, configure the filters instead"
+    ))
+}
+
+/// 由多个过滤器组成的流水线
+#[derive(Default)]
+pub struct FilterPipeline {
+    filters: Vec<Box<dyn TokenFilter>>,
+}
+
+impl FilterPipeline {
+    /// 在流水线的末尾加入一个过滤器
+    pub fn push(&mut self, filter: Box<dyn TokenFilter>) {
+        self.filters.push(filter);
+    }
+
+    /// 解析 `filters=` 参数中以逗号分隔的过滤器，`stop` 和 `stem` 可以通过 `:` 指定语言，
+    /// `remove_diacritics` 通过 `:` 指定 unicode61 中的取值
+    pub(crate) fn parse(filters: &str, options: &FilterOptions) -> Result<Self, crate::Error> {
+        let mut pipeline = Self::with_pattern_filter(options);
+        for filter in filters.split(',').map(str::trim) {
+            let (name, lang) = match filter.split_once(':') {
+                Some((name, lang)) => (name, Some(lang)),
+                None => (filter, None),
+            };
+            let filter: Box<dyn TokenFilter> = match (name, lang) {
+                ("nfkc", None) => Box::new(NfkcFilter),
+                ("nfc", None) => Box::new(NormalizeFilter(Normalizer {
+                    normalization: Normalization::Nfc,
+                    remove_diacritics: RemoveDiacritics::Keep,
+                    case_sensitive: true,
+                })),
+                ("remove_diacritics", Some(value)) => {
+                    let remove_diacritics =
+                        RemoveDiacritics::from_value(value).ok_or_else(|| {
+                            crate::Error::TokenizerArgumentIncorrect(format!(
+                                "remove_diacritics must be 0, 1 or 2, got {value}"
+                            ))
+                        })?;
+                    Box::new(NormalizeFilter(Normalizer {
+                        normalization: Normalization::None,
+                        remove_diacritics,
+                        case_sensitive: true,
+                    }))
+                }
+                ("confusables", None) => Box::new(ConfusablesFilter),
+                ("lower", None) => Box::new(LowercaseFilter),
+                ("stop", lang) => {
+                    let mut stopwords = options.stopwords.clone();
+                    if let Some(lang) = lang {
+                        stopwords.set_langs(StopWordLang::parse_list(lang)?);
+                    }
+                    Box::new(StopFilter {
+                        stopwords,
+                        index_original: options.index_original,
+                    })
+                }
+                ("stem", lang) => Box::new(StemFilter {
+                    stemmer: lang.map(parse_stemmer).transpose()?,
+                    index_original: options.index_original,
+                }),
+                ("pinyin", None) => Box::new(PinyinFilter::new(options)),
+                ("word_pinyin", None) => Box::new(WordPinyinFilter),
+                _ => {
+                    return Err(crate::Error::TokenizerArgumentIncorrect(format!(
+                        "unknown token filter: {filter}"
+                    )));
+                }
+            };
+            pipeline.push(filter);
+        }
+        Ok(pipeline)
+    }
+
+    /// 只包含 `keep=` 和 `drop=` 正则过滤器的流水线，没有指定时为空
+    fn with_pattern_filter(options: &FilterOptions) -> Self {
+        let mut pipeline = Self::default();
+        if !options.pattern_filter.is_empty() {
+            pipeline.push(Box::new(options.pattern_filter.clone()));
+        }
+        pipeline
+    }
+
+    /// 没有指定 `filters=` 时使用的默认流水线，相当于 `normalize,stop,pinyin,stem`
+    ///
+    /// 按照分词器的 `normalizer` 做归一化，`enable_stopword` 为 `false` 时省略停词过滤，
+    /// `pinyin` 是分词器处理汉字和拼音的过滤器，`None` 表示不处理拼音
+    pub(crate) fn builtin(
+        normalizer: Normalizer,
+        enable_stopword: bool,
+        pinyin: Option<Box<dyn TokenFilter>>,
+        options: &FilterOptions,
+    ) -> Self {
+        let mut pipeline = Self::with_pattern_filter(options);
+        pipeline.push(Box::new(NormalizeFilter(normalizer)));
+        if enable_stopword {
+            pipeline.push(Box::new(StopFilter {
+                stopwords: options.stopwords.clone(),
+                index_original: options.index_original,
+            }));
+        }
+        if let Some(pinyin) = pinyin {
+            pipeline.push(pinyin);
+        }
+        pipeline.push(Box::new(StemFilter {
+            stemmer: None,
+            index_original: options.index_original,
+        }));
+        pipeline
+    }
+
+    /// 使用流水线中的过滤器依次处理一个单词
+    pub fn run(&self, word: Position, context: &FilterContext) -> Vec<Position> {
+        let mut positions = vec![word];
+        for filter in &self.filters {
+            let mut output = Vec::with_capacity(positions.len());
+            for position in positions {
+                filter.filter(position, context, &mut output);
+            }
+            positions = output;
+        }
+        positions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenizer::TokenizeReason;
+    use crate::tokenizer::filter::{
+        FilterContext, FilterPipeline, LowercaseFilter, Position, TokenFilter,
+    };
+    use crate::tokenizer::test_utils::{count, open, token, tokenize, tokens};
+
+    /// 将单词按照连字符拆分成多个位置
+    struct HyphenFilter;
+
+    impl TokenFilter for HyphenFilter {
+        fn filter(&self, position: Position, _: &FilterContext, output: &mut Vec<Position>) {
+            for part in position.token.split('-') {
+                output.push(Position::new(part, position.range.clone()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_filter() {
+        let mut pipeline = FilterPipeline::default();
+        pipeline.push(Box::new(LowercaseFilter));
+        pipeline.push(Box::new(HyphenFilter));
+        let context = FilterContext {
+            reason: &TokenizeReason::Document,
+            text: "Wi-Fi",
+            is_last: true,
+            stemmer: None,
+        };
+        let tokens = pipeline
+            .run(Position::new("Wi-Fi", 0..5), &context)
+            .into_iter()
+            .map(|position| position.token)
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["wi", "fi"], tokens);
+    }
+
+    #[test]
+    fn test_register_tokenizer_with_filters() {
+        let conn = open();
+        assert_eq!(
+            vec![
+                token("jump", false),
+                token("yin", false),
+                token("hang", false),
+                token("heng", false),
+                token("xing", false),
+                token("de", false),
+                token("di", false),
+            ],
+            tokens(
+                &conn,
+                &["simple", "filters=nfkc,lower,stop:en,stem:en,pinyin"],
+                TokenizeReason::Document,
+                "Ｔhe Jumping 银行 的"
+            )
+        );
+        // 省略停词过滤和词干提取
+        assert_eq!(
+            vec![token("the", false), token("jumping", false)],
+            tokens(
+                &conn,
+                &["simple", "filters=lower"],
+                TokenizeReason::Document,
+                "The Jumping"
+            )
+        );
+        // 没有 pinyin 过滤器时汉字按照原字写入
+        assert_eq!(
+            vec![token("银", false), token("行", false)],
+            tokens(
+                &conn,
+                &["simple", "filters=lower"],
+                TokenizeReason::Document,
+                "银行"
+            )
+        );
+        // jieba 的中文停词只用于汉字，词语的全拼和首字母只在写入文档时写入
+        assert_eq!(
+            vec![
+                token("国歌", false),
+                token("guoge", true),
+                token("gg", true),
+                token("the", false),
+                token("jump", false),
+            ],
+            tokens(
+                &conn,
+                &["jieba", "filters=nfkc,lower,stop:zh,stem,pinyin"],
+                TokenizeReason::Document,
+                "我们的国歌 The Jumping"
+            )
+        );
+        assert_eq!(
+            vec![
+                token("国歌", false),
+                token("jumped", false),
+                token("jump", true)
+            ],
+            tokens(
+                &conn,
+                &["jieba", "filters=nfkc,lower,stop,stem,pinyin"],
+                TokenizeReason::Query { prefix: true },
+                "国歌 jumped"
+            )
+        );
+        for filters in ["filters=nfkc,unknown", "filters=stem:xx", "filters=stop:fr"] {
+            let args = ["simple", filters];
+            assert!(tokenize(&conn, &args, TokenizeReason::Document, "x").is_err());
+        }
+
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'simple ''filters=lower,pinyin''');
+             CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize = 'jieba ''filters=nfkc,lower,stem,pinyin''');
+             INSERT INTO t1(text) VALUES ('The Jumping'), ('银行');
+             INSERT INTO t2(text) VALUES ('The Jumping'), ('我们的国歌');",
+        )
+        .unwrap();
+        assert_eq!(1, count(&conn, "t1", "the"));
+        assert_eq!(0, count(&conn, "t1", "jump"));
+        assert_eq!(1, count(&conn, "t1", "yin"));
+        assert_eq!(1, count(&conn, "t1", "银行"));
+        assert_eq!(1, count(&conn, "t2", "jump"));
+        // 拼音前缀不提取词干
+        assert_eq!(1, count(&conn, "t2", "guoge*"));
+        assert_eq!(1, count(&conn, "t2", "我们"));
+    }
+
+    #[test]
+    fn test_builtin_filters() {
+        let conn = open();
+        // 默认的处理方式与对应的流水线一致
+        let cases: &[(&[&str], &[&str], TokenizeReason, &str)] = &[
+            (
+                &["simple"],
+                &["simple", "filters=nfkc,lower,stop,pinyin,stem"],
+                TokenizeReason::Document,
+                "The Jumping 银行 的 iPhones",
+            ),
+            (
+                &["simple", "enable_pinyin_query"],
+                &[
+                    "simple",
+                    "enable_pinyin_query",
+                    "filters=nfkc,lower,stop,pinyin,stem",
+                ],
+                TokenizeReason::Query { prefix: true },
+                "zhongguo jumping",
+            ),
+            (
+                &["jieba", "enable_pinyin", "cut_for_search"],
+                &[
+                    "jieba",
+                    "cut_for_search",
+                    "filters=nfkc,lower,stop,word_pinyin,stem",
+                ],
+                TokenizeReason::Document,
+                "中华人民共和国的国歌 Jumping",
+            ),
+        ];
+        for (args, filters, reason, text) in cases {
+            assert_eq!(
+                tokens(&conn, args, reason.clone(), text),
+                tokens(&conn, filters, reason.clone(), text)
+            );
+        }
+        // 由默认的流水线实现的参数不能与 filters= 同时使用
+        for args in [
+            &["simple", "disable_pinyin", "filters=pinyin"][..],
+            &["simple", "disable_stopword", "filters=stop"],
+            &["simple", "remove_diacritics", "2", "filters=lower"],
+            &["simple", "filters=lower", "case_sensitive"],
+            &["jieba", "enable_pinyin", "filters=lower"],
+            &["jieba", "normalization=nfc", "filters=lower"],
+        ] {
+            assert!(tokenize(&conn, args, TokenizeReason::Document, "x").is_err());
+        }
+    }
+}
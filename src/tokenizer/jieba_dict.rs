@@ -0,0 +1,842 @@
+//! jieba 词典，以及通过文件、SQLite 表或者 SQL 函数加入的用户词典
+//!
+//! 每个词典有一个名称，同一个名称的词典在进程内共享，第一次使用时才会创建。
+//! 没有指定名称、只通过 `dict=` 或者 `dict_table=` 加载用户词典的表使用私有词典，
+//! 用户词典来源相同的表共享同一个私有词典
+
+use jieba_rs::Jieba;
+use log::warn;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{
+    Arc, LazyLock, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak,
+};
+use std::thread::JoinHandle;
+
+/// 默认词典的名称
+pub const DEFAULT_DICT_NAME: &str = "default";
+
+/// 进程内共享的默认词典，直接持有解析 jieba 内置默认词典得到的分词器
+static DEFAULT_DICT: LazyLock<Arc<JiebaDict>> = LazyLock::new(|| {
+    Arc::new(JiebaDict {
+        owns_base: true,
+        ..JiebaDict::with_jieba(Jieba::new(), true)
+    })
+});
+
+/// 没有加入用户词典的默认词典的副本，其他包含默认词典的词典从这里复制，避免重复解析
+///
+/// 只在创建其他包含默认词典的词典、或者第一次修改默认词典时从默认词典复制，只使用默认词典时不占用额外的内存
+static BASE_JIEBA: LazyLock<Jieba> = LazyLock::new(|| DEFAULT_DICT.jieba().clone());
+
+/// 进程内共享的词典集合，`load` 注册的 jieba 分词器和 SQL 函数都使用这个集合
+static JIEBA_DICTS: LazyLock<Arc<JiebaDicts>> = LazyLock::new(|| Arc::new(JiebaDicts::new()));
+
+/// 用户词典的来源
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum UserDictSource {
+    /// 通过 `dict=` 参数加载的词典文件
+    File(String),
+    /// 通过 `dict_table=` 参数加载的 SQLite 表，`db` 是表所在的数据库，见 [`database_key`]
+    Table { db: String, table: String },
+    /// 通过 `jieba_add_word` 或者 [`JiebaDict::add_word`] 加入的单词
+    Word,
+}
+
+impl UserDictSource {
+    /// `connection` 的 main 数据库中的表
+    pub(crate) fn table(connection: &Connection, table: &str) -> Self {
+        Self::Table {
+            db: database_key(connection),
+            table: table.to_owned(),
+        }
+    }
+}
+
+/// 一个已经加载的用户词典
+#[derive(Clone, Debug)]
+struct UserDict {
+    source: UserDictSource,
+    /// 最近一次读取到的单词，重新读取失败时继续使用
+    words: Vec<UserWord>,
+}
+
+/// 用户词典中的一个单词
+#[derive(Clone, Debug)]
+struct UserWord {
+    word: String,
+    freq: Option<usize>,
+    tag: Option<String>,
+}
+
+/// 一个 jieba 词典，包括基础词典和后续加入的用户词典
+pub struct JiebaDict {
+    jieba: RwLock<Jieba>,
+    /// 是否包含 jieba 内置的默认词典
+    with_default_dict: bool,
+    /// 已经加载的用户词典，在重新加载词典时依次重放
+    user_dict: Mutex<Vec<UserDict>>,
+    /// 词典的版本号，每次修改词典都会递增
+    version: AtomicU64,
+    /// 是否是持有解析结果的默认词典，修改之前需要先复制出 [`BASE_JIEBA`]
+    owns_base: bool,
+}
+
+impl Default for JiebaDict {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JiebaDict {
+    /// 创建一个包含 jieba 内置默认词典的词典
+    pub fn new() -> Self {
+        Self::with_base(true)
+    }
+
+    /// 创建一个空的词典，只包含之后加入的单词
+    pub fn empty() -> Self {
+        Self::with_base(false)
+    }
+
+    /// 使用内存中的单词创建一个词典，`with_default_dict` 表示是否同时包含 jieba 内置的默认词典
+    pub fn from_words<I, S>(words: I, with_default_dict: bool) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let words = words
+            .into_iter()
+            .map(|word| UserWord {
+                word: word.as_ref().to_owned(),
+                freq: None,
+                tag: None,
+            })
+            .collect::<Vec<UserWord>>();
+        let mut jieba = Self::base_jieba(with_default_dict);
+        add_words(&mut jieba, &words);
+        Self {
+            user_dict: Mutex::new(vec![UserDict {
+                source: UserDictSource::Word,
+                words,
+            }]),
+            ..Self::with_jieba(jieba, with_default_dict)
+        }
+    }
+
+    fn with_base(with_default_dict: bool) -> Self {
+        Self::with_jieba(Self::base_jieba(with_default_dict), with_default_dict)
+    }
+
+    fn with_jieba(jieba: Jieba, with_default_dict: bool) -> Self {
+        Self {
+            jieba: RwLock::new(jieba),
+            with_default_dict,
+            user_dict: Mutex::new(Vec::new()),
+            version: AtomicU64::new(0),
+            owns_base: false,
+        }
+    }
+
+    fn base_jieba(with_default_dict: bool) -> Jieba {
+        if with_default_dict {
+            BASE_JIEBA.clone()
+        } else {
+            Jieba::empty()
+        }
+    }
+
+    /// 获取 jieba 分词器
+    pub(crate) fn jieba(&self) -> RwLockReadGuard<'_, Jieba> {
+        self.jieba
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// 当前词典的版本号，每次修改词典后递增，可以用来判断使用这个词典的表是否需要重建
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    /// 往词典中加入一个单词，返回这个单词的词频
+    ///
+    /// 省略词频时由 jieba 计算一个使这个单词能被切分出来的词频
+    pub fn add_word(&self, word: &str, freq: Option<usize>, tag: Option<&str>) -> usize {
+        self.save_base();
+        let mut user_dict = self.lock_user_dict();
+        let freq = self.write_jieba().add_word(word, freq, tag);
+        user_dict.push(UserDict {
+            source: UserDictSource::Word,
+            words: vec![UserWord {
+                word: word.to_owned(),
+                freq: Some(freq),
+                tag: tag.map(str::to_owned),
+            }],
+        });
+        self.version.fetch_add(1, Ordering::SeqCst);
+        freq
+    }
+
+    /// 加载词典文件，每一行依次是单词、可选的词频和可选的词性，以空白字符分隔
+    ///
+    /// 同一个文件只会被加载一次，需要重新读取文件时使用 [`JiebaDict::reload`]
+    pub(crate) fn load_dict_file(&self, path: &str) -> Result<(), crate::Error> {
+        let source = UserDictSource::File(path.to_owned());
+        if self.is_loaded(&source) {
+            return Ok(());
+        }
+        let words = read_dict_file(path)?;
+        self.add_user_dict(UserDict { source, words });
+        Ok(())
+    }
+
+    /// 从 SQLite 表中加载单词，表的前三列依次是单词、可选的词频和可选的词性
+    ///
+    /// 同一个表只会被加载一次，需要重新读取表时使用 [`JiebaDict::reload`]
+    pub(crate) fn load_dict_table(
+        &self,
+        connection: &Connection,
+        table: &str,
+    ) -> Result<(), crate::Error> {
+        let source = UserDictSource::table(connection, table);
+        if self.is_loaded(&source) {
+            return Ok(());
+        }
+        // 读取表时不持有锁，读取的过程中可能会创建其他使用 jieba 的 tokenizer
+        let words = read_dict_table(connection, table)?;
+        self.add_user_dict(UserDict { source, words });
+        Ok(())
+    }
+
+    /// 重新构建词典，重新读取全部词典文件和 SQLite 表，并重放通过 [`JiebaDict::add_word`] 加入的单词
+    ///
+    /// `connection` 的 main 数据库中的表通过 `connection` 读取，其他数据库文件中的表以只读方式打开数据库读取，
+    /// 其他连接的内存数据库无法读取；文件或者表读取失败时，继续使用上一次读取到的单词。
+    /// 返回新的词典版本号
+    pub(crate) fn reload(&self, connection: &Connection) -> u64 {
+        self.save_base();
+        let mut entries = self.lock_user_dict().clone();
+        let current_db = database_key(connection);
+        // 读取文件和表时不持有锁
+        for entry in &mut entries {
+            let words = match &entry.source {
+                UserDictSource::File(path) => read_dict_file(path),
+                UserDictSource::Table { db, table } if *db == current_db => {
+                    read_dict_table(connection, table)
+                }
+                UserDictSource::Table { db, table } => read_other_dict_table(db, table),
+                UserDictSource::Word => continue,
+            };
+            match words {
+                Ok(words) => entry.words = words,
+                Err(error) => warn!("keep the previous words of {:?}: {error}", entry.source),
+            }
+        }
+        let mut user_dict = self.lock_user_dict();
+        // 重新读取的过程中加入的用户词典，保持原样加入新的词典
+        entries.extend(user_dict.split_off(entries.len()));
+        let mut jieba = Self::base_jieba(self.with_default_dict);
+        for entry in &entries {
+            add_words(&mut jieba, &entry.words);
+        }
+        *user_dict = entries;
+        *self.write_jieba() = jieba;
+        self.version.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// 词典内容的指纹，由基础词典和全部用户词典的单词计算得到
+    ///
+    /// 版本号只在进程内递增，指纹只取决于词典的内容，可以保存到数据库中，在之后的进程中判断表是否需要重建
+    pub fn fingerprint(&self) -> String {
+        let mut hash = Fnv64::default();
+        hash.write(&[u8::from(self.with_default_dict)]);
+        for entry in self.lock_user_dict().iter() {
+            for word in &entry.words {
+                hash.write(word.word.as_bytes());
+                hash.write(&[0]);
+                if let Some(freq) = word.freq {
+                    hash.write(&(freq as u64).to_le_bytes());
+                }
+                hash.write(&[0]);
+                hash.write(word.tag.as_deref().unwrap_or_default().as_bytes());
+                hash.write(&[0]);
+            }
+        }
+        format!("{:016x}", hash.0)
+    }
+
+    fn is_loaded(&self, source: &UserDictSource) -> bool {
+        self.lock_user_dict()
+            .iter()
+            .any(|entry| &entry.source == source)
+    }
+
+    /// 将已经读取的用户词典加入词典，如果这个用户词典已经被其他调用者加入，则忽略
+    fn add_user_dict(&self, entry: UserDict) {
+        self.save_base();
+        let mut user_dict = self.lock_user_dict();
+        if user_dict.iter().any(|loaded| loaded.source == entry.source) {
+            return;
+        }
+        add_words(&mut self.write_jieba(), &entry.words);
+        user_dict.push(entry);
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// 第一次修改默认词典之前，复制出没有用户词典的 [`BASE_JIEBA`]，之后重新加载词典时使用
+    fn save_base(&self) {
+        if self.owns_base {
+            LazyLock::force(&BASE_JIEBA);
+        }
+    }
+
+    fn write_jieba(&self) -> RwLockWriteGuard<'_, Jieba> {
+        self.jieba
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_user_dict(&self) -> MutexGuard<'_, Vec<UserDict>> {
+        self.user_dict
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// FNV-1a 64 位哈希，结果不依赖 Rust 版本，用于计算可以持久保存的词典指纹
+struct Fnv64(u64);
+
+impl Default for Fnv64 {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv64 {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// 按照名称管理的一组 jieba 词典
+pub struct JiebaDicts {
+    dicts: Mutex<HashMap<String, Arc<JiebaDict>>>,
+    /// 按照用户词典来源共享的私有词典，不再被任何 tokenizer 使用时释放
+    private: Mutex<HashMap<Vec<UserDictSource>, Weak<JiebaDict>>>,
+}
+
+impl Default for JiebaDicts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JiebaDicts {
+    /// 创建一个词典集合，其中的默认词典是进程内共享的默认词典
+    ///
+    /// 默认词典在第一次使用时才会加载，创建词典集合不会解析 jieba 内置的默认词典
+    pub fn new() -> Self {
+        Self {
+            dicts: Mutex::new(HashMap::new()),
+            private: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 获取这个名称的词典，不存在时创建一个包含 jieba 内置默认词典的词典
+    pub fn get_or_create(&self, name: &str) -> Arc<JiebaDict> {
+        self.lock_dicts()
+            .entry(name.to_owned())
+            .or_insert_with(|| {
+                if name == DEFAULT_DICT_NAME {
+                    DEFAULT_DICT.clone()
+                } else {
+                    Arc::new(JiebaDict::new())
+                }
+            })
+            .clone()
+    }
+
+    /// 获取这个名称的词典
+    pub fn get(&self, name: &str) -> Option<Arc<JiebaDict>> {
+        if name == DEFAULT_DICT_NAME {
+            return Some(self.get_or_create(name));
+        }
+        self.lock_dicts().get(name).cloned()
+    }
+
+    /// 获取这个名称的词典，不存在时返回错误
+    pub(crate) fn find(&self, name: &str) -> Result<Arc<JiebaDict>, crate::Error> {
+        self.get(name).ok_or_else(|| {
+            crate::Error::FunctionArgumentIncorrect(format!("no such jieba dictionary: {name}"))
+        })
+    }
+
+    /// 以这个名称注册词典，替换同名的词典
+    ///
+    /// 已经创建的 tokenizer 继续使用原来的词典，之后创建的 tokenizer 使用新的词典
+    pub fn register(&self, name: &str, dict: JiebaDict) -> Arc<JiebaDict> {
+        let dict = Arc::new(dict);
+        self.lock_dicts().insert(name.to_owned(), dict.clone());
+        dict
+    }
+
+    /// 获取从 `sources` 加载用户词典的私有词典，不存在时创建一个包含 jieba 内置默认词典的词典
+    ///
+    /// 私有词典不在默认词典上修改，使用不同用户词典的表互不影响；返回的词典由调用者加载 `sources`
+    pub(crate) fn get_or_create_private(&self, sources: Vec<UserDictSource>) -> Arc<JiebaDict> {
+        let mut private = self.lock_private();
+        private.retain(|_, dict| dict.strong_count() > 0);
+        if let Some(dict) = private.get(&sources).and_then(Weak::upgrade) {
+            return dict;
+        }
+        let dict = Arc::new(JiebaDict::new());
+        private.insert(sources, Arc::downgrade(&dict));
+        dict
+    }
+
+    /// 重新加载仍在使用的全部私有词典，见 [`JiebaDict::reload`]
+    pub(crate) fn reload_private(&self, connection: &Connection) {
+        let dicts = self
+            .lock_private()
+            .values()
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<Arc<JiebaDict>>>();
+        // 重新加载时不持有锁，读取表的过程中可能会创建新的私有词典
+        for dict in dicts {
+            dict.reload(connection);
+        }
+    }
+
+    fn lock_private(&self) -> MutexGuard<'_, HashMap<Vec<UserDictSource>, Weak<JiebaDict>>> {
+        self.private
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_dicts(&self) -> MutexGuard<'_, HashMap<String, Arc<JiebaDict>>> {
+        self.dicts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// 进程内共享的词典集合
+pub fn jieba_dicts() -> Arc<JiebaDicts> {
+    JIEBA_DICTS.clone()
+}
+
+/// 在后台线程中加载 jieba 内置的默认词典
+///
+/// 第一次使用 jieba 分词时需要解析默认词典，耗时较长。短时间运行的程序可以在启动时调用这个函数，
+/// 让解析词典与其他初始化工作同时进行；词典加载完成之前开始的分词会等待加载完成。
+/// 调用者不需要等待返回的线程，丢弃 `JoinHandle` 不会中断加载；需要确认加载完成时可以 `join`
+pub fn jieba_warm_up() -> JoinHandle<()> {
+    std::thread::spawn(|| {
+        LazyLock::force(&DEFAULT_DICT);
+    })
+}
+
+/// 进程内共享的默认词典
+pub(crate) fn default_dict() -> Arc<JiebaDict> {
+    DEFAULT_DICT.clone()
+}
+
+/// 标识 `connection` 的 main 数据库，数据库文件使用文件路径
+///
+/// 内存数据库和临时数据库只属于这个连接，使用连接的地址区分不同连接的数据库
+fn database_key(connection: &Connection) -> String {
+    match connection.path() {
+        Some(path) if !path.is_empty() => path.to_owned(),
+        _ => format!(":memory:{:p}", unsafe { connection.handle() }),
+    }
+}
+
+fn add_words(jieba: &mut Jieba, words: &[UserWord]) {
+    for word in words {
+        jieba.add_word(&word.word, word.freq, word.tag.as_deref());
+    }
+}
+
+fn read_dict_file(path: &str) -> Result<Vec<UserWord>, crate::Error> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|error| crate::Error::LoadDictFailure(format!("{path}: {error}")))?;
+    let mut words = Vec::new();
+    for (line_no, line) in content.lines().enumerate() {
+        let mut iter = line.split_whitespace();
+        let Some(word) = iter.next() else {
+            continue;
+        };
+        let freq = iter
+            .next()
+            .map(|freq| {
+                freq.parse::<usize>().map_err(|error| {
+                    crate::Error::LoadDictFailure(format!(
+                        "{path}:{}: invalid frequency {freq}: {error}",
+                        line_no + 1
+                    ))
+                })
+            })
+            .transpose()?;
+        words.push(UserWord {
+            word: word.to_owned(),
+            freq,
+            tag: iter.next().map(str::to_owned),
+        });
+    }
+    Ok(words)
+}
+
+fn read_dict_table(connection: &Connection, table: &str) -> Result<Vec<UserWord>, crate::Error> {
+    let sql = format!(
+        "SELECT * FROM \"{}\"",
+        rusqlite::vtab::escape_double_quote(table)
+    );
+    let mut stmt = connection.prepare(&sql)?;
+    let column_count = stmt.column_count();
+    let mut rows = stmt.query([])?;
+    let mut words = Vec::new();
+    while let Some(row) = rows.next()? {
+        let word = match row.get_ref(0)? {
+            ValueRef::Text(word) => str::from_utf8(word)?.to_owned(),
+            _ => continue,
+        };
+        let freq = if column_count > 1 {
+            match row.get_ref(1)? {
+                ValueRef::Integer(freq) if freq >= 0 => Some(freq as usize),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        let tag = if column_count > 2 {
+            match row.get_ref(2)? {
+                ValueRef::Text(tag) => Some(str::from_utf8(tag)?.to_owned()),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        words.push(UserWord { word, freq, tag });
+    }
+    Ok(words)
+}
+
+/// 读取其他连接的 main 数据库中的表，以只读方式打开这个数据库文件
+fn read_other_dict_table(db: &str, table: &str) -> Result<Vec<UserWord>, crate::Error> {
+    if db.starts_with(":memory:") {
+        return Err(crate::Error::LoadDictFailure(format!(
+            "{table}: the in-memory database belongs to another connection"
+        )));
+    }
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+        | OpenFlags::SQLITE_OPEN_URI
+        | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    let connection = Connection::open_with_flags(db, flags)?;
+    read_dict_table(&connection, table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JiebaDict, UserDictSource, default_dict, jieba_dicts, jieba_warm_up};
+    use crate::load;
+    use crate::tokenizer::jieba_tokenizer::JiebaTokenizer;
+    use crate::tokenizer::{TokenizeReason, Tokenizer};
+    use jieba_rs::Jieba;
+    use rusqlite::Connection;
+    use std::time::Instant;
+
+    fn cut(text: &str) -> Vec<String> {
+        default_dict()
+            .jieba()
+            .cut(text, false)
+            .into_iter()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    fn match_count(conn: &Connection, table: &str, query: &str) -> i64 {
+        conn.query_row(
+            &format!("SELECT count(*) FROM {table} WHERE {table} MATCH ?1"),
+            [query],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_dict_file() {
+        let path = std::env::temp_dir().join(format!("jieba-user-{}.dict", std::process::id()));
+        std::fs::write(&path, "阿司匹林肠溶片 10 n\n\n").unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        conn.execute_batch(&format!(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'jieba ''dict={}''');
+             CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize = 'jieba');
+             INSERT INTO t1(text) VALUES ('每日口服阿司匹林肠溶片');
+             INSERT INTO t2(text) VALUES ('每日口服阿司匹林肠溶片');",
+            path.display()
+        ))
+        .unwrap();
+        assert_eq!(match_count(&conn, "t1", "阿司匹林肠溶片"), 1);
+        assert_eq!(match_count(&conn, "t1", "肠溶片"), 0);
+        // 没有指定 dict_name 时词典文件加载到私有词典中，默认词典和其他表不受影响
+        assert_ne!(cut("阿司匹林肠溶片"), vec!["阿司匹林肠溶片"]);
+        assert_eq!(match_count(&conn, "t2", "肠溶片"), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_dict_table() {
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE my_words(word TEXT, freq INTEGER, tag TEXT);
+             INSERT INTO my_words VALUES ('布洛芬缓释胶囊', NULL, 'n');
+             CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'jieba ''dict_table=my_words''');
+             INSERT INTO t1(text) VALUES ('发热时服用布洛芬缓释胶囊');",
+        )
+        .unwrap();
+        assert_eq!(match_count(&conn, "t1", "布洛芬缓释胶囊"), 1);
+        assert_eq!(match_count(&conn, "t1", "缓释胶囊"), 0);
+        assert_ne!(cut("布洛芬缓释胶囊"), vec!["布洛芬缓释胶囊"]);
+
+        // 其他连接中同名的表是不同的用户词典
+        let other = Connection::open_in_memory().unwrap();
+        load(&other).unwrap();
+        other
+            .execute_batch(
+                "CREATE TABLE my_words(word TEXT);
+                 CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'jieba ''dict_table=my_words''');
+                 INSERT INTO t1(text) VALUES ('发热时服用布洛芬缓释胶囊');",
+            )
+            .unwrap();
+        assert_eq!(match_count(&other, "t1", "缓释胶囊"), 1);
+
+        let result = conn.execute_batch(
+            "CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize = 'jieba ''dict_table=no_such_table''')",
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_reload_dict_table_from_other_connection() {
+        let path = std::env::temp_dir().join(format!("jieba-reload-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        load(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE my_words(word TEXT);
+             INSERT INTO my_words VALUES ('肠溶阿司匹林');
+             CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'jieba ''dict_table=my_words''');",
+        )
+        .unwrap();
+        let dict =
+            jieba_dicts().get_or_create_private(vec![UserDictSource::table(&conn, "my_words")]);
+        assert_eq!(
+            vec!["肠溶阿司匹林"],
+            dict.jieba().cut("肠溶阿司匹林", false)
+        );
+        assert_ne!(
+            vec!["维生素咀嚼片"],
+            dict.jieba().cut("维生素咀嚼片", false)
+        );
+
+        // 在没有打开这个数据库的连接中重新加载，以只读方式打开数据库文件读取表
+        conn.execute("INSERT INTO my_words VALUES ('维生素咀嚼片')", [])
+            .unwrap();
+        let memory = Connection::open_in_memory().unwrap();
+        jieba_dicts().reload_private(&memory);
+        assert_eq!(
+            vec!["维生素咀嚼片"],
+            dict.jieba().cut("维生素咀嚼片", false)
+        );
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_jieba_add_word_and_reload() {
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'jieba ''dict_name=reload''');
+             INSERT INTO t1(text) VALUES ('复方甘草口服溶液');",
+        )
+        .unwrap();
+        let version = || {
+            conn.query_row("SELECT jieba_dict_version('reload')", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .unwrap()
+        };
+        let stale = || {
+            conn.query_row(
+                "SELECT stale FROM jieba_tables WHERE name = 't1'",
+                [],
+                |row| row.get::<_, Option<i64>>(0),
+            )
+            .unwrap()
+        };
+        // 没有通过 jieba_rebuild 重建过的表，无法判断是否需要重建
+        assert_eq!(stale(), None);
+        let rebuilt = conn
+            .query_row("SELECT jieba_rebuild('t1')", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .unwrap();
+        assert_eq!(stale(), Some(0));
+
+        let before = version();
+        let freq = conn
+            .query_row(
+                "SELECT jieba_add_word('复方甘草口服溶液', NULL, NULL, 'reload')",
+                [],
+                |row| row.get::<_, i64>(0),
+            )
+            .unwrap();
+        assert!(freq > 0);
+        let after = version();
+        assert!(after > before);
+        let dict = jieba_dicts().get("reload").unwrap();
+        assert_eq!(
+            vec!["复方甘草口服溶液"],
+            dict.jieba().cut("复方甘草口服溶液", false)
+        );
+
+        // 已经写入的文档需要重建索引后才能使用新的词典
+        assert_eq!(stale(), Some(1));
+        assert_eq!(match_count(&conn, "t1", "复方甘草口服溶液"), 0);
+        let fingerprint = conn
+            .query_row("SELECT jieba_rebuild('t1')", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .unwrap();
+        assert_ne!(fingerprint, rebuilt);
+        assert_eq!(stale(), Some(0));
+        assert_eq!(match_count(&conn, "t1", "复方甘草口服溶液"), 1);
+        assert!(
+            conn.execute("SELECT jieba_rebuild('no_such_table')", [])
+                .is_err()
+        );
+
+        // 重新加载词典后，通过 jieba_add_word 加入的单词仍然有效，词典的内容没有变化
+        let reloaded = conn
+            .query_row("SELECT jieba_reload('reload')", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .unwrap();
+        assert!(reloaded > after);
+        assert_eq!(
+            vec!["复方甘草口服溶液"],
+            dict.jieba().cut("复方甘草口服溶液", false)
+        );
+        assert_eq!(stale(), Some(0));
+        assert_ne!(cut("复方甘草口服溶液"), vec!["复方甘草口服溶液"]);
+
+        assert!(
+            conn.execute("SELECT jieba_add_word('', NULL, NULL, 'reload')", [])
+                .is_err()
+        );
+        assert!(
+            conn.execute("SELECT jieba_add_word('词', 0, NULL, 'reload')", [])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_named_dicts() {
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        let dict = jieba_dicts().register("legal", JiebaDict::from_words(["不可抗力条款"], true));
+        assert_eq!(0, dict.version());
+        assert_eq!(
+            vec!["不可抗力条款"],
+            dict.jieba().cut("不可抗力条款", false)
+        );
+        assert_ne!(cut("不可抗力条款"), vec!["不可抗力条款"]);
+
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'jieba ''dict_name=legal''');
+             CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize = 'jieba');
+             INSERT INTO t1(text) VALUES ('合同中的不可抗力条款');
+             INSERT INTO t2(text) VALUES ('合同中的不可抗力条款');",
+        )
+        .unwrap();
+        assert_eq!(match_count(&conn, "t1", "不可抗力条款"), 1);
+        assert_eq!(match_count(&conn, "t2", "不可抗力条款"), 1);
+        // 使用 legal 词典的表把整个词语作为一个词元
+        assert_eq!(match_count(&conn, "t1", "条款"), 0);
+        assert_eq!(match_count(&conn, "t2", "条款"), 1);
+
+        // 通过 SQL 函数往不存在的词典中加入单词时，创建这个词典
+        let version = |name: &str| {
+            conn.query_row("SELECT jieba_dict_version(?1)", [name], |row| {
+                row.get::<_, i64>(0)
+            })
+        };
+        assert!(version("tenant").is_err());
+        conn.query_row(
+            "SELECT jieba_add_word('星际争霸战队', NULL, NULL, 'tenant')",
+            [],
+            |row| row.get::<_, i64>(0),
+        )
+        .unwrap();
+        assert_eq!(1, version("tenant").unwrap());
+        let tenant = jieba_dicts().get("tenant").unwrap();
+        assert_eq!(
+            vec!["星际争霸战队"],
+            tenant.jieba().cut("星际争霸战队", false)
+        );
+        assert_ne!(cut("星际争霸战队"), vec!["星际争霸战队"]);
+
+        let empty = JiebaDict::empty();
+        empty.add_word("星海", Some(1), None);
+        assert_eq!(vec!["星海"], empty.jieba().cut("星海", false));
+    }
+
+    #[test]
+    fn test_warm_up() {
+        jieba_warm_up().join().unwrap();
+        // 预热之后创建 tokenizer 并第一次分词不再解析默认词典，比解析一次默认词典快
+        let start = Instant::now();
+        let mut tokenizer = JiebaTokenizer::new(&(), Vec::new()).unwrap();
+        tokenizer
+            .tokenize(
+                TokenizeReason::Document,
+                "我们中出了一个叛徒".as_bytes(),
+                |_, _, _| Ok(()),
+            )
+            .unwrap();
+        let tokenize_elapsed = start.elapsed();
+        let start = Instant::now();
+        let parsed = Jieba::new();
+        let parse_elapsed = start.elapsed();
+        assert!(
+            tokenize_elapsed < parse_elapsed,
+            "first tokenize {tokenize_elapsed:?}, parse {parse_elapsed:?}"
+        );
+        // 默认词典直接持有解析结果，其他包含默认词典的词典从默认词典复制
+        assert_eq!(
+            parsed.cut("我们中出了一个叛徒", false),
+            default_dict().jieba().cut("我们中出了一个叛徒", false)
+        );
+        assert_eq!(
+            parsed.cut("我们中出了一个叛徒", false),
+            JiebaDict::new().jieba().cut("我们中出了一个叛徒", false)
+        );
+
+        let conn = Connection::open_in_memory().unwrap();
+        load(&conn).unwrap();
+        let result = conn
+            .query_row("SELECT jieba_warm_up()", [], |row| {
+                row.get::<_, Option<i64>>(0)
+            })
+            .unwrap();
+        assert_eq!(result, None);
+    }
+}
@@ -1,27 +1,147 @@
-use crate::STOPWORD;
+use crate::tokenizer::filter::{
+    FilterContext, FilterOptions, FilterPipeline, Position, TokenFilter, WordPinyinFilter,
+    conflicting_filters_arg,
+};
+use crate::tokenizer::jieba_dict::{
+    DEFAULT_DICT_NAME, JiebaDict, UserDictSource, default_dict, jieba_dicts,
+};
+use crate::tokenizer::pattern::PatternFilter;
+use crate::tokenizer::stages::{PushToken, TokenStages};
+use crate::tokenizer::stopwords::{StopWordLang, StopWords, is_builtin_stopword};
+use crate::tokenizer::synonyms::Synonyms;
+use crate::tokenizer::unicode61::Normalizer;
 use crate::tokenizer::{
     TokenizeReason, Tokenizer,
-    utils::{EN_STEMMER, is_space_or_ascii_punctuation_str, make_lowercase},
+    utils::{
+        DEFAULT_STEMMER, DbHandle, ORIGINAL_PREFIX, is_pinyin_like,
+        is_space_or_ascii_punctuation_str, locale_stemmer, make_lowercase, parse_stemmer,
+        push_quoted,
+    },
 };
+use crate::utils::{offset_in, to_rusqlite_error};
 use jieba_rs::Jieba;
-use rusqlite::Error;
+use rusqlite::{Connection, Error};
+use rust_stemmers::Algorithm;
 use std::ffi::CStr;
-use std::ops::Range;
-use std::sync::LazyLock;
+use std::ops::{ControlFlow, Range};
+use std::sync::Arc;
+
+/// jieba 的分词模式
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CutMode {
+    /// 精确模式，对应 `Jieba::cut`
+    #[default]
+    Precise,
+    /// 全模式，对应 `Jieba::cut_all`，这个模式不使用 HMM
+    All,
+    /// 搜索引擎模式，对应 `Jieba::cut_for_search`
+    Search,
+}
+
+impl CutMode {
+    /// 通过名称获取分词模式，可以是 `precise`、`all` 和 `search`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "precise" => Some(Self::Precise),
+            "all" => Some(Self::All),
+            "search" => Some(Self::Search),
+            _ => None,
+        }
+    }
+
+    /// 使用这个模式进行分词
+    pub fn cut<'a>(&self, jieba: &Jieba, text: &'a str, hmm: bool) -> Vec<&'a str> {
+        match self {
+            Self::Precise => jieba.cut(text, hmm),
+            Self::All => jieba.cut_all(text),
+            Self::Search => jieba.cut_for_search(text, hmm),
+        }
+    }
+}
+
+/// 按照词性过滤单词
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PosFilter {
+    /// 只保留这些词性的单词
+    Allow(Vec<String>),
+    /// 去掉这些词性的单词
+    Deny(Vec<String>),
+}
+
+impl PosFilter {
+    /// 解析以逗号分隔的词性列表
+    fn parse_tags(tags: &str) -> Vec<String> {
+        tags.split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_owned)
+            .collect()
+    }
 
-static JIEBA: LazyLock<Jieba> = LazyLock::new(Jieba::new);
+    /// 这个词性的单词是否可以写入
+    pub fn accepts(&self, tag: &str) -> bool {
+        match self {
+            Self::Allow(tags) => tags.iter().any(|allowed| allowed == tag),
+            Self::Deny(tags) => !tags.iter().any(|denied| denied == tag),
+        }
+    }
+}
 
 /// 使用 jieba 分词器
 pub struct JiebaTokenizer {
     /// 是否启用停词表, 默认启用
     enable_stopword: bool,
+    /// 使用的停词表，默认使用内置的停词表
+    stopwords: StopWords,
+    /// 分词模式，默认使用精确模式
+    cut_mode: CutMode,
+    /// 是否使用 HMM 发现新词，默认启用
+    enable_hmm: bool,
+    /// 写入文档时是否同时写入词语的全拼和首字母，默认不启用
+    enable_pinyin: bool,
+    /// 按照词性过滤单词，默认不过滤
+    pos_filter: Option<PosFilter>,
+    /// 写入文档时是否将词性作为 tokendata 写入，默认不启用
+    pos_tokendata: bool,
+    /// 使用的词典，默认使用进程内共享的默认词典；只指定 `dict=` 或者 `dict_table=` 时使用私有词典
+    dict: Arc<JiebaDict>,
+    /// 词干提取算法，默认使用英语，`None` 表示不做词干提取
+    stemmer: Option<Algorithm>,
+    /// 写入文档时是否同时写入原词，默认不启用
+    index_original: bool,
+    /// 处理切分出的单词的过滤器流水线，默认按照上面的配置构建，见 [`FilterPipeline::builtin`]
+    filters: FilterPipeline,
+    /// 是否通过 `filters=` 指定了过滤器流水线
+    custom_filters: bool,
+    /// 单词的归一化方式，默认按照 NFKC 归一化并转换成小写
+    normalizer: Normalizer,
+    /// 折叠易混淆字符、展开同义词以及限制 token 的长度和个数
+    stages: TokenStages,
+    /// 通过 `keep=` 和 `drop=` 指定的正则过滤器
+    pattern_filter: PatternFilter,
 }
 
 impl Default for JiebaTokenizer {
     fn default() -> Self {
-        Self {
+        let mut tokenizer = Self {
             enable_stopword: true,
-        }
+            stopwords: StopWords::default(),
+            cut_mode: CutMode::default(),
+            enable_hmm: true,
+            enable_pinyin: false,
+            pos_filter: None,
+            pos_tokendata: false,
+            dict: default_dict(),
+            stemmer: DEFAULT_STEMMER,
+            index_original: false,
+            filters: FilterPipeline::default(),
+            custom_filters: false,
+            normalizer: Normalizer::default(),
+            stages: TokenStages::default(),
+            pattern_filter: PatternFilter::default(),
+        };
+        tokenizer.build_filters();
+        tokenizer
     }
 }
 
@@ -29,6 +149,443 @@ impl JiebaTokenizer {
     /// 不启用停词表
     pub fn disable_stopword(&mut self) {
         self.enable_stopword = false;
+        self.build_filters();
+    }
+    /// 设置分词模式
+    pub fn set_cut_mode(&mut self, cut_mode: CutMode) {
+        self.cut_mode = cut_mode;
+    }
+    /// 不使用 HMM 发现新词
+    pub fn disable_hmm(&mut self) {
+        self.enable_hmm = false;
+    }
+    /// 写入文档时同时写入词语的全拼和首字母
+    pub fn enable_pinyin(&mut self) {
+        self.enable_pinyin = true;
+        self.build_filters();
+    }
+    /// 按照词性过滤单词
+    pub fn set_pos_filter(&mut self, pos_filter: PosFilter) {
+        self.pos_filter = Some(pos_filter);
+    }
+    /// 写入文档时将词性作为 tokendata 写入，需要 FTS5 表启用 `tokendata=1`
+    pub fn enable_pos_tokendata(&mut self) {
+        self.pos_tokendata = true;
+    }
+    /// 设置词干提取算法，`None` 表示不做词干提取
+    pub fn set_stemmer(&mut self, stemmer: Option<Algorithm>) {
+        self.stemmer = stemmer;
+    }
+    /// 写入文档时，将转换成小写的原词加上 `=` 前缀，作为词干的 colocated token 写入，
+    /// 查询时以 `=` 开头的单词只匹配原词
+    pub fn enable_index_original(&mut self) {
+        self.index_original = true;
+        self.build_filters();
+    }
+    /// 设置同义词表，查询时同义词作为 colocated token 写入
+    pub fn set_synonyms(&mut self, synonyms: Synonyms) {
+        self.stages.set_synonyms(synonyms);
+    }
+    /// 写入文档时也将同义词作为 colocated token 写入
+    pub fn enable_index_synonyms(&mut self) {
+        self.stages.enable_index_synonyms();
+    }
+    /// 使用过滤器流水线处理切分出的单词，代替默认的归一化、停词过滤、词干提取和拼音处理
+    pub fn set_filters(&mut self, filters: FilterPipeline) {
+        self.filters = filters;
+        self.custom_filters = true;
+    }
+    /// 创建过滤器时使用的配置
+    fn filter_options(&self) -> FilterOptions<'_> {
+        FilterOptions {
+            stopwords: &self.stopwords,
+            index_original: self.index_original,
+            pinyin_query: false,
+            colocate_readings: false,
+            pattern_filter: &self.pattern_filter,
+        }
+    }
+    /// 按照当前的配置构建默认的过滤器流水线，通过 `filters=` 指定了流水线时不做处理
+    fn build_filters(&mut self) {
+        if self.custom_filters {
+            return;
+        }
+        let pinyin = self
+            .enable_pinyin
+            .then(|| Box::new(WordPinyinFilter) as Box<dyn TokenFilter>);
+        self.filters = FilterPipeline::builtin(
+            self.normalizer,
+            self.enable_stopword,
+            pinyin,
+            &self.filter_options(),
+        );
+    }
+    /// 使用的词典
+    pub(crate) fn dict(&self) -> &Arc<JiebaDict> {
+        &self.dict
+    }
+    /// 是否需要标注词性
+    fn need_tag(&self) -> bool {
+        self.pos_filter.is_some() || self.pos_tokendata
+    }
+
+    /// 将查询文档转换成 SQLite 的 match 语句，适用于启用了 `enable_pinyin` 的 jieba 分词器
+    ///
+    /// 查询文档使用 `dict` 按照 jieba 分词，汉语词语精确匹配；可以作为拼音的单词对全拼和首字母做前缀匹配，
+    /// 同时按照写入文档时的方式对单词做归一化和停词过滤，两种解释以 OR 连接。词干提取由 FTS5 查询时表的分词器完成
+    pub fn tokenize_query(text: &str, dict: &JiebaDict) -> Option<String> {
+        let mut word_buf = String::new();
+        let mut fragments = Vec::new();
+        for word in dict.jieba().cut(text, true) {
+            if is_space_or_ascii_punctuation_str(word) {
+                continue;
+            }
+            make_lowercase(word, &mut word_buf);
+            if text[..offset_in(text, word)].ends_with(ORIGINAL_PREFIX) {
+                // 以 = 开头的单词只匹配原词，需要表的分词器启用 index_original
+                if !is_builtin_stopword(word_buf.as_str(), &StopWordLang::ALL) {
+                    let mut term = String::new();
+                    push_quoted(&format!("{ORIGINAL_PREFIX}{word_buf}"), &mut term);
+                    fragments.push(format!("({term})"));
+                }
+                continue;
+            }
+            let mut terms = Vec::new();
+            if is_pinyin_like(word_buf.as_str()) {
+                let mut term = String::new();
+                push_quoted(&word_buf, &mut term);
+                term.push('*');
+                terms.push(term);
+            }
+            if !is_builtin_stopword(word_buf.as_str(), &StopWordLang::ALL) {
+                let mut term = String::new();
+                push_quoted(&word_buf, &mut term);
+                terms.push(term);
+            }
+            if !terms.is_empty() {
+                fragments.push(format!("({})", terms.join(" OR ")));
+            }
+        }
+        Some(fragments.join(" AND "))
+    }
+
+    /// 分词，可以识别的 locale 决定词干提取使用的语言，否则使用 `stemmer=` 参数指定的语言
+    fn tokenize_words(
+        &self,
+        reason: &TokenizeReason,
+        text: &str,
+        locale: Option<&str>,
+        push_token: &mut PushToken,
+    ) -> Result<ControlFlow<()>, Error> {
+        let stemmer = locale.and_then(locale_stemmer).map_or(self.stemmer, Some);
+        let jieba = self.dict.jieba();
+        let is_query = matches!(reason, TokenizeReason::Query { .. });
+        let need_sub_words = self.cut_mode == CutMode::Search && !is_query;
+        let mut interrupt = self.stages.interrupt_check();
+        // 很长的文本分段切分，每一段之前检查是否被中断，达到 max_tokens 后不再切分后面的文本
+        let mut chunks = chunks(text).peekable();
+        while let Some(chunk) = chunks.next() {
+            interrupt.check_now()?;
+            // 搜索引擎模式先按照精确模式切分，长词包含的短词在写入文档时作为 colocated token，
+            // 与长词处于同一位置，保证短语查询的位置不受影响；词语的拼音同样只在写入文档时作为 colocated token。
+            // 查询时只使用长词，否则这些 colocated token 会成为查询词的同义词
+            let words: Vec<(&str, Option<&str>)> = match self.cut_mode {
+                // 标注词性时按照精确模式切分
+                CutMode::Precise | CutMode::Search if self.need_tag() => jieba
+                    .tag(chunk, self.enable_hmm)
+                    .into_iter()
+                    .map(|tag| (tag.word, Some(tag.tag)))
+                    .collect(),
+                CutMode::Search => jieba
+                    .cut(chunk, self.enable_hmm)
+                    .into_iter()
+                    .map(|word| (word, None))
+                    .collect(),
+                cut_mode => cut_mode
+                    .cut(&jieba, chunk, self.enable_hmm)
+                    .into_iter()
+                    .map(|word| (word, None))
+                    .collect(),
+            };
+            // 前缀查询只对最后一个单词做前缀匹配
+            let last = chunks
+                .peek()
+                .is_none()
+                .then(|| {
+                    words
+                        .iter()
+                        .rposition(|(word, _)| !is_space_or_ascii_punctuation_str(word))
+                })
+                .flatten();
+            for (index, (word, tag)) in words.into_iter().enumerate() {
+                // 如果是空字符、控制字符、ascii标点字符组成组成的字符串，也不处理
+                if is_space_or_ascii_punctuation_str(word) {
+                    continue;
+                }
+                interrupt.check()?;
+                if let (Some(pos_filter), Some(tag)) = (&self.pos_filter, tag)
+                    && !pos_filter.accepts(tag)
+                {
+                    continue;
+                }
+                // 词性只在写入文档时作为 tokendata 写入
+                let tokendata = tag.filter(|_| self.pos_tokendata && !is_query);
+                let context = FilterContext {
+                    reason,
+                    text,
+                    is_last: Some(index) == last,
+                    stemmer,
+                };
+                let positions = self.filters.run(word_position(text, word), &context);
+                if positions.is_empty() {
+                    continue;
+                }
+                for position in positions {
+                    if position.push(false, tokendata, push_token)?.is_break() {
+                        return Ok(ControlFlow::Break(()));
+                    }
+                }
+                if need_sub_words {
+                    // 短词经过同样的流水线，与长词处于同一位置
+                    for sub_word in sub_words(&jieba, word) {
+                        for position in self.filters.run(word_position(text, sub_word), &context) {
+                            if position.push(true, tokendata, push_token)?.is_break() {
+                                return Ok(ControlFlow::Break(()));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+/// 分段切分时每一段的最小字节数
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// 将文本分成若干段，每一段在至少 [`CHUNK_LEN`] 字节之后的第一个分隔字符处结束，
+/// 分隔字符两侧的文本被 jieba 分别切分，因此分段不影响切分结果；
+/// 之后的 [`CHUNK_LEN`] 字节之内没有分隔字符时在第 [`CHUNK_LEN`] 字节处的字符边界结束，这时可能切断一个单词
+fn chunks(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let (chunk, tail) = rest.split_at(chunk_end(rest));
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+fn chunk_end(text: &str) -> usize {
+    if text.len() <= 2 * CHUNK_LEN {
+        return text.len();
+    }
+    let start = (CHUNK_LEN..)
+        .find(|&index| text.is_char_boundary(index))
+        .unwrap_or(CHUNK_LEN);
+    text[start..]
+        .char_indices()
+        .take_while(|&(index, _)| index < CHUNK_LEN)
+        .find(|&(_, ch)| is_chunk_separator(ch))
+        .map(|(index, ch)| start + index + ch.len_utf8())
+        .unwrap_or(start)
+}
+
+/// 不会出现在 jieba 切分出的单词中间的字符，`\r\n` 作为一个整体，因此不包括 `\r` 和 `\n`
+fn is_chunk_separator(ch: char) -> bool {
+    !ch.is_alphanumeric() && !matches!(ch, '+' | '#' | '&' | '.' | '_' | '%' | '-' | '\r' | '\n')
+}
+
+/// 切分出的单词在文本中的位置，sqlite 要求的是 byte 偏移量，全模式中的单词可能互相重叠
+fn word_position(text: &str, word: &str) -> Position {
+    let start = offset_in(text, word);
+    Position::new(word, start..start + word.len())
+}
+
+/// 搜索引擎模式中，长词包含的在词典中的二字词和三字词，与 `Jieba::cut_for_search` 的切分方式一致
+fn sub_words<'a>(jieba: &Jieba, word: &'a str) -> Vec<&'a str> {
+    let char_indices = word
+        .char_indices()
+        .map(|(index, _)| index)
+        .chain(std::iter::once(word.len()))
+        .collect::<Vec<usize>>();
+    let char_count = char_indices.len() - 1;
+    let mut words = Vec::new();
+    for n in [2, 3] {
+        if char_count <= n {
+            continue;
+        }
+        for i in 0..=char_count - n {
+            let gram = &word[char_indices[i]..char_indices[i + n]];
+            if jieba.has_word(gram) {
+                words.push(gram);
+            }
+        }
+    }
+    words
+}
+
+impl JiebaTokenizer {
+    /// 解析参数创建 tokenizer，`db` 是注册 tokenizer 的数据库连接，用于通过 `dict_table=`、
+    /// `stopwords=table:` 和 `synonyms=` 参数读取用户词典、停词表和同义词表
+    fn with_db(db: Option<DbHandle>, args: Vec<String>) -> Result<Self, Error> {
+        let mut tokenizer = Self {
+            stages: TokenStages::new(db.clone()),
+            ..Self::default()
+        };
+        let mut dict_name = None;
+        let mut dict_files = Vec::new();
+        let mut dict_tables = Vec::new();
+        let mut synonyms_table = None;
+        let mut filters = None;
+        // 由默认的流水线实现的参数，与 `filters=` 同时使用时返回错误
+        let mut pipeline_arg = None;
+        let mut args_iter = args.iter();
+        while let Some(arg) = args_iter.next() {
+            match arg.as_str() {
+                "disable_stopword" => {
+                    tokenizer.disable_stopword();
+                    pipeline_arg = Some(arg.as_str());
+                }
+                "cut" => {
+                    tokenizer.set_cut_mode(CutMode::Precise);
+                }
+                "cut_all" => {
+                    tokenizer.set_cut_mode(CutMode::All);
+                }
+                "cut_for_search" => {
+                    tokenizer.set_cut_mode(CutMode::Search);
+                }
+                "disable_hmm" => {
+                    tokenizer.disable_hmm();
+                }
+                "enable_pinyin" => {
+                    tokenizer.enable_pinyin();
+                    pipeline_arg = Some(arg.as_str());
+                }
+                "pos_tokendata" => {
+                    tokenizer.enable_pos_tokendata();
+                }
+                "index_original" => {
+                    tokenizer.enable_index_original();
+                }
+                "index_synonyms" => {
+                    tokenizer.enable_index_synonyms();
+                }
+                arg => {
+                    if tokenizer
+                        .normalizer
+                        .apply_arg(arg, &mut args_iter)
+                        .map_err(to_rusqlite_error)?
+                    {
+                        pipeline_arg = Some(arg);
+                        continue;
+                    }
+                    if tokenizer
+                        .stopwords
+                        .apply_arg(arg, db.as_ref())
+                        .map_err(to_rusqlite_error)?
+                        || tokenizer.stages.apply_arg(arg).map_err(to_rusqlite_error)?
+                        || tokenizer
+                            .pattern_filter
+                            .apply_arg(arg)
+                            .map_err(to_rusqlite_error)?
+                    {
+                        continue;
+                    }
+                    if let Some(tags) = arg.strip_prefix("pos=") {
+                        tokenizer.set_pos_filter(PosFilter::Allow(PosFilter::parse_tags(tags)));
+                    } else if let Some(tags) = arg.strip_prefix("exclude_pos=") {
+                        tokenizer.set_pos_filter(PosFilter::Deny(PosFilter::parse_tags(tags)));
+                    } else if let Some(name) = arg.strip_prefix("dict_name=") {
+                        dict_name = Some(name);
+                    } else if let Some(path) = arg.strip_prefix("dict=") {
+                        dict_files.push(path);
+                    } else if let Some(table) = arg.strip_prefix("dict_table=") {
+                        dict_tables.push(table);
+                    } else if let Some(language) = arg.strip_prefix("stemmer=") {
+                        tokenizer.set_stemmer(parse_stemmer(language).map_err(to_rusqlite_error)?);
+                    } else if let Some(table) = arg.strip_prefix("synonyms=") {
+                        synonyms_table = Some(table);
+                    } else if let Some(spec) = arg.strip_prefix("filters=") {
+                        filters = Some(spec);
+                    }
+                }
+            }
+        }
+        // 用户词典加载到 dict_name 指定的词典中，词典在第一次使用时创建；没有指定 dict_name 时
+        // 加载到按照用户词典来源共享的私有词典中，不影响使用默认词典的其他表
+        let connection = match (&db, dict_tables.is_empty()) {
+            (Some(db), _) => Some(db.connection()?),
+            (None, true) => None,
+            (None, false) => {
+                return Err(to_rusqlite_error(crate::Error::TokenizerArgumentIncorrect(
+                    "dict_table= requires a database connection".to_owned(),
+                )));
+            }
+        };
+        let dicts = jieba_dicts();
+        tokenizer.dict = match dict_name {
+            Some(name) => dicts.get_or_create(name),
+            None if dict_files.is_empty() && dict_tables.is_empty() => {
+                dicts.get_or_create(DEFAULT_DICT_NAME)
+            }
+            None => dicts.get_or_create_private(
+                dict_files
+                    .iter()
+                    .map(|path| UserDictSource::File((*path).to_owned()))
+                    .chain(dict_tables.iter().filter_map(|table| {
+                        connection
+                            .as_ref()
+                            .map(|connection| UserDictSource::table(connection, table))
+                    }))
+                    .collect(),
+            ),
+        };
+        for path in dict_files {
+            tokenizer
+                .dict
+                .load_dict_file(path)
+                .map_err(to_rusqlite_error)?;
+        }
+        // 从 SQLite 表中加载用户词典，借用注册 tokenizer 的数据库连接
+        if let Some(connection) = &connection {
+            for table in dict_tables {
+                tokenizer
+                    .dict
+                    .load_dict_table(connection, table)
+                    .map_err(to_rusqlite_error)?;
+            }
+        }
+        if tokenizer.need_tag() && tokenizer.cut_mode == CutMode::All {
+            // 全模式切分出的单词互相重叠，无法标注词性
+            return Err(to_rusqlite_error(crate::Error::TokenizerArgumentIncorrect(
+                "pos filter and pos_tokendata cannot be used with cut_all".to_owned(),
+            )));
+        }
+        // 过滤器使用全部参数解析完成后的停词表等配置
+        match filters {
+            Some(filters) => {
+                if let Some(arg) = pipeline_arg {
+                    return Err(to_rusqlite_error(conflicting_filters_arg(arg)));
+                }
+                let filters = FilterPipeline::parse(filters, &tokenizer.filter_options())
+                    .map_err(to_rusqlite_error)?;
+                tokenizer.set_filters(filters);
+            }
+            None => tokenizer.build_filters(),
+        }
+        if let Some(table) = synonyms_table {
+            // 同义词按照这个分词器的配置切分成 token 序列
+            let synonyms = tokenizer.stages.load_synonyms(table, |text, push_token| {
+                tokenizer.tokenize_words(&TokenizeReason::Document, text, None, push_token)
+            })?;
+            tokenizer.set_synonyms(synonyms);
+        }
+        Ok(tokenizer)
     }
 }
 
@@ -39,62 +596,112 @@ impl Tokenizer for JiebaTokenizer {
         c"jieba"
     }
 
+    /// 没有数据库连接，不能使用从表中读取数据的参数
     fn new(_global: &Self::Global, args: Vec<String>) -> Result<Self, Error> {
-        let mut tokenizer = Self::default();
-        for arg in args {
-            if arg.as_str() == "disable_stopword" {
-                tokenizer.disable_stopword();
-            }
-        }
-        Ok(tokenizer)
+        Self::with_db(None, args)
+    }
+
+    fn new_with_connection(
+        _global: &Self::Global,
+        connection: &Connection,
+        args: Vec<String>,
+    ) -> Result<Self, Error> {
+        Self::with_db(Some(DbHandle::new(connection)), args)
     }
 
     fn tokenize<TKF>(
         &mut self,
-        _reason: TokenizeReason,
+        reason: TokenizeReason,
         text: &[u8],
-        mut push_token: TKF,
+        push_token: TKF,
     ) -> Result<(), Error>
     where
         TKF: FnMut(&[u8], Range<usize>, bool) -> Result<(), Error>,
     {
-        let text = String::from_utf8_lossy(text);
-        // 使用 jieba 进行分词
-        let mut word_buf = String::new();
-        let mut index = 0_usize;
-        for word in JIEBA.cut(text.as_ref(), true) {
-            // sqlite 要求的是 byte 偏移量
-            let range = index..index + word.len();
-            index += word.len();
-            // 如果是空字符、控制字符、ascii标点字符组成组成的字符串，也不处理
-            if is_space_or_ascii_punctuation_str(word) {
-                continue;
-            }
-            // 对单词做归一化处理，并且将单词转换成小写
-            let need_stem = make_lowercase(word, &mut word_buf);
-            if self.enable_stopword && STOPWORD.contains(word_buf.as_str()) {
-                // 不处理停词
-                continue;
-            }
-            if need_stem {
-                let stemmed = EN_STEMMER.stem(word_buf.as_str()).into_owned();
-                (push_token)(stemmed.as_bytes(), range, false)?;
-            } else {
-                (push_token)(word_buf.as_bytes(), range, false)?;
-            }
-        }
-        Ok(())
+        self.tokenize_with_locale(reason, text, None, push_token)
+    }
+
+    /// 可以识别的 locale 决定词干提取使用的语言，否则使用 `stemmer=` 参数指定的语言；
+    /// 折叠易混淆字符、展开同义词和限制 token 由 [`TokenStages`] 完成
+    fn tokenize_with_locale<TKF>(
+        &mut self,
+        reason: TokenizeReason,
+        text: &[u8],
+        locale: Option<&str>,
+        push_token: TKF,
+    ) -> Result<(), Error>
+    where
+        TKF: FnMut(&[u8], Range<usize>, bool) -> Result<(), Error>,
+    {
+        self.stages.run(
+            &reason,
+            text,
+            |text, push_token| self.tokenize_words(&reason, text, locale, push_token),
+            push_token,
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::JIEBA;
+    use super::{CHUNK_LEN, CutMode, JiebaTokenizer, chunks};
+    use crate::tokenizer::jieba_dict::default_dict;
+    use crate::tokenizer::test_utils::{count, open, token, tokenize, tokens};
+    use crate::tokenizer::{TokenizeReason, Tokenizer};
+
+    #[test]
+    fn test_cut_chunks() {
+        let dict = default_dict();
+        let jieba = dict.jieba();
+        let text =
+            "我将点燃星海！天上的stars全部都是 eye，C++ 与 foo.bar_baz%\r\n不要凝视。".repeat(5000);
+        let pieces = chunks(&text).collect::<Vec<_>>();
+        assert!(pieces.len() > 1);
+        assert_eq!(text, pieces.concat());
+        // 分段切分与整体切分的结果相同
+        for cut_mode in [CutMode::Precise, CutMode::All, CutMode::Search] {
+            let words = cut_mode.cut(&jieba, &text, true);
+            let chunked = pieces
+                .iter()
+                .flat_map(|chunk| cut_mode.cut(&jieba, chunk, true))
+                .collect::<Vec<_>>();
+            assert_eq!(words, chunked);
+        }
+        // 没有分隔字符时在字符边界处结束
+        let text = "银行".repeat(CHUNK_LEN);
+        assert!(chunks(&text).all(|chunk| chunk.len() <= 2 * CHUNK_LEN));
+        assert_eq!(text, chunks(&text).collect::<String>());
+    }
+
+    #[test]
+    fn test_new_without_connection() {
+        let new = |args: &[&str]| {
+            JiebaTokenizer::new(&(), args.iter().map(|arg| arg.to_string()).collect())
+        };
+        let mut tokenizer = new(&[]).unwrap();
+        let mut words = Vec::new();
+        tokenizer
+            .tokenize(
+                TokenizeReason::Document,
+                "我将点燃星海".as_bytes(),
+                |token, _, _| {
+                    words.push(String::from_utf8_lossy(token).into_owned());
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(vec!["点燃", "星海"], words);
+        // 没有数据库连接时不能从表中读取数据
+        assert!(new(&["dict_table=user_dict"]).is_err());
+        assert!(new(&["stopwords=table:stopwords"]).is_err());
+    }
 
     #[test]
     fn test_tokenize_by_jieba_cut() {
         let text = "The quick (\"brown\") fox can't jump 32.3 feet, right? 我将点燃星海！天上的stars全部都是 eye，不要凝视";
-        let words = JIEBA.cut(text, false);
+        let dict = default_dict();
+        let jieba = dict.jieba();
+        let words = jieba.cut(text, false);
         let vec = [
             "The",
             " ",
@@ -140,7 +747,7 @@ mod tests {
             "凝视",
         ];
         assert_eq!(words, vec);
-        let words = JIEBA.cut(text, true);
+        let words = jieba.cut(text, true);
         let vec = [
             "The",
             " ",
@@ -185,4 +792,84 @@ mod tests {
         ];
         assert_eq!(words, vec);
     }
+
+    #[test]
+    fn test_register_jieba_tokenizer_cut_for_search() {
+        let conn = open();
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'jieba cut_for_search');
+             CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize = 'jieba');
+             INSERT INTO t1(text) VALUES ('中华人民共和国国歌'), ('国家');
+             INSERT INTO t2(text) VALUES ('中华人民共和国国歌'), ('国家');",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                token("中华人民共和国", false),
+                token("中华", true),
+                token("华人", true),
+                token("人民", true),
+                token("共和", true),
+                token("共和国", true),
+                token("国歌", false),
+            ],
+            tokens(
+                &conn,
+                &["jieba", "cut_for_search"],
+                TokenizeReason::Document,
+                "中华人民共和国国歌"
+            )
+        );
+        // 搜索引擎模式可以通过短词找到长词，精确模式不能
+        assert_eq!(count(&conn, "t1", "中华"), 1);
+        assert_eq!(count(&conn, "t2", "中华"), 0);
+        // 短词与长词处于同一位置，短语查询不受影响
+        assert_eq!(count(&conn, "t1", "\"中华人民共和国 国歌\""), 1);
+        assert_eq!(count(&conn, "t1", "\"共和国 国歌\""), 1);
+        assert_eq!(count(&conn, "t1", "\"中华 人民\""), 0);
+    }
+
+    #[test]
+    fn test_register_jieba_tokenizer_with_pos() {
+        let conn = open();
+        let tokens = |args: &[&str], reason: TokenizeReason| -> Vec<String> {
+            tokens(&conn, args, reason, "我将点燃星海 stars")
+                .into_iter()
+                .map(|(token, _)| token)
+                .collect()
+        };
+        assert_eq!(
+            vec!["点燃", "星海", "star"],
+            tokens(&["jieba"], TokenizeReason::Document)
+        );
+        assert_eq!(
+            vec!["星海", "star"],
+            tokens(&["jieba", "pos=n,ns,eng"], TokenizeReason::Document)
+        );
+        assert_eq!(
+            vec!["点燃", "star"],
+            tokens(&["jieba", "exclude_pos=ns"], TokenizeReason::Document)
+        );
+        assert_eq!(
+            vec!["点燃\0v", "星海\0ns", "star\0eng"],
+            tokens(&["jieba", "pos_tokendata"], TokenizeReason::Document)
+        );
+        assert_eq!(
+            vec!["点燃", "星海", "star"],
+            tokens(
+                &["jieba", "pos_tokendata"],
+                TokenizeReason::Query { prefix: false }
+            )
+        );
+        let args = ["jieba", "cut_all", "pos=n"];
+        assert!(tokenize(&conn, &args, TokenizeReason::Document, "星海").is_err());
+
+        // 词性作为 tokendata 写入时，FTS5 表需要启用 tokendata=1
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'jieba pos_tokendata', tokendata = 1);
+             INSERT INTO t1(text) VALUES ('我将点燃星海'), ('天上的stars');",
+        )
+        .unwrap();
+        assert_eq!(1, count(&conn, "t1", "星海"));
+    }
 }
@@ -0,0 +1,419 @@
+//! token 长度和个数的限制，防止异常的文档（例如很长的 base64 数据）使索引膨胀
+//!
+//! * `max_token_len=N`：token 最多包含 N 个字符，超过时按照 `limit_policy=` 处理
+//! * `min_token_len=N`：少于 N 个字符的 token 被丢弃
+//! * `max_tokens=N`：每次分词最多写入 N 个位置，超过时按照 `limit_policy=` 处理
+//! * `limit_policy=drop|truncate|error`：超过限制时丢弃、截断或者返回 `SQLITE_TOOBIG`，默认丢弃。
+//!   超过 `max_tokens` 时 `drop` 和 `truncate` 都只保留前 N 个位置
+//!
+//! 分词过程中会定期检查 `sqlite3_is_interrupted`，`sqlite3_interrupt` 可以取消正在进行的分词：
+//! 分词器切分单词和写入 token 时都会检查，`jieba` 将很长的文本分段切分，每一段之前检查一次
+
+use crate::tokenizer::utils::DbHandle;
+use rusqlite::Error;
+use rusqlite::ffi;
+use std::borrow::Cow;
+use std::ops::{ControlFlow, Range};
+
+/// 每写入多少个 token 检查一次数据库连接是否被中断
+const INTERRUPT_CHECK_INTERVAL: usize = 256;
+
+/// 定期检查数据库连接是否被 `sqlite3_interrupt` 中断，每调用 [`INTERRUPT_CHECK_INTERVAL`] 次检查一次
+pub(crate) struct InterruptCheck<'a> {
+    db: Option<&'a DbHandle>,
+    count: usize,
+}
+
+impl<'a> InterruptCheck<'a> {
+    /// `db` 为 `None` 时不做检查
+    pub(crate) fn new(db: Option<&'a DbHandle>) -> Self {
+        Self { db, count: 0 }
+    }
+
+    /// 数据库连接被中断时返回 `SQLITE_INTERRUPT`
+    pub(crate) fn check(&mut self) -> Result<(), Error> {
+        let check = self.count.is_multiple_of(INTERRUPT_CHECK_INTERVAL);
+        self.count += 1;
+        if check { self.check_now() } else { Ok(()) }
+    }
+
+    /// 立即检查，用于每次开销较大的操作之前
+    pub(crate) fn check_now(&mut self) -> Result<(), Error> {
+        match self.db {
+            Some(db) if db.is_interrupted() => Err(Error::SqliteFailure(
+                ffi::Error::new(ffi::SQLITE_INTERRUPT),
+                None,
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 超过限制时的处理方式
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LimitPolicy {
+    /// 丢弃超过限制的 token
+    #[default]
+    Drop,
+    /// 将过长的 token 截断
+    Truncate,
+    /// 返回错误，写入或者查询失败
+    Error,
+}
+
+impl LimitPolicy {
+    /// 通过名称获取处理方式，可以是 `drop`、`truncate` 和 `error`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "drop" => Some(Self::Drop),
+            "truncate" => Some(Self::Truncate),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+/// token 长度和个数的限制，默认没有限制
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TokenLimits {
+    /// token 的最大字符数
+    pub max_token_len: Option<usize>,
+    /// token 的最小字符数
+    pub min_token_len: Option<usize>,
+    /// 每次分词的最大位置个数
+    pub max_tokens: Option<usize>,
+    pub policy: LimitPolicy,
+}
+
+impl TokenLimits {
+    /// 处理 `max_token_len=`、`min_token_len=`、`max_tokens=` 和 `limit_policy=` 参数，不是这些参数时返回 `false`
+    pub(crate) fn apply_arg(&mut self, arg: &str) -> Result<bool, crate::Error> {
+        let Some((name, value)) = arg.split_once('=') else {
+            return Ok(false);
+        };
+        let limit = match name {
+            "max_token_len" => &mut self.max_token_len,
+            "min_token_len" => &mut self.min_token_len,
+            "max_tokens" => &mut self.max_tokens,
+            "limit_policy" => {
+                self.policy = LimitPolicy::from_name(value).ok_or_else(|| {
+                    crate::Error::TokenizerArgumentIncorrect(format!(
+                        "limit_policy must be drop, truncate or error, got {value}"
+                    ))
+                })?;
+                return Ok(true);
+            }
+            _ => return Ok(false),
+        };
+        *limit = match value.parse::<usize>() {
+            Ok(value) if value > 0 => Some(value),
+            _ => {
+                return Err(crate::Error::TokenizerArgumentIncorrect(format!(
+                    "{name} must be a positive integer, got {value}"
+                )));
+            }
+        };
+        Ok(true)
+    }
+
+    /// 对分词结果应用限制，`tokenize` 使用分词器进行分词
+    ///
+    /// 被丢弃的位置，其 colocated token 也一起丢弃；达到 `max_tokens` 后写入 token 的回调返回
+    /// [`ControlFlow::Break`]，分词器收到后立即停止分词
+    pub(crate) fn limit<F, TKF>(
+        &self,
+        db: Option<&DbHandle>,
+        tokenize: F,
+        mut push_token: TKF,
+    ) -> Result<(), Error>
+    where
+        F: FnOnce(
+            &mut dyn FnMut(&[u8], Range<usize>, bool) -> Result<ControlFlow<()>, Error>,
+        ) -> Result<ControlFlow<()>, Error>,
+        TKF: FnMut(&[u8], Range<usize>, bool) -> Result<(), Error>,
+    {
+        let mut interrupt = InterruptCheck::new(db);
+        let mut positions = 0;
+        // 当前位置是否被丢弃
+        let mut dropped = false;
+        // 达到 max_tokens 后停止分词不是错误
+        let _stopped = tokenize(&mut |token, range, colocated| {
+            interrupt.check()?;
+            if colocated && dropped {
+                return Ok(ControlFlow::Continue(()));
+            }
+            let Some(token) = self.check_len(token)? else {
+                dropped |= !colocated;
+                return Ok(ControlFlow::Continue(()));
+            };
+            if !colocated {
+                if self
+                    .max_tokens
+                    .is_some_and(|max_tokens| positions >= max_tokens)
+                {
+                    if self.policy == LimitPolicy::Error {
+                        return Err(too_big(format!("more than {positions} tokens in the text")));
+                    }
+                    return Ok(ControlFlow::Break(()));
+                }
+                positions += 1;
+                dropped = false;
+            }
+            push_token(&token, range, colocated)?;
+            Ok(ControlFlow::Continue(()))
+        })?;
+        Ok(())
+    }
+
+    /// 检查 token 的长度，返回需要写入的 token，`None` 表示丢弃这个 token
+    fn check_len<'a>(&self, token: &'a [u8]) -> Result<Option<Cow<'a, [u8]>>, Error> {
+        if self.max_token_len.is_none() && self.min_token_len.is_none() {
+            return Ok(Some(Cow::Borrowed(token)));
+        }
+        // tokendata 不计入长度
+        let end = token
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(token.len());
+        let Ok(text) = std::str::from_utf8(&token[..end]) else {
+            return Ok(Some(Cow::Borrowed(token)));
+        };
+        let mut chars = text.char_indices();
+        if let Some(min_token_len) = self.min_token_len
+            && chars.clone().nth(min_token_len - 1).is_none()
+        {
+            return Ok(None);
+        }
+        let Some(max_token_len) = self.max_token_len else {
+            return Ok(Some(Cow::Borrowed(token)));
+        };
+        let Some((index, _)) = chars.nth(max_token_len) else {
+            return Ok(Some(Cow::Borrowed(token)));
+        };
+        match self.policy {
+            LimitPolicy::Drop => Ok(None),
+            // 保留 tokendata
+            LimitPolicy::Truncate => {
+                Ok(Some(Cow::Owned([&token[..index], &token[end..]].concat())))
+            }
+            LimitPolicy::Error => Err(too_big(format!(
+                "token is longer than {max_token_len} characters"
+            ))),
+        }
+    }
+}
+
+fn too_big(msg: String) -> Error {
+    Error::SqliteFailure(ffi::Error::new(ffi::SQLITE_TOOBIG), Some(msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::jieba_tokenizer::JiebaTokenizer;
+    use crate::tokenizer::simple_tokenizer::SimpleTokenizer;
+    use crate::tokenizer::stages::PushToken;
+    use crate::tokenizer::test_utils::{count, open, token, tokenize};
+    use crate::tokenizer::{TokenizeReason, Tokenizer};
+    use rusqlite::Connection;
+
+    #[test]
+    fn test_limit_interrupted() {
+        let conn = Connection::open_in_memory().unwrap();
+        let db = DbHandle::new(&conn);
+        let tokenize = |push_token: &mut PushToken| {
+            for _ in 0..1000 {
+                if push_token(b"token", 0..5, false)?.is_break() {
+                    return Ok(ControlFlow::Break(()));
+                }
+            }
+            Ok(ControlFlow::Continue(()))
+        };
+        let mut count = 0;
+        let limits = TokenLimits::default();
+        limits
+            .limit(Some(&db), tokenize, |_, _, _| {
+                count += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(1000, count);
+        // 达到 max_tokens 后停止分词，不是错误
+        let mut tokenized = 0;
+        let mut count = 0;
+        let limits = TokenLimits {
+            max_tokens: Some(10),
+            ..TokenLimits::default()
+        };
+        limits
+            .limit(
+                Some(&db),
+                |push_token| {
+                    for _ in 0..1000 {
+                        tokenized += 1;
+                        if push_token(b"token", 0..5, false)?.is_break() {
+                            return Ok(ControlFlow::Break(()));
+                        }
+                    }
+                    Ok(ControlFlow::Continue(()))
+                },
+                |_, _, _| {
+                    count += 1;
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!((11, 10), (tokenized, count));
+        // sqlite3_interrupt 之后停止分词
+        conn.get_interrupt_handle().interrupt();
+        let result = limits.limit(Some(&db), tokenize, |_, _, _| Ok(()));
+        assert!(matches!(
+            result,
+            Err(Error::SqliteFailure(error, None)) if error.code == rusqlite::ErrorCode::OperationInterrupted
+        ));
+    }
+
+    #[test]
+    fn test_tokenize_interrupted() {
+        fn assert_interrupted<T: Tokenizer>(
+            global: &T::Global,
+            conn: &Connection,
+            args: &[&str],
+            text: &str,
+        ) {
+            let args = args.iter().map(|arg| arg.to_string()).collect();
+            let mut tokenizer = T::new_with_connection(global, conn, args).unwrap();
+            let mut count = 0;
+            let result =
+                tokenizer.tokenize(TokenizeReason::Document, text.as_bytes(), |_, _, _| {
+                    count += 1;
+                    Ok(())
+                });
+            assert!(matches!(
+                result,
+                Err(Error::SqliteFailure(error, None)) if error.code == rusqlite::ErrorCode::OperationInterrupted
+            ));
+            // 切分单词时就已经停止，没有写入任何 token
+            assert_eq!(0, count);
+        }
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.get_interrupt_handle().interrupt();
+        let text = "我将点燃星海 rust sqlite ".repeat(10000);
+        for args in [&[][..], &["pattern=\\s+"], &["tokenchars", "-"]] {
+            assert_interrupted::<SimpleTokenizer>(&(), &conn, args, &text);
+        }
+        for args in [
+            &[][..],
+            &["cut_all"],
+            &["cut_for_search"],
+            &["pos_tokendata"],
+        ] {
+            assert_interrupted::<JiebaTokenizer>(&(), &conn, args, &text);
+        }
+    }
+
+    #[test]
+    fn test_register_tokenizer_with_token_limits() {
+        let conn = open();
+        let tokens = |args: &[&str], text: &str| -> Result<Vec<(String, bool)>, crate::Error> {
+            Ok(tokenize(&conn, args, TokenizeReason::Document, text)?
+                .into_iter()
+                .map(|token| (token.token, token.colocated))
+                .collect())
+        };
+        let text = "rust abcdefghijklmnop ox 银行";
+        // 过长的 token 默认被丢弃
+        assert_eq!(
+            vec![
+                token("rust", false),
+                token("ox", false),
+                token("yin", false),
+                token("hang", false),
+                token("heng", false),
+                token("xing", false),
+            ],
+            tokens(&["simple", "max_token_len=8"], text).unwrap()
+        );
+        assert_eq!(
+            vec![
+                token("rust", false),
+                token("abcdefgh", false),
+                token("ox", false),
+            ],
+            tokens(
+                &["simple", "max_token_len=8", "limit_policy=truncate"],
+                "rust abcdefghijklmnop ox"
+            )
+            .unwrap()
+        );
+        // 被丢弃的位置，其 colocated token 也一起丢弃
+        assert_eq!(
+            vec![
+                token("rust", false),
+                token("abcdefghijklmnop", false),
+                token("hang", false),
+                token("heng", false),
+                token("xing", false),
+            ],
+            tokens(&["simple", "min_token_len=4"], text).unwrap()
+        );
+        assert_eq!(
+            vec![
+                token("rust", false),
+                token("abcdefghijklmnop", false),
+                token("ox", false),
+            ],
+            tokens(&["simple", "max_tokens=3"], text).unwrap()
+        );
+        assert_eq!(
+            vec![
+                token("rust", false),
+                token("abcd", false),
+                token("ox", false),
+                token("银行", false),
+                token("yinh", true),
+                token("yh", true),
+            ],
+            tokens(
+                &[
+                    "jieba",
+                    "max_token_len=4",
+                    "limit_policy=truncate",
+                    "enable_pinyin"
+                ],
+                text
+            )
+            .unwrap()
+        );
+        for args in [
+            &["simple", "max_token_len=8", "limit_policy=error"][..],
+            &["jieba", "max_tokens=3", "limit_policy=error"],
+        ] {
+            assert!(matches!(
+                tokens(args, text),
+                Err(crate::Error::TokenizeFailure(rusqlite::ffi::SQLITE_TOOBIG))
+            ));
+        }
+        for args in [
+            &["simple", "max_tokens=0"][..],
+            &["simple", "min_token_len=x"],
+            &["jieba", "limit_policy=skip"],
+        ] {
+            assert!(tokens(args, text).is_err());
+        }
+
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'simple ''max_token_len=8''');
+             CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize = 'simple ''max_token_len=8'' ''limit_policy=error''');",
+        )
+        .unwrap();
+        conn.execute("INSERT INTO t1(text) VALUES (?1)", [text])
+            .unwrap();
+        assert!(
+            conn.execute("INSERT INTO t2(text) VALUES (?1)", [text])
+                .is_err()
+        );
+        assert_eq!(1, count(&conn, "t1", "rust ox"));
+    }
+}
@@ -1,10 +1,21 @@
 // 代码来自 https://gist.github.com/ColonelThirtyTwo/3dd1fe04e4cff0502fa70d12f3a6e72e/revisions
 // 针对 Rust 和 ruqlite 的新版本做了一些调整
 
+pub mod confusables;
+pub mod filter;
+pub mod jieba_dict;
 pub mod jieba_tokenizer;
+pub mod limits;
+pub mod pattern;
+pub mod protected;
 pub mod simple_tokenizer;
+pub mod stages;
+pub mod stopwords;
+pub mod synonyms;
+pub mod unicode61;
 mod utils;
 
+use crate::tokenizer::utils::DbHandle;
 use rusqlite::Connection;
 use rusqlite::ffi::{
     FTS5_TOKEN_COLOCATED, FTS5_TOKENIZE_AUX, FTS5_TOKENIZE_DOCUMENT, FTS5_TOKENIZE_PREFIX,
@@ -12,7 +23,7 @@ use rusqlite::ffi::{
     fts5_api, fts5_tokenizer_v2, sqlite3_bind_pointer, sqlite3_finalize, sqlite3_prepare_v3,
     sqlite3_step, sqlite3_stmt,
 };
-use std::ffi::{CStr, c_char, c_int, c_void};
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
 use std::fmt::Formatter;
 use std::ops::Range;
 use std::panic::AssertUnwindSafe;
@@ -69,6 +80,17 @@ impl TryFrom<c_int> for TokenizeReason {
     }
 }
 
+impl From<TokenizeReason> for c_int {
+    fn from(value: TokenizeReason) -> Self {
+        match value {
+            TokenizeReason::Document => FTS5_TOKENIZE_DOCUMENT,
+            TokenizeReason::Query { prefix: false } => FTS5_TOKENIZE_QUERY,
+            TokenizeReason::Query { prefix: true } => FTS5_TOKENIZE_QUERY | FTS5_TOKENIZE_PREFIX,
+            TokenizeReason::Aux => FTS5_TOKENIZE_AUX,
+        }
+    }
+}
+
 /// Tokenizer
 pub trait Tokenizer: Sized + Send + 'static {
     /// 一个全局数据的类型
@@ -81,6 +103,18 @@ pub trait Tokenizer: Sized + Send + 'static {
     ///
     /// 在 xCreate 中被调用，xCreate 的 azArg 参数转换成 Vec<String>，并以此提供给 new方法使用
     fn new(global: &Self::Global, args: Vec<String>) -> Result<Self, rusqlite::Error>;
+    /// 使用注册 tokenizer 的数据库连接创建 Tokenizer
+    ///
+    /// 在 xCreate 中被调用，`connection` 借用 [`register_tokenizer`] 时的数据库连接，
+    /// 需要从数据库中读取数据的 tokenizer 可以在这里使用它。默认忽略连接，直接调用 [`Tokenizer::new`]
+    fn new_with_connection(
+        global: &Self::Global,
+        connection: &Connection,
+        args: Vec<String>,
+    ) -> Result<Self, rusqlite::Error> {
+        let _ = connection;
+        Self::new(global, args)
+    }
     /// 分词的具体实现
     ///
     /// 应该检查 `text` 对象，并且对每个 `token` 调用 `push_token` 这个回调方法
@@ -98,6 +132,23 @@ pub trait Tokenizer: Sized + Send + 'static {
     ) -> Result<(), rusqlite::Error>
     where
         TKF: FnMut(&[u8], Range<usize>, bool) -> Result<(), rusqlite::Error>;
+    /// 带有 locale 的分词
+    ///
+    /// FTS5 表启用了 `locale=1`，并且文本通过 `fts5_locale()` 指定了 locale 时，`locale` 不为 `None`。
+    /// 默认忽略 locale，直接调用 [`Tokenizer::tokenize`]
+    fn tokenize_with_locale<TKF>(
+        &mut self,
+        reason: TokenizeReason,
+        text: &[u8],
+        locale: Option<&str>,
+        push_token: TKF,
+    ) -> Result<(), rusqlite::Error>
+    where
+        TKF: FnMut(&[u8], Range<usize>, bool) -> Result<(), rusqlite::Error>,
+    {
+        let _ = locale;
+        self.tokenize(reason, text, push_token)
+    }
 }
 
 unsafe extern "C" fn x_create<T: Tokenizer>(
@@ -106,12 +157,15 @@ unsafe extern "C" fn x_create<T: Tokenizer>(
     nargs: c_int,
     out_tokenizer: *mut *mut Fts5Tokenizer,
 ) -> c_int {
-    let global = unsafe { &*global.cast::<T::Global>() };
+    let global = unsafe { &*global.cast::<Registered<T::Global>>() };
     let args = (0..nargs as usize)
         .map(|i| unsafe { *args.add(i) })
         .map(|s| unsafe { CStr::from_ptr(s).to_string_lossy().into_owned() })
         .collect::<Vec<String>>();
-    let res = std::panic::catch_unwind(AssertUnwindSafe(move || T::new(global, args)));
+    let res = std::panic::catch_unwind(AssertUnwindSafe(move || {
+        let connection = global.db.connection()?;
+        T::new_with_connection(&global.global, &connection, args)
+    }));
     match res {
         Ok(Ok(v)) => {
             let bp = Box::into_raw(Box::new(v));
@@ -121,7 +175,13 @@ unsafe extern "C" fn x_create<T: Tokenizer>(
             SQLITE_OK
         }
         Ok(Err(rusqlite::Error::SqliteFailure(e, _))) => e.extended_code,
-        Ok(Err(_)) => SQLITE_ERROR,
+        Ok(Err(error)) => {
+            log::error!(
+                "<{} as Tokenizer>::new failed: {error}",
+                std::any::type_name::<T>()
+            );
+            SQLITE_ERROR
+        }
         Err(msg) => {
             log::error!(
                 "<{} as Tokenizer>::new panic: {}",
@@ -148,7 +208,7 @@ unsafe extern "C" fn x_delete<T: Tokenizer>(v: *mut Fts5Tokenizer) {
 }
 
 unsafe extern "C" fn x_destroy<T: Tokenizer>(v: *mut c_void) {
-    let tokenizer = unsafe { Box::from_raw(v.cast::<T::Global>()) };
+    let tokenizer = unsafe { Box::from_raw(v.cast::<Registered<T::Global>>()) };
     match std::panic::catch_unwind(AssertUnwindSafe(move || drop(tokenizer))) {
         Ok(()) => {}
         Err(e) => {
@@ -161,15 +221,15 @@ unsafe extern "C" fn x_destroy<T: Tokenizer>(v: *mut c_void) {
     }
 }
 
-/// 忽略 locale 配置
+/// locale 通过 [`Tokenizer::tokenize_with_locale`] 传给 tokenizer
 unsafe extern "C" fn x_tokenize<T: Tokenizer>(
     this: *mut Fts5Tokenizer,
     ctx: *mut c_void,
     flag: c_int,
     data: *const c_char,
     data_len: c_int,
-    _locale: *const c_char,
-    _locale_len: c_int,
+    locale: *const c_char,
+    locale_len: c_int,
     push_token: Option<
         unsafe extern "C" fn(*mut c_void, c_int, *const c_char, c_int, c_int, c_int) -> c_int,
     >,
@@ -184,6 +244,14 @@ unsafe extern "C" fn x_tokenize<T: Tokenizer>(
     };
 
     let data = unsafe { std::slice::from_raw_parts(data.cast::<u8>(), data_len as usize) };
+    // 没有指定 locale 时，locale 是空指针或者空串
+    let locale = if locale.is_null() || locale_len <= 0 {
+        None
+    } else {
+        let locale =
+            unsafe { std::slice::from_raw_parts(locale.cast::<u8>(), locale_len as usize) };
+        str::from_utf8(locale).ok()
+    };
 
     let push_token = push_token.expect("No provide push token function");
     let push_token = |token: &[u8],
@@ -218,7 +286,9 @@ unsafe extern "C" fn x_tokenize<T: Tokenizer>(
         }
     };
 
-    match std::panic::catch_unwind(AssertUnwindSafe(|| this.tokenize(reason, data, push_token))) {
+    match std::panic::catch_unwind(AssertUnwindSafe(|| {
+        this.tokenize_with_locale(reason, data, locale, push_token)
+    })) {
         Ok(Ok(())) => SQLITE_OK,
         Ok(Err(rusqlite::Error::SqliteFailure(e, _))) => e.extended_code,
         Ok(Err(_)) => SQLITE_ERROR,
@@ -250,6 +320,7 @@ pub enum RegisterTokenizerError {
     Fts5ApiVersionTooLow,
     Fts5xCreateTokenizerV2Nul,
     Fts5xCreateTokenizerFailed(i32),
+    Fts5xFindTokenizerV2Nul,
 }
 
 impl std::fmt::Display for RegisterTokenizerError {
@@ -273,6 +344,9 @@ impl std::fmt::Display for RegisterTokenizerError {
                     "Fts5 xCreateTokenizer failed, the error flag when sqlite returned is {rc}."
                 )
             }
+            RegisterTokenizerError::Fts5xFindTokenizerV2Nul => {
+                write!(f, "Fts5 api xFindTokenizer_v2 ptr is null.")
+            }
         }
     }
 }
@@ -316,6 +390,12 @@ unsafe fn get_fts5_api(db: &Connection) -> Result<*mut fts5_api, RegisterTokeniz
     Ok(api)
 }
 
+/// 注册 tokenizer 时保存的全局数据和数据库连接
+struct Registered<G> {
+    global: G,
+    db: DbHandle,
+}
+
 /// 注册 Tokenizer
 pub fn register_tokenizer<T: Tokenizer>(
     db: &Connection,
@@ -323,7 +403,10 @@ pub fn register_tokenizer<T: Tokenizer>(
 ) -> Result<(), RegisterTokenizerError> {
     unsafe {
         let api: *mut fts5_api = get_fts5_api(db)?;
-        let global_data = Box::into_raw(Box::new(global_data));
+        let global_data = Box::into_raw(Box::new(Registered {
+            global: global_data,
+            db: DbHandle::new(db),
+        }));
         if (*api).iVersion < FTS5_API_VERSION {
             return Err(RegisterTokenizerError::Fts5ApiVersionTooLow);
         }
@@ -350,6 +433,205 @@ pub fn register_tokenizer<T: Tokenizer>(
     }
 }
 
+/// 由 FTS5 中的 tokenizer 得到的 token
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Token {
+    /// token 的内容
+    pub token: String,
+    /// token 在文本中的起始位置
+    pub start: usize,
+    /// token 在文本中的结束位置
+    pub end: usize,
+    /// 是否设置了 `FTS5_TOKEN_COLOCATED`
+    pub colocated: bool,
+}
+
+/// 使用已经注册到 FTS5 中的 tokenizer 对文本进行分词
+///
+/// 通过 `xFindTokenizer_v2` 查找 tokenizer，并调用它的 `xCreate` 和 `xTokenize`，
+/// 得到的 token 与 FTS5 写入索引或者查询时看到的 token 一致。`args` 中的第一个元素是 tokenizer 名称，
+/// `locale` 与 `fts5_locale()` 指定的 locale 一致
+pub fn tokenize_with_fts5(
+    db: &Connection,
+    args: &[String],
+    reason: TokenizeReason,
+    text: &str,
+    locale: Option<&str>,
+) -> Result<Vec<Token>, crate::Error> {
+    let Some(name) = args.first() else {
+        return Err(crate::Error::TokenizerNotFound(String::new()));
+    };
+    let name_c = CString::new(name.as_str())
+        .map_err(|_| crate::Error::TokenizerNotFound(name.to_owned()))?;
+    let args_c = args[1..]
+        .iter()
+        .map(|arg| {
+            CString::new(arg.as_str()).map_err(|_| {
+                crate::Error::TokenizerArgumentIncorrect(format!(
+                    "tokenizer argument contains a NUL byte: {arg:?}"
+                ))
+            })
+        })
+        .collect::<Result<Vec<CString>, crate::Error>>()?;
+    let mut argv = args_c.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+    let text_len: c_int = text
+        .len()
+        .try_into()
+        .map_err(|_| crate::Error::TokenizeFailure(rusqlite::ffi::SQLITE_TOOBIG))?;
+    let locale = locale.unwrap_or_default();
+    let locale_len: c_int = locale
+        .len()
+        .try_into()
+        .map_err(|_| crate::Error::TokenizeFailure(rusqlite::ffi::SQLITE_TOOBIG))?;
+
+    unsafe {
+        let api = get_fts5_api(db)?;
+        if (*api).iVersion < FTS5_API_VERSION {
+            return Err(RegisterTokenizerError::Fts5ApiVersionTooLow.into());
+        }
+        let find_tokenizer = (*api)
+            .xFindTokenizer_v2
+            .ok_or(RegisterTokenizerError::Fts5xFindTokenizerV2Nul)?;
+        let mut user_data: *mut c_void = std::ptr::null_mut();
+        let mut tokenizer: *mut fts5_tokenizer_v2 = std::ptr::null_mut();
+        if find_tokenizer(api, name_c.as_ptr(), &mut user_data, &mut tokenizer) != SQLITE_OK
+            || tokenizer.is_null()
+        {
+            return Err(crate::Error::TokenizerNotFound(name.to_owned()));
+        }
+        let (Some(create), Some(delete), Some(tokenize)) = (
+            (*tokenizer).xCreate,
+            (*tokenizer).xDelete,
+            (*tokenizer).xTokenize,
+        ) else {
+            return Err(crate::Error::TokenizerNotFound(name.to_owned()));
+        };
+
+        let mut instance: *mut Fts5Tokenizer = std::ptr::null_mut();
+        let rc = create(
+            user_data,
+            argv.as_mut_ptr(),
+            argv.len() as c_int,
+            &mut instance,
+        );
+        if rc != SQLITE_OK {
+            return Err(crate::Error::CreateTokenizerFailure(args.join(" "), rc));
+        }
+        let mut tokens = Vec::<Token>::new();
+        let rc = tokenize(
+            instance,
+            (&mut tokens as *mut Vec<Token>).cast::<c_void>(),
+            reason.into(),
+            text.as_ptr().cast::<c_char>(),
+            text_len,
+            locale.as_ptr().cast::<c_char>(),
+            locale_len,
+            Some(collect_token),
+        );
+        delete(instance);
+        if rc != SQLITE_OK {
+            return Err(crate::Error::TokenizeFailure(rc));
+        }
+        Ok(tokens)
+    }
+}
+
+/// `tokenize_with_fts5` 中的 xToken 回调，将 token 保存到 `ctx` 指向的 `Vec<Token>` 中
+unsafe extern "C" fn collect_token(
+    ctx: *mut c_void,
+    flags: c_int,
+    token: *const c_char,
+    token_len: c_int,
+    start: c_int,
+    end: c_int,
+) -> c_int {
+    let tokens = unsafe { &mut *ctx.cast::<Vec<Token>>() };
+    let token = unsafe { std::slice::from_raw_parts(token.cast::<u8>(), token_len as usize) };
+    tokens.push(Token {
+        token: String::from_utf8_lossy(token).into_owned(),
+        start: start as usize,
+        end: end as usize,
+        colocated: flags & FTS5_TOKEN_COLOCATED != 0,
+    });
+    SQLITE_OK
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use crate::tokenizer::{Token, TokenizeReason, tokenize_with_fts5};
+    use rusqlite::Connection;
+
+    /// 打开加载了扩展的内存数据库
+    pub(crate) fn open() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::load(&conn).unwrap();
+        conn
+    }
+
+    /// 使用已经注册的 tokenizer 分词，`args` 的第一个元素是 tokenizer 的名称
+    pub(crate) fn tokenize(
+        conn: &Connection,
+        args: &[&str],
+        reason: TokenizeReason,
+        text: &str,
+    ) -> Result<Vec<Token>, crate::Error> {
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        tokenize_with_fts5(conn, &args, reason, text, None)
+    }
+
+    /// 分词，返回 token 以及是否为 colocated token
+    pub(crate) fn tokens(
+        conn: &Connection,
+        args: &[&str],
+        reason: TokenizeReason,
+        text: &str,
+    ) -> Vec<(String, bool)> {
+        tokenize(conn, args, reason, text)
+            .unwrap()
+            .into_iter()
+            .map(|token| (token.token, token.colocated))
+            .collect()
+    }
+
+    /// 写入文档时分词，只返回不是 colocated 的 token
+    pub(crate) fn words(conn: &Connection, args: &[&str], text: &str) -> Vec<String> {
+        tokenize(conn, args, TokenizeReason::Document, text)
+            .unwrap()
+            .into_iter()
+            .filter(|token| !token.colocated)
+            .map(|token| token.token)
+            .collect()
+    }
+
+    /// 与 [tokens] 的返回值比较时使用
+    pub(crate) fn token(token: &str, colocated: bool) -> (String, bool) {
+        (token.to_owned(), colocated)
+    }
+
+    /// FTS5 表中匹配查询的行数
+    pub(crate) fn count(conn: &Connection, table: &str, query: &str) -> i64 {
+        conn.query_row(
+            &format!("SELECT count(*) FROM {table} WHERE {table} MATCH ?1"),
+            [query],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    /// FTS5 表中匹配查询的行的第一列，按照 rowid 排序
+    pub(crate) fn search(conn: &Connection, table: &str, query: &str) -> Vec<String> {
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT * FROM {table} WHERE {table} MATCH ?1 ORDER BY rowid"
+            ))
+            .unwrap();
+        stmt.query_map([query], |row| row.get::<_, String>(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::tokenizer::jieba_tokenizer::JiebaTokenizer;
@@ -0,0 +1,220 @@
+//! 基于正则表达式的分词和过滤
+//!
+//! * `pattern=<regex>`：`simple` 按照正则表达式切分文本，代替 Unicode 标准的单词边界，
+//!   `pattern_group=-1`（默认）表示以匹配的文本作为分隔符，`pattern_group=N` 表示提取每个匹配的第 N 个分组，
+//!   与 Lucene 的 PatternTokenizer 一致
+//! * `keep=<regex>` 和 `drop=<regex>`：`simple` 和 `jieba` 只保留或者丢弃与正则表达式匹配的位置，
+//!   作为过滤器流水线的第一个过滤器，正则表达式匹配的是单词在分词文本中对应的文本，不受归一化、词干提取和拼音转换的影响；
+//!   启用 `fold_confusables` 时匹配折叠后的文本
+//!
+//! 正则表达式在创建 tokenizer 时编译一次
+
+use crate::pinyin::has_pinyin;
+use crate::tokenizer::filter::{FilterContext, Position, TokenFilter};
+use crate::tokenizer::limits::InterruptCheck;
+use regex::Regex;
+use rusqlite::Error;
+use std::ops::Range;
+
+/// 编译正则表达式
+pub(crate) fn compile(name: &str, pattern: &str) -> Result<Regex, crate::Error> {
+    Regex::new(pattern).map_err(|error| {
+        crate::Error::TokenizerArgumentIncorrect(format!("invalid {name} regex: {error}"))
+    })
+}
+
+/// 按照正则表达式切分文本
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    regex: Regex,
+    /// 提取的分组，`None` 表示以匹配的文本作为分隔符
+    group: Option<usize>,
+}
+
+impl Pattern {
+    /// 通过 `pattern=` 和 `pattern_group=` 参数的值创建
+    pub(crate) fn new(pattern: &str, group: Option<&str>) -> Result<Self, crate::Error> {
+        let regex = compile("pattern", pattern)?;
+        let group = match group.map(str::parse::<isize>) {
+            None | Some(Ok(-1)) => None,
+            Some(Ok(group)) if group >= 0 && (group as usize) < regex.captures_len() => {
+                Some(group as usize)
+            }
+            _ => {
+                return Err(crate::Error::TokenizerArgumentIncorrect(format!(
+                    "pattern_group must be -1 or a group of the pattern, got {}",
+                    group.unwrap_or_default()
+                )));
+            }
+        };
+        Ok(Self { regex, group })
+    }
+
+    /// 切分单词，返回单词的字节偏移量和单词，有拼音的汉字总是单独作为一个单词
+    ///
+    /// 数据库连接被中断时返回 `SQLITE_INTERRUPT`
+    pub(crate) fn split<'a>(
+        &self,
+        text: &'a str,
+        interrupt: &mut InterruptCheck,
+    ) -> Result<Vec<(usize, &'a str)>, Error> {
+        let mut words = Vec::new();
+        match self.group {
+            Some(group) => {
+                for captures in self.regex.captures_iter(text) {
+                    interrupt.check()?;
+                    if let Some(matched) = captures.get(group) {
+                        push_piece(text, matched.range(), &mut words);
+                    }
+                }
+            }
+            None => {
+                let mut start = 0;
+                for matched in self.regex.find_iter(text) {
+                    interrupt.check()?;
+                    push_piece(text, start..matched.start(), &mut words);
+                    start = matched.end();
+                }
+                push_piece(text, start..text.len(), &mut words);
+            }
+        }
+        Ok(words)
+    }
+}
+
+/// 加入切分出的文本，其中的汉字单独作为一个单词
+fn push_piece<'a>(text: &'a str, range: Range<usize>, words: &mut Vec<(usize, &'a str)>) {
+    let mut start = range.start;
+    for (index, ch) in text[range.clone()].char_indices() {
+        let index = range.start + index;
+        if has_pinyin(&ch) {
+            if start < index {
+                words.push((start, &text[start..index]));
+            }
+            words.push((index, &text[index..index + ch.len_utf8()]));
+            start = index + ch.len_utf8();
+        }
+    }
+    if start < range.end {
+        words.push((start, &text[start..range.end]));
+    }
+}
+
+/// `keep=` 和 `drop=` 正则过滤器
+#[derive(Clone, Debug, Default)]
+pub struct PatternFilter {
+    keep: Option<Regex>,
+    drop: Option<Regex>,
+}
+
+impl PatternFilter {
+    /// 处理 `keep=` 和 `drop=` 参数，不是这些参数时返回 `false`
+    pub(crate) fn apply_arg(&mut self, arg: &str) -> Result<bool, crate::Error> {
+        if let Some(pattern) = arg.strip_prefix("keep=") {
+            self.keep = Some(compile("keep", pattern)?);
+        } else if let Some(pattern) = arg.strip_prefix("drop=") {
+            self.drop = Some(compile("drop", pattern)?);
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// 原文中的文本是否需要保留
+    fn accepts(&self, text: &str) -> bool {
+        self.keep.as_ref().is_none_or(|keep| keep.is_match(text))
+            && !self.drop.as_ref().is_some_and(|drop| drop.is_match(text))
+    }
+
+    /// 是否没有指定 `keep=` 和 `drop=`
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keep.is_none() && self.drop.is_none()
+    }
+}
+
+/// 过滤器流水线的第一个过滤器，被丢弃的位置，其 colocated token 也一起丢弃
+impl TokenFilter for PatternFilter {
+    fn filter(&self, position: Position, context: &FilterContext, output: &mut Vec<Position>) {
+        let text = context.text.get(position.range.clone()).unwrap_or_default();
+        if self.accepts(text) {
+            output.push(position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenizer::TokenizeReason;
+    use crate::tokenizer::test_utils::{count, open, tokenize, words};
+
+    #[test]
+    fn test_register_tokenizer_with_pattern() {
+        let conn = open();
+        let words = |args: &[&str], text: &str| words(&conn, args, text);
+        let text = "order AB-1234, box 2024; 银行 rust";
+        assert_eq!(
+            vec![
+                "ab", "1234", "box", "2024", "yin", "hang", "heng", "xing", "rust"
+            ],
+            words(&["simple"], text)
+        );
+        // 以匹配的文本作为分隔符，汉字仍然单独作为一个单词
+        assert_eq!(
+            vec![
+                "ab-1234", "box", "2024", "yin", "hang", "heng", "xing", "rust"
+            ],
+            words(&["simple", "pattern=[,;\\s]+"], text)
+        );
+        // 提取匹配的文本或者其中的分组
+        assert_eq!(
+            vec!["ab-1234"],
+            words(
+                &["simple", "pattern=[A-Z]{2}-\\d+", "pattern_group=0"],
+                text
+            )
+        );
+        assert_eq!(
+            vec!["1234"],
+            words(
+                &["simple", "pattern=([A-Z]{2})-(\\d+)", "pattern_group=2"],
+                text
+            )
+        );
+        // 去掉纯数字，正则表达式匹配的是原文
+        assert_eq!(
+            vec!["ab", "box", "yin", "hang", "heng", "xing", "rust"],
+            words(&["simple", "drop=^\\d+$"], text)
+        );
+        assert_eq!(
+            vec!["ab-1234", "box", "银行", "rust"],
+            words(&["jieba", "drop=^\\d+$"], text)
+        );
+        // 正则过滤器是流水线的第一个过滤器，指定 filters= 时同样生效
+        assert_eq!(
+            vec!["order", "ab", "box", "银", "行", "rust"],
+            words(&["simple", "drop=^\\d+$", "filters=lower"], text)
+        );
+        // 只保留 SKU 和汉字
+        assert_eq!(
+            vec!["ab-1234", "银行"],
+            words(&["jieba", "keep=^[A-Z]{2}-\\d+$|^\\p{Han}+$"], text)
+        );
+        for args in [
+            &["simple", "pattern=("][..],
+            &["simple", "pattern=\\d+", "pattern_group=1"],
+            &["simple", "pattern=\\d+", "pattern_group=x"],
+            &["jieba", "keep=["],
+        ] {
+            assert!(tokenize(&conn, args, TokenizeReason::Document, "x").is_err());
+        }
+
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'simple ''pattern=[,;\\s]+'' ''drop=^\\d+$''');
+             INSERT INTO t1(text) VALUES ('order AB-1234, box 2024');",
+        )
+        .unwrap();
+        assert_eq!(1, count(&conn, "t1", "\"ab-1234\""));
+        assert_eq!(0, count(&conn, "t1", "ab"));
+        assert_eq!(0, count(&conn, "t1", "2024"));
+    }
+}
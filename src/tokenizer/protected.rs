@@ -0,0 +1,312 @@
+//! 受保护的词语，`simple` 不会拆分这些词语，也不会对其提取词干
+//!
+//! 与停词表一样，通过 `protected=` 参数从 `file:` 开头的文件或者 `table:` 开头的表中加载，可以指定多次，
+//! `protected+=` 的值是以逗号分隔的词语。全部词语在创建 tokenizer 时编译成一个 Aho-Corasick 自动机，
+//! 词语和文本按照分词器的归一化方式（不区分大小写）归一化后匹配，例如 `Café` 匹配 `CAFÉ`，`iOS` 匹配全角的 `ｉＯＳ`，
+//! 匹配的起止位置必须是单词的边界。
+//! 匹配的原文经过分词器的归一化后作为一个 token 写入，位置是词语在原文中的范围：
+//!
+//! * 以汉字开头的词语与第一个汉字的拼音处于同一位置，汉字仍然按照原来的方式写入，例如 `小米` 与 `xiao` 处于同一位置
+//! * 其他词语代替被覆盖的单词，例如 `iOS` 写入 `ios`，而不是词干 `io`；指定 `case_sensitive` 时写入 `iOS`
+
+use crate::tokenizer::stopwords::{load_list, split_words};
+use crate::tokenizer::unicode61::Normalizer;
+use crate::tokenizer::utils::DbHandle;
+use aho_corasick::AhoCorasick;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashSet};
+use std::ops::Range;
+use std::sync::Arc;
+use unicode_normalization::char::is_combining_mark;
+
+/// 受保护的词语
+#[derive(Clone, Debug, Default)]
+pub struct ProtectedTerms {
+    /// 通过 `protected=` 加载的词表
+    lists: Vec<Arc<HashSet<String>>>,
+    /// 通过 `protected+=` 加入的词语
+    added: HashSet<String>,
+    /// 匹配时使用的归一化方式，即分词器的归一化方式，但是不区分大小写
+    match_normalizer: Normalizer,
+    /// 写入 token 时使用的归一化方式，即分词器的归一化方式
+    normalizer: Normalizer,
+    /// 匹配词语的自动机
+    automaton: Option<AhoCorasick>,
+}
+
+/// 一个受保护的词语在文本中的匹配
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProtectedMatch {
+    /// 写入的 token，即按照分词器的归一化方式归一化后的原文
+    pub term: String,
+    /// 词语在文本中的范围
+    pub range: Range<usize>,
+    /// 词语覆盖的第一个单词的下标
+    pub first_word: usize,
+    /// 词语覆盖的最后一个单词的下标
+    pub last_word: usize,
+}
+
+impl ProtectedTerms {
+    /// 加入受保护的词语，词语在 [`Self::build`] 时按照分词器的归一化方式做归一化
+    pub fn add<I, S>(&mut self, terms: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for term in terms {
+            self.added.insert(term.as_ref().trim().to_owned());
+        }
+    }
+
+    /// 处理 `protected=` 和 `protected+=` 参数，不是这些参数时返回 `false`
+    pub(crate) fn apply_arg(
+        &mut self,
+        arg: &str,
+        db: Option<&DbHandle>,
+    ) -> Result<bool, crate::Error> {
+        if let Some(terms) = arg.strip_prefix("protected+=") {
+            self.add(split_words(terms));
+        } else if let Some(source) = arg.strip_prefix("protected=") {
+            let list = load_list(
+                "protected",
+                source,
+                db,
+                crate::Error::LoadProtectedTermsFailure,
+            )?;
+            self.lists.push(list);
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// 编译自动机，在全部参数处理完成后调用，`normalizer` 是分词器的归一化方式
+    pub(crate) fn build(&mut self, normalizer: Normalizer) -> Result<(), crate::Error> {
+        self.normalizer = normalizer;
+        self.match_normalizer = Normalizer {
+            case_sensitive: false,
+            ..normalizer
+        };
+        // 词表中的词语已经按照默认的方式归一化，再次归一化使其与文本的归一化方式一致
+        let terms = self
+            .lists
+            .iter()
+            .flat_map(|list| list.iter())
+            .chain(self.added.iter())
+            .map(|term| {
+                let mut buf = String::new();
+                self.match_normalizer.normalize(term, &mut buf);
+                buf
+            })
+            .filter(|term| !term.is_empty())
+            .collect::<BTreeSet<_>>();
+        if terms.is_empty() {
+            self.automaton = None;
+            return Ok(());
+        }
+        let automaton = AhoCorasick::builder().build(&terms).map_err(|error| {
+            crate::Error::TokenizerArgumentIncorrect(format!(
+                "failed to build protected terms: {error}"
+            ))
+        })?;
+        self.automaton = Some(automaton);
+        Ok(())
+    }
+
+    /// 查找文本中的受保护词语，`words` 是从 `text` 中切分出的单词及其字节偏移量
+    ///
+    /// 返回的匹配互不重叠，按照位置排列，重叠时保留开始位置靠前的匹配，开始位置相同时保留较长的匹配
+    pub fn find(&self, text: &str, words: &[(usize, &str)]) -> Vec<ProtectedMatch> {
+        let Some(automaton) = &self.automaton else {
+            return Vec::new();
+        };
+        let normalized = NormalizedText::new(&self.match_normalizer, text);
+        let mut candidates = Vec::new();
+        for matched in automaton.find_overlapping_iter(normalized.as_str()) {
+            let Some(range) = normalized.original_range(matched.range()) else {
+                continue;
+            };
+            // 匹配的起止位置必须分别是某个单词的开始和结束
+            let first = words.binary_search_by_key(&range.start, |(index, _)| *index);
+            let last = words
+                .partition_point(|(index, _)| *index < range.end)
+                .checked_sub(1)
+                .filter(|&last| words[last].0 + words[last].1.len() == range.end);
+            if let (Ok(first_word), Some(last_word)) = (first, last) {
+                candidates.push(ProtectedMatch {
+                    term: String::new(),
+                    range,
+                    first_word,
+                    last_word,
+                });
+            }
+        }
+        candidates.sort_by_key(|candidate| (candidate.range.start, Reverse(candidate.range.end)));
+        let mut matches: Vec<ProtectedMatch> = Vec::new();
+        for candidate in candidates {
+            if matches
+                .last()
+                .is_none_or(|last| last.range.end <= candidate.range.start)
+            {
+                matches.push(candidate);
+            }
+        }
+        for matched in &mut matches {
+            self.normalizer
+                .normalize(&text[matched.range.clone()], &mut matched.term);
+        }
+        matches
+    }
+}
+
+/// 归一化后的文本，记录每个字节在原文中的范围，与 [`FoldedText`](crate::tokenizer::confusables::FoldedText) 类似
+struct NormalizedText {
+    text: String,
+    /// 归一化后文本的每个字节所属的字符序列在原文中的范围
+    sources: Vec<Range<usize>>,
+}
+
+impl NormalizedText {
+    /// 基本字符与其后的组合用附加符号一起归一化，保证 NFC 和 NFKC 可以组合字符
+    fn new(normalizer: &Normalizer, text: &str) -> Self {
+        let mut normalized = String::with_capacity(text.len());
+        let mut sources = Vec::with_capacity(text.len());
+        let mut buf = String::new();
+        let mut start = 0;
+        for (index, ch) in text.char_indices().skip(1) {
+            if !is_combining_mark(ch) {
+                normalizer.normalize(&text[start..index], &mut buf);
+                normalized.push_str(&buf);
+                sources.resize(normalized.len(), start..index);
+                start = index;
+            }
+        }
+        normalizer.normalize(&text[start..], &mut buf);
+        normalized.push_str(&buf);
+        sources.resize(normalized.len(), start..text.len());
+        Self {
+            text: normalized,
+            sources,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// 将归一化后文本中的范围转换成原文中的范围，范围的起止位置不是原文中字符序列的边界时返回 `None`
+    fn original_range(&self, range: Range<usize>) -> Option<Range<usize>> {
+        if range.is_empty() {
+            return None;
+        }
+        let first = &self.sources[range.start];
+        let last = &self.sources[range.end - 1];
+        let starts = range.start == 0 || self.sources[range.start - 1] != *first;
+        let ends = self.sources.get(range.end) != Some(last);
+        (starts && ends).then_some(first.start..last.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenizer::TokenizeReason;
+    use crate::tokenizer::test_utils::{count, open, tokenize};
+
+    #[test]
+    fn test_register_tokenizer_with_protected_terms() {
+        let conn = open();
+        let tokens = |args: &[&str], text: &str| -> Vec<(String, usize, usize, bool)> {
+            tokenize(&conn, args, TokenizeReason::Document, text)
+                .unwrap()
+                .into_iter()
+                .map(|token| (token.token, token.start, token.end, token.colocated))
+                .collect()
+        };
+        let token = |token: &str, start: usize, end: usize, colocated: bool| {
+            (token.to_owned(), start, end, colocated)
+        };
+        let text = "小米手机 iOS kiosk Visual Studio";
+        // 默认对 iOS 提取词干，“小”是停词
+        assert_eq!(token("io", 13, 16, false), tokens(&["simple"], text)[4]);
+        // 受保护的汉字词语与第一个汉字的拼音处于同一位置，其他词语代替被覆盖的单词，kiosk 中的 ios 不匹配
+        let expected = vec![
+            token("xiao", 0, 3, false),
+            token("小米", 0, 6, true),
+            token("mi", 3, 6, false),
+            token("shou", 6, 9, false),
+            token("ji", 9, 12, false),
+            token("wei", 9, 12, false),
+            token("ios", 13, 16, false),
+            token("kiosk", 17, 22, false),
+            token("visual studio", 23, 36, false),
+        ];
+        assert_eq!(
+            expected,
+            tokens(&["simple", "protected+=小米,ios,Visual Studio"], text)
+        );
+        assert_eq!(
+            expected,
+            tokens(
+                &[
+                    "simple",
+                    "protected+=小米,ios,Visual Studio",
+                    "filters=lower,stem,pinyin"
+                ],
+                text
+            )
+        );
+        assert_eq!(
+            vec![
+                token("小", 0, 3, false),
+                token("小米", 0, 6, true),
+                token("米", 3, 6, false),
+                token("ios", 7, 10, false),
+            ],
+            tokens(
+                &["simple", "protected+=小米,ios", "disable_pinyin"],
+                "小米 IOS"
+            )
+        );
+        // 词语和文本归一化后匹配，组合用附加符号、全角字符和大小写都不影响匹配，写入的是归一化后的原文
+        assert_eq!(
+            vec![
+                token("café", 0, 6, false),
+                token("ios", 7, 16, false),
+                token("café", 17, 22, false),
+            ],
+            tokens(
+                &["simple", "protected+=Café,iOS"],
+                "Cafe\u{301} ｉＯＳ CAFÉ"
+            )
+        );
+        assert_eq!(
+            vec![token("cafe", 0, 5, false), token("ios", 6, 15, false)],
+            tokens(
+                &["simple", "protected+=Café,iOS", "remove_diacritics", "2"],
+                "Café ｉＯＳ"
+            )
+        );
+        // 区分大小写时写入原文的大小写
+        assert_eq!(
+            vec![token("iOS", 0, 3, false), token("IOS", 4, 7, false)],
+            tokens(&["simple", "protected+=ios", "case_sensitive"], "iOS IOS")
+        );
+
+        conn.execute_batch(
+            "CREATE TABLE my_protected(term TEXT);
+             INSERT INTO my_protected VALUES ('小米'), ('iOS');
+             CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'simple ''protected=table:my_protected''');
+             INSERT INTO t1(text) VALUES ('小米手机 iOS'), ('米饭 kiosk');",
+        )
+        .unwrap();
+        assert_eq!(1, count(&conn, "t1", "小米"));
+        assert_eq!(1, count(&conn, "t1", "xiao mi"));
+        assert_eq!(2, count(&conn, "t1", "米"));
+        // kiosk 中的 ios 不匹配
+        assert_eq!(1, count(&conn, "t1", "ios"));
+        assert_eq!(1, count(&conn, "t1", "IOS"));
+        let args = ["simple", "protected=other:x"];
+        assert!(tokenize(&conn, &args, TokenizeReason::Document, "x").is_err());
+    }
+}
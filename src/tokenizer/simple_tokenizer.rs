@@ -1,12 +1,28 @@
-use crate::STOPWORD;
-use crate::pinyin::{get_pinyin, split_pinyin};
+use crate::pinyin::{DEFAULT_MAX_ALTERNATIVES, get_pinyin, segment_pinyin, split_pinyin};
+use crate::tokenizer::filter::{
+    FilterContext, FilterOptions, FilterPipeline, PinyinFilter, Position, TokenFilter,
+    conflicting_filters_arg,
+};
+use crate::tokenizer::pattern::{Pattern, PatternFilter};
+use crate::tokenizer::protected::{ProtectedMatch, ProtectedTerms};
+use crate::tokenizer::stages::{PushToken, TokenStages};
+use crate::tokenizer::stopwords::{StopWordLang, StopWords, is_builtin_stopword};
+use crate::tokenizer::synonyms::Synonyms;
+use crate::tokenizer::unicode61::{Normalizer, TokenChars};
 use crate::tokenizer::{
     TokenizeReason, Tokenizer,
-    utils::{EN_STEMMER, make_lowercase, need_pinyin},
+    utils::{
+        DEFAULT_STEMMER, DbHandle, ORIGINAL_PREFIX, is_pinyin_like, locale_stemmer, make_lowercase,
+        need_pinyin, parse_stemmer, push_quoted,
+    },
 };
-use rusqlite::Error;
+use crate::utils::to_rusqlite_error;
+use log::debug;
+use rusqlite::{Connection, Error};
+use rust_stemmers::Algorithm;
+use std::collections::{BTreeSet, HashMap};
 use std::ffi::CStr;
-use std::ops::Range;
+use std::ops::{ControlFlow, Range};
 use unicode_segmentation::UnicodeSegmentation;
 
 /// 适用于拼音和中文的分词器
@@ -15,14 +31,55 @@ pub struct SimpleTokenizer {
     enable_pinyin: bool,
     /// 是否启用停词表, 默认启用
     enable_stopword: bool,
+    /// 使用的停词表，默认使用内置的停词表
+    stopwords: StopWords,
+    /// 查询时是否将连续的拼音串切分成音节，默认不启用
+    enable_pinyin_query: bool,
+    /// 多音字的其他读音是否作为 colocated token 写入，默认每个读音各是一个位置
+    colocate_readings: bool,
+    /// 词干提取算法，默认使用英语，`None` 表示不做词干提取
+    stemmer: Option<Algorithm>,
+    /// 写入文档时是否同时写入原词，默认不启用
+    index_original: bool,
+    /// 处理切分出的单词的过滤器流水线，默认按照上面的配置构建，见 [`FilterPipeline::builtin`]
+    filters: FilterPipeline,
+    /// 是否通过 `filters=` 指定了过滤器流水线
+    custom_filters: bool,
+    /// 单词的归一化方式，默认按照 NFKC 归一化并转换成小写
+    normalizer: Normalizer,
+    /// 折叠易混淆字符、展开同义词以及限制 token 的长度和个数
+    stages: TokenStages,
+    /// 通过 `keep=` 和 `drop=` 指定的正则过滤器
+    pattern_filter: PatternFilter,
+    /// 通过 `pattern=` 指定的正则表达式分词，代替 Unicode 标准的单词边界
+    pattern: Option<Pattern>,
+    /// 不拆分、不提取词干的受保护词语
+    protected: ProtectedTerms,
+    /// 通过 `tokenchars`、`separators` 和 `categories` 指定的单词字符，默认按照 Unicode 标准的单词边界切分
+    token_chars: Option<TokenChars>,
 }
 
 impl Default for SimpleTokenizer {
     fn default() -> Self {
-        Self {
+        let mut tokenizer = Self {
             enable_pinyin: true,
             enable_stopword: true,
-        }
+            stopwords: StopWords::default(),
+            enable_pinyin_query: false,
+            colocate_readings: false,
+            stemmer: DEFAULT_STEMMER,
+            index_original: false,
+            filters: FilterPipeline::default(),
+            custom_filters: false,
+            normalizer: Normalizer::default(),
+            stages: TokenStages::default(),
+            pattern_filter: PatternFilter::default(),
+            pattern: None,
+            protected: ProtectedTerms::default(),
+            token_chars: None,
+        };
+        tokenizer.build_filters();
+        tokenizer
     }
 }
 
@@ -30,31 +87,183 @@ impl SimpleTokenizer {
     /// 关闭拼音分词
     pub fn disable_pinyin(&mut self) {
         self.enable_pinyin = false;
+        self.build_filters();
     }
     /// 不启用停词表
     pub fn disable_stopword(&mut self) {
         self.enable_stopword = false;
+        self.build_filters();
     }
-    /// 将查询文档转换成 SQLite 的 match 语句
-    pub fn tokenize_query(text: &str) -> Option<String> {
-        let mut match_sql = "".to_owned();
-        for (_, word) in text.unicode_word_indices() {
+    /// 查询时将连续的拼音串切分成音节，使得不借助 `simple_query` 也能使用拼音查询
+    pub fn enable_pinyin_query(&mut self) {
+        self.enable_pinyin_query = true;
+        self.build_filters();
+    }
+    /// 多音字的其他读音作为第一个读音的 colocated token 写入，多音字与前后的汉字组成的拼音短语都可以匹配。
+    /// 这个参数改变写入索引的内容，已有的表启用后需要重建索引
+    pub fn enable_colocate_readings(&mut self) {
+        self.colocate_readings = true;
+        self.build_filters();
+    }
+    /// 设置词干提取算法，`None` 表示不做词干提取
+    pub fn set_stemmer(&mut self, stemmer: Option<Algorithm>) {
+        self.stemmer = stemmer;
+    }
+    /// 写入文档时，将转换成小写的原词加上 `=` 前缀，作为词干的 colocated token 写入，
+    /// 查询时以 `=` 开头的单词只匹配原词
+    pub fn enable_index_original(&mut self) {
+        self.index_original = true;
+        self.build_filters();
+    }
+    /// 设置同义词表，查询时同义词作为 colocated token 写入
+    pub fn set_synonyms(&mut self, synonyms: Synonyms) {
+        self.stages.set_synonyms(synonyms);
+    }
+    /// 写入文档时也将同义词作为 colocated token 写入
+    pub fn enable_index_synonyms(&mut self) {
+        self.stages.enable_index_synonyms();
+    }
+    /// 使用过滤器流水线处理切分出的单词，代替默认的归一化、停词过滤、拼音处理和词干提取
+    pub fn set_filters(&mut self, filters: FilterPipeline) {
+        self.filters = filters;
+        self.custom_filters = true;
+    }
+    /// 创建过滤器时使用的配置
+    fn filter_options(&self) -> FilterOptions<'_> {
+        FilterOptions {
+            stopwords: &self.stopwords,
+            index_original: self.index_original,
+            pinyin_query: self.enable_pinyin && self.enable_pinyin_query,
+            colocate_readings: self.colocate_readings,
+            pattern_filter: &self.pattern_filter,
+        }
+    }
+    /// 按照当前的配置构建默认的过滤器流水线，通过 `filters=` 指定了流水线时不做处理
+    fn build_filters(&mut self) {
+        if self.custom_filters {
+            return;
+        }
+        let options = self.filter_options();
+        let pinyin = self
+            .enable_pinyin
+            .then(|| Box::new(PinyinFilter::new(&options)) as Box<dyn TokenFilter>);
+        self.filters =
+            FilterPipeline::builtin(self.normalizer, self.enable_stopword, pinyin, &options);
+    }
+    /// 将查询文档转换成 SQLite 的 match 语句，生成的语句受到 `limits` 的限制
+    ///
+    /// 对于非汉字的单词，如果可以作为拼音，则给出拼音拆分的组合；同时按照写入文档时的方式，
+    /// 对单词做归一化和停词过滤，两种解释以 OR 连接。词干提取由 FTS5 查询时表的分词器完成，
+    /// 所以生成的语句适用于任意 `stemmer` 配置
+    ///
+    /// 以 `=` 开头的单词只匹配单词的原形，例如 `=liked` 不会匹配 liking，需要表的分词器启用 `index_original`
+    ///
+    /// 如果查询项的总数超过 [`QueryLimits::max_terms`]，从查询项最多的单词开始逐个退化为精确匹配，
+    /// 直到总数不超过限制：汉字按照原字匹配，拼音串只保留音节个数最少的组合和原词
+    pub fn tokenize_query(text: &str, limits: &QueryLimits) -> Option<String> {
+        let explain = Self::explain_query(text, limits);
+        let match_sql = explain
+            .words
+            .iter()
+            .filter_map(QueryWord::fragment)
+            .collect::<Vec<String>>()
+            .join(" AND ");
+        Some(match_sql)
+    }
+
+    /// 解释查询文档中的每个单词是如何被转换成 match 语句的
+    pub fn explain_query(text: &str, limits: &QueryLimits) -> QueryExplain {
+        let mut words = Vec::new();
+        let mut total_terms = 0;
+        let mut word_buf = String::new();
+        for (index, word) in text.unicode_word_indices() {
+            let mut query_word = QueryWord {
+                word: word.to_owned(),
+                kind: QueryWordKind::English,
+                readings: Vec::new(),
+                alternatives: Vec::new(),
+                groups: Vec::new(),
+                fallback: Vec::new(),
+                degraded: false,
+            };
             // 判断是否是单字
             if need_pinyin(word) {
+                query_word.kind = QueryWordKind::Hanzi;
                 if let Some(ch) = word.chars().next()
                     && let Some(pinyin_vec) = get_pinyin(&ch)
                 {
-                    for pinyin in pinyin_vec {
-                        let sql = Self::split_pinyin_to_sql(&pinyin);
-                        Self::append_match_sql(sql, &mut match_sql);
-                    }
+                    query_word.readings = pinyin_vec;
+                }
+                for pinyin in query_word.readings.iter().take(limits.max_readings) {
+                    let alternatives = split_pinyin(pinyin, limits.max_alternatives);
+                    query_word.groups.push(Self::pinyin_to_terms(&alternatives));
+                    query_word.alternatives.extend(alternatives);
+                }
+                let mut term = String::new();
+                push_quoted(word, &mut term);
+                query_word.fallback.push(term);
+            } else if text[..index].ends_with(ORIGINAL_PREFIX) {
+                // 以 = 开头的单词只匹配原词，需要表的分词器启用 index_original
+                query_word.kind = QueryWordKind::Original;
+                make_lowercase(word, &mut word_buf);
+                if !is_builtin_stopword(word_buf.as_str(), &StopWordLang::ALL) {
+                    let mut term = String::new();
+                    push_quoted(&format!("{ORIGINAL_PREFIX}{word_buf}"), &mut term);
+                    query_word.groups.push(vec![term.clone()]);
+                    query_word.fallback.push(term);
                 }
             } else {
-                let sql = Self::split_pinyin_to_sql(word);
-                Self::append_match_sql(sql, &mut match_sql);
+                make_lowercase(word, &mut word_buf);
+                let mut terms = Vec::new();
+                if is_pinyin_like(word_buf.as_str()) {
+                    if let Some(syllables) = segment_pinyin(word_buf.as_str(), true) {
+                        query_word.kind = QueryWordKind::Pinyin;
+                        if syllables.len() > 1 {
+                            query_word
+                                .fallback
+                                .push(format!("{}*", syllables.join("+")));
+                        }
+                    }
+                    let alternatives = split_pinyin(word_buf.as_str(), limits.max_alternatives);
+                    terms = Self::pinyin_to_terms(&alternatives);
+                    query_word.alternatives.extend(alternatives);
+                }
+                if !is_builtin_stopword(word_buf.as_str(), &StopWordLang::ALL) {
+                    let mut term = String::new();
+                    push_quoted(&word_buf, &mut term);
+                    terms.push(term);
+                }
+                if !terms.is_empty() {
+                    query_word.groups.push(terms);
+                }
+                let mut term = String::new();
+                push_quoted(&word_buf, &mut term);
+                query_word.fallback.push(term);
             }
+            total_terms += query_word.term_count();
+            words.push(query_word);
         }
-        Some(match_sql)
+
+        if total_terms > limits.max_terms {
+            debug!(
+                "simple_query produces {total_terms} terms, more than {}, fall back to exact matching",
+                limits.max_terms
+            );
+        }
+        // 从查询项最多的单词开始退化，直到总数不超过限制或者没有可以退化的单词
+        while total_terms > limits.max_terms {
+            let Some(word) = words
+                .iter_mut()
+                .filter(|word| word.fallback.len() < word.term_count())
+                .max_by_key(|word| word.term_count())
+            else {
+                break;
+            };
+            total_terms -= word.term_count();
+            word.degraded = true;
+            total_terms += word.term_count();
+        }
+        QueryExplain { words }
     }
 
     fn append_match_sql(sql: String, buf: &mut String) {
@@ -67,98 +276,382 @@ impl SimpleTokenizer {
         buf.push(')');
     }
 
-    fn split_pinyin_to_sql(word: &str) -> String {
-        let pinyin_set = split_pinyin(word);
-        pinyin_set
+    fn pinyin_to_terms(alternatives: &BTreeSet<String>) -> Vec<String> {
+        alternatives
+            .iter()
+            .map(|pinyin| format!("{pinyin}*"))
+            .collect()
+    }
+
+    /// 分词，可以识别的 locale 决定词干提取使用的语言，否则使用 `stemmer=` 参数指定的语言
+    fn tokenize_words(
+        &self,
+        reason: &TokenizeReason,
+        text: &str,
+        locale: Option<&str>,
+        push_token: &mut PushToken,
+    ) -> Result<ControlFlow<()>, Error> {
+        let stemmer = locale.and_then(locale_stemmer).map_or(self.stemmer, Some);
+        // 切分单词时也检查是否被中断，很长的文本在写入第一个 token 之前就可以取消
+        let mut interrupt = self.stages.interrupt_check();
+        // 默认使用 unicode_word_indices 进行分词，所有中文字符应该是单独一个字符成 word
+        let words = match (&self.pattern, &self.token_chars) {
+            (Some(pattern), _) => pattern.split(text, &mut interrupt)?,
+            (None, Some(token_chars)) => token_chars.split(text, &mut interrupt)?,
+            (None, None) => text
+                .unicode_word_indices()
+                .map(|word| interrupt.check().map(|_| word))
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+        // 受保护的词语，以覆盖的第一个单词的下标索引
+        let protected: HashMap<usize, ProtectedMatch> = self
+            .protected
+            .find(text, &words)
             .into_iter()
-            .fold(String::new(), |mut acc, pinyin| {
-                if acc.is_empty() {
-                    acc.push_str(&pinyin);
-                    acc.push('*');
-                } else {
-                    acc.push_str(" OR ");
-                    acc.push_str(&pinyin);
-                    acc.push('*');
+            .map(|matched| (matched.first_word, matched))
+            .collect();
+        // 被受保护的词语代替的单词
+        let mut skip_until = 0;
+        for (i, &(index, word)) in words.iter().enumerate() {
+            if i < skip_until {
+                continue;
+            }
+            interrupt.check()?;
+            let matched = protected.get(&i);
+            if let Some(matched) = matched
+                && !need_pinyin(word)
+            {
+                // 其他词语代替被覆盖的单词，不做停词过滤和词干提取
+                if push_token(matched.term.as_bytes(), matched.range.clone(), false)?.is_break() {
+                    return Ok(ControlFlow::Break(()));
+                }
+                skip_until = matched.last_word + 1;
+                continue;
+            }
+            let context = FilterContext {
+                reason,
+                text,
+                is_last: i + 1 == words.len(),
+                stemmer,
+            };
+            let mut word = Position::new(word, index..index + word.len());
+            // 受保护词语的第一个汉字不做停词过滤
+            word.keyword = matched.is_some();
+            let positions = self.filters.run(word, &context);
+            // 以汉字开头的受保护词语与第一个位置处于同一位置，汉字仍然按照原来的方式写入
+            let mut positions = positions.iter();
+            if let Some(matched) = matched {
+                let term = matched.term.as_bytes();
+                let Some(first) = positions.next() else {
+                    if (push_token)(term, matched.range.clone(), false)?.is_break() {
+                        return Ok(ControlFlow::Break(()));
+                    }
+                    continue;
                 };
-                acc
-            })
+                if first.push(false, None, push_token)?.is_break()
+                    || (push_token)(term, matched.range.clone(), true)?.is_break()
+                {
+                    return Ok(ControlFlow::Break(()));
+                }
+            }
+            for position in positions {
+                if position.push(false, None, push_token)?.is_break() {
+                    return Ok(ControlFlow::Break(()));
+                }
+            }
+        }
+        Ok(ControlFlow::Continue(()))
     }
 }
 
-impl Tokenizer for SimpleTokenizer {
-    type Global = ();
+/// `simple_query` 对查询文档的解释
+#[derive(Clone, Debug)]
+pub struct QueryExplain {
+    /// 查询文档中的全部单词
+    pub words: Vec<QueryWord>,
+}
 
-    fn name() -> &'static CStr {
-        c"simple"
+/// 查询文档中单词的类型
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QueryWordKind {
+    /// 有拼音的单个汉字
+    Hanzi,
+    /// 可以被切分成拼音的单词
+    Pinyin,
+    /// 以 `=` 开头，只匹配原词的单词
+    Original,
+    /// 其他单词
+    English,
+}
+
+impl QueryWordKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QueryWordKind::Hanzi => "hanzi",
+            QueryWordKind::Pinyin => "pinyin",
+            QueryWordKind::Original => "original",
+            QueryWordKind::English => "english",
+        }
     }
+}
 
-    fn new(_global: &Self::Global, args: Vec<String>) -> Result<Self, Error> {
-        let mut tokenizer = Self::default();
-        for arg in args {
+/// `simple_query` 对单个单词的解释
+#[derive(Clone, Debug)]
+pub struct QueryWord {
+    /// 原始单词
+    pub word: String,
+    /// 单词的类型
+    pub kind: QueryWordKind,
+    /// 汉字的全部读音
+    pub readings: Vec<String>,
+    /// 拼音拆分的组合
+    pub alternatives: Vec<String>,
+    /// 若干组以 OR 连接的查询项，组与组之间以 AND 连接
+    groups: Vec<Vec<String>>,
+    /// 超过限制后退化使用的以 OR 连接的查询项：原词的精确匹配，拼音串还包括音节个数最少的组合
+    fallback: Vec<String>,
+    /// 是否因为查询项的总数超过限制而退化
+    pub degraded: bool,
+}
+
+impl QueryWord {
+    /// 这个单词对应的 match 子句，停词等不参与查询的单词返回 `None`
+    pub fn fragment(&self) -> Option<String> {
+        if self.groups.is_empty() {
+            return None;
+        }
+        let mut match_sql = "".to_owned();
+        if self.degraded {
+            SimpleTokenizer::append_match_sql(self.fallback.join(" OR "), &mut match_sql);
+        } else {
+            for terms in &self.groups {
+                SimpleTokenizer::append_match_sql(terms.join(" OR "), &mut match_sql);
+            }
+        }
+        Some(match_sql)
+    }
+
+    /// 这个单词当前产生的查询项个数
+    fn term_count(&self) -> usize {
+        if self.groups.is_empty() {
+            0
+        } else if self.degraded {
+            self.fallback.len()
+        } else {
+            self.groups.iter().map(Vec::len).sum()
+        }
+    }
+}
+
+/// 生成 match 语句时的限制，避免查询语句过于庞大
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QueryLimits {
+    /// 每个汉字最多使用的读音个数
+    pub max_readings: usize,
+    /// 每个单词最多保留的拼音组合个数
+    pub max_alternatives: usize,
+    /// match 语句中最多包含的查询项个数
+    pub max_terms: usize,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        Self {
+            max_readings: 4,
+            max_alternatives: DEFAULT_MAX_ALTERNATIVES,
+            max_terms: 64,
+        }
+    }
+}
+
+impl SimpleTokenizer {
+    /// 解析参数创建 tokenizer，`db` 是注册 tokenizer 的数据库连接，
+    /// 用于通过 `stopwords=table:`、`protected=table:` 和 `synonyms=` 参数读取词表和同义词表
+    fn with_db(db: Option<DbHandle>, args: Vec<String>) -> Result<Self, Error> {
+        let mut tokenizer = Self {
+            stages: TokenStages::new(db.clone()),
+            ..Self::default()
+        };
+        let mut synonyms_table = None;
+        let mut filters = None;
+        // 由默认的流水线实现的参数，与 `filters=` 同时使用时返回错误
+        let mut pipeline_arg = None;
+        let mut pattern = None;
+        let mut pattern_group = None;
+        let mut args_iter = args.iter();
+        while let Some(arg) = args_iter.next() {
             match arg.as_str() {
                 "disable_pinyin" => {
                     tokenizer.disable_pinyin();
+                    pipeline_arg = Some(arg.as_str());
                 }
                 "disable_stopword" => {
                     tokenizer.disable_stopword();
+                    pipeline_arg = Some(arg.as_str());
+                }
+                "enable_pinyin_query" => {
+                    tokenizer.enable_pinyin_query();
+                }
+                "colocate_readings" => {
+                    tokenizer.enable_colocate_readings();
+                }
+                "index_original" => {
+                    tokenizer.enable_index_original();
+                }
+                "index_synonyms" => {
+                    tokenizer.enable_index_synonyms();
+                }
+                arg => {
+                    if tokenizer
+                        .normalizer
+                        .apply_arg(arg, &mut args_iter)
+                        .map_err(to_rusqlite_error)?
+                    {
+                        pipeline_arg = Some(arg);
+                        continue;
+                    }
+                    if tokenizer
+                        .stopwords
+                        .apply_arg(arg, db.as_ref())
+                        .map_err(to_rusqlite_error)?
+                        || tokenizer.stages.apply_arg(arg).map_err(to_rusqlite_error)?
+                        || tokenizer
+                            .pattern_filter
+                            .apply_arg(arg)
+                            .map_err(to_rusqlite_error)?
+                        || tokenizer
+                            .protected
+                            .apply_arg(arg, db.as_ref())
+                            .map_err(to_rusqlite_error)?
+                        || TokenChars::apply_arg(&mut tokenizer.token_chars, arg, &mut args_iter)
+                            .map_err(to_rusqlite_error)?
+                    {
+                        continue;
+                    }
+                    if let Some(language) = arg.strip_prefix("stemmer=") {
+                        tokenizer.set_stemmer(parse_stemmer(language).map_err(to_rusqlite_error)?);
+                    } else if let Some(table) = arg.strip_prefix("synonyms=") {
+                        synonyms_table = Some(table);
+                    } else if let Some(spec) = arg.strip_prefix("filters=") {
+                        filters = Some(spec);
+                    } else if let Some(regex) = arg.strip_prefix("pattern=") {
+                        pattern = Some(regex);
+                    } else if let Some(group) = arg.strip_prefix("pattern_group=") {
+                        pattern_group = Some(group);
+                    }
                 }
-                _ => {}
             }
         }
+        tokenizer
+            .protected
+            .build(tokenizer.normalizer)
+            .map_err(to_rusqlite_error)?;
+        if let Some(pattern) = pattern {
+            tokenizer.pattern =
+                Some(Pattern::new(pattern, pattern_group).map_err(to_rusqlite_error)?);
+        }
+        // 过滤器使用全部参数解析完成后的停词表等配置
+        match filters {
+            Some(filters) => {
+                if let Some(arg) = pipeline_arg {
+                    return Err(to_rusqlite_error(conflicting_filters_arg(arg)));
+                }
+                let filters = FilterPipeline::parse(filters, &tokenizer.filter_options())
+                    .map_err(to_rusqlite_error)?;
+                tokenizer.set_filters(filters);
+            }
+            None => tokenizer.build_filters(),
+        }
+        if let Some(table) = synonyms_table {
+            // 同义词按照这个分词器的配置切分成 token 序列
+            let synonyms = tokenizer.stages.load_synonyms(table, |text, push_token| {
+                tokenizer.tokenize_words(&TokenizeReason::Document, text, None, push_token)
+            })?;
+            tokenizer.set_synonyms(synonyms);
+        }
         Ok(tokenizer)
     }
+}
+
+impl Tokenizer for SimpleTokenizer {
+    type Global = ();
+
+    fn name() -> &'static CStr {
+        c"simple"
+    }
+
+    /// 没有数据库连接，不能使用从表中读取数据的参数
+    fn new(_global: &Self::Global, args: Vec<String>) -> Result<Self, Error> {
+        Self::with_db(None, args)
+    }
+
+    fn new_with_connection(
+        _global: &Self::Global,
+        connection: &Connection,
+        args: Vec<String>,
+    ) -> Result<Self, Error> {
+        Self::with_db(Some(DbHandle::new(connection)), args)
+    }
 
     fn tokenize<TKF>(
         &mut self,
-        _reason: TokenizeReason,
+        reason: TokenizeReason,
         text: &[u8],
-        mut push_token: TKF,
+        push_token: TKF,
     ) -> Result<(), Error>
     where
         TKF: FnMut(&[u8], Range<usize>, bool) -> Result<(), Error>,
     {
-        let text = String::from_utf8_lossy(text);
-        // 使用 unicode_word_indices 进行分词，所有中文字符应该是单独一个字符成 word
-        let mut word_buf = String::new();
-        for (index, word) in text.unicode_word_indices() {
-            let range = index..index + word.len();
-            // 开启 pinyin 并且这个是中文字符
-            if self.enable_pinyin && need_pinyin(word) {
-                if self.enable_stopword && STOPWORD.contains(word) {
-                    // 不处理停词
-                    continue;
-                }
-                if let Some(ch) = word.chars().next()
-                    && let Some(pinyin_vec) = get_pinyin(&ch)
-                {
-                    for pinyin in pinyin_vec {
-                        (push_token)(pinyin.as_bytes(), range.clone(), false)?;
-                    }
-                }
-            } else {
-                // 不需要使用 pinyin 模块进行处理
-                // 对单词做归一化处理，并且将单词转换成小写
-                let need_stem = make_lowercase(word, &mut word_buf);
-                if self.enable_stopword && STOPWORD.contains(word_buf.as_str()) {
-                    // 不处理停词
-                    continue;
-                }
-                if need_stem {
-                    let stemmed = EN_STEMMER.stem(word_buf.as_str()).into_owned();
-                    (push_token)(stemmed.as_bytes(), range, false)?;
-                } else {
-                    (push_token)(word_buf.as_bytes(), range, false)?;
-                }
-            }
-        }
-        Ok(())
+        self.tokenize_with_locale(reason, text, None, push_token)
+    }
+
+    /// 可以识别的 locale 决定词干提取使用的语言，否则使用 `stemmer=` 参数指定的语言；
+    /// 折叠易混淆字符、展开同义词和限制 token 由 [`TokenStages`] 完成
+    fn tokenize_with_locale<TKF>(
+        &mut self,
+        reason: TokenizeReason,
+        text: &[u8],
+        locale: Option<&str>,
+        push_token: TKF,
+    ) -> Result<(), Error>
+    where
+        TKF: FnMut(&[u8], Range<usize>, bool) -> Result<(), Error>,
+    {
+        self.stages.run(
+            &reason,
+            text,
+            |text, push_token| self.tokenize_words(&reason, text, locale, push_token),
+            push_token,
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::SimpleTokenizer;
+    use crate::tokenizer::test_utils::{open, search, tokens};
+    use crate::tokenizer::{TokenizeReason, Tokenizer};
     use unicode_segmentation::UnicodeSegmentation;
 
+    #[test]
+    fn test_new_without_connection() {
+        let new = |args: &[&str]| {
+            SimpleTokenizer::new(&(), args.iter().map(|arg| arg.to_string()).collect())
+        };
+        let mut tokenizer = new(&["disable_pinyin", "stopwords+=fox"]).unwrap();
+        let mut words = Vec::new();
+        tokenizer
+            .tokenize(TokenizeReason::Document, b"The quick fox", |token, _, _| {
+                words.push(String::from_utf8_lossy(token).into_owned());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(vec!["quick"], words);
+        // 没有数据库连接时不能从表中读取数据
+        assert!(new(&["stopwords=table:stopwords"]).is_err());
+        assert!(new(&["protected=table:protected"]).is_err());
+    }
+
     #[test]
     fn test_tokenize_by_unicode_word_indices() {
         let text = "The quick (\"brown\") fox can't jump 32.3 feet, right? 我将点燃星海！天上的stars全部都是 eye，不要凝视";
@@ -195,4 +688,44 @@ mod tests {
         ];
         assert_eq!(&uwi1[..], b);
     }
+
+    #[test]
+    fn test_register_simple_tokenizer_with_pinyin_query() {
+        let conn = open();
+        // 创建一个测试表, 查询时切分拼音串，多音字的读音处于同一位置
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'simple enable_pinyin_query colocate_readings');
+             CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize = 'simple enable_pinyin_query');
+             INSERT INTO t1(text) VALUES ('中华人民共和国国歌'), ('静夜思'), ('国家'), ('中国'),
+                 ('举头望明月'), ('I''m making a sqlite tokenizer'), ('方案'), ('反感'), ('西安');
+             INSERT INTO t2(text) SELECT text FROM t1;",
+        )
+        .unwrap();
+        assert_eq!(vec!["中国"], search(&conn, "t1", "zhongguo"));
+        assert_eq!(vec!["中华人民共和国国歌"], search(&conn, "t1", "guoge"));
+        // 前缀查询时，最后一个音节可以不完整
+        assert_eq!(vec!["中国"], search(&conn, "t1", "zhongg*"));
+        assert_eq!(vec!["国家"], search(&conn, "t1", "guoj*"));
+        // 多音字的每个读音各是一个位置时，“国”的第二个读音 gu 隔开了“国”和“家”
+        assert!(search(&conn, "t2", "guoj*").is_empty());
+        assert_eq!(vec!["中国"], search(&conn, "t2", "zhongguo"));
+        // 只使用一种切分方式，不同切分方式的音节不会任意组合
+        assert_eq!(vec!["方案"], search(&conn, "t1", "fangan"));
+        assert_eq!(
+            vec![("fang".to_owned(), false), ("an".to_owned(), false)],
+            tokens(
+                &conn,
+                &["simple", "enable_pinyin_query"],
+                TokenizeReason::Query { prefix: false },
+                "fangan"
+            )
+        );
+        assert!(search(&conn, "t1", "xian").is_empty());
+        assert_eq!(vec!["西安"], search(&conn, "t1", "\"xi an\""));
+        // 不能切分成拼音的单词保持原有的处理
+        assert_eq!(
+            vec!["I'm making a sqlite tokenizer"],
+            search(&conn, "t1", "tokenizer")
+        );
+    }
 }
@@ -0,0 +1,124 @@
+//! `simple` 和 `jieba` 共用的分词流程
+//!
+//! 分词器只负责切分单词，单词经过过滤器流水线得到 token。作用于整个文本或者整个分词结果的处理在这里完成：
+//!
+//! * `fold_confusables`：分词前去掉不可见字符并折叠易混淆字符，token 的位置转换成原文中的位置
+//! * `synonyms=` 和 `index_synonyms`：展开同义词，见 [`Synonyms`]
+//! * `max_token_len=` 等参数：限制 token 的长度和个数，见 [`TokenLimits`]
+
+use crate::tokenizer::TokenizeReason;
+use crate::tokenizer::confusables::FoldedText;
+use crate::tokenizer::limits::{InterruptCheck, TokenLimits};
+use crate::tokenizer::synonyms::{Synonyms, split_term};
+use crate::tokenizer::utils::DbHandle;
+use rusqlite::Error;
+use std::ops::{ControlFlow, Range};
+use std::sync::Arc;
+
+/// 写入 token 的回调，参数是 token、token 在文本中的位置以及是否是 colocated token
+///
+/// 返回 [`ControlFlow::Break`] 时分词器停止分词，同样返回 `Break`，例如达到 `max_tokens` 之后
+pub(crate) type PushToken<'a> =
+    dyn FnMut(&[u8], Range<usize>, bool) -> Result<ControlFlow<()>, Error> + 'a;
+
+/// 分词器共用的分词流程
+#[derive(Clone, Default)]
+pub(crate) struct TokenStages {
+    /// 分词前去掉不可见字符并折叠易混淆字符
+    fold_confusables: bool,
+    /// 通过 `synonyms=` 加载的同义词表，默认不展开同义词
+    synonyms: Option<Arc<Synonyms>>,
+    /// 写入文档时是否展开同义词，默认只在查询时展开
+    index_synonyms: bool,
+    /// token 长度和个数的限制，默认没有限制
+    limits: TokenLimits,
+    /// 创建 tokenizer 的数据库连接，用于检查分词是否被中断
+    db: Option<DbHandle>,
+}
+
+impl TokenStages {
+    pub(crate) fn new(db: Option<DbHandle>) -> Self {
+        Self {
+            db,
+            ..Self::default()
+        }
+    }
+
+    /// 处理 `fold_confusables` 以及 [`TokenLimits`] 的参数，不是这些参数时返回 `false`
+    pub(crate) fn apply_arg(&mut self, arg: &str) -> Result<bool, crate::Error> {
+        if arg == "fold_confusables" {
+            self.fold_confusables = true;
+            return Ok(true);
+        }
+        self.limits.apply_arg(arg)
+    }
+
+    /// 分词器在切分单词的循环中使用，定期检查数据库连接是否被中断
+    pub(crate) fn interrupt_check(&self) -> InterruptCheck<'_> {
+        InterruptCheck::new(self.db.as_ref())
+    }
+
+    /// 设置同义词表
+    pub(crate) fn set_synonyms(&mut self, synonyms: Synonyms) {
+        self.synonyms = Some(Arc::new(synonyms));
+    }
+
+    /// 写入文档时也展开同义词
+    pub(crate) fn enable_index_synonyms(&mut self) {
+        self.index_synonyms = true;
+    }
+
+    /// 从表中加载同义词，同义词按照分词器的配置切分成 token 序列，`tokenize` 与 [`Self::run`] 中的相同
+    pub(crate) fn load_synonyms<F>(&self, table: &str, tokenize: F) -> Result<Synonyms, Error>
+    where
+        F: Fn(&str, &mut PushToken) -> Result<ControlFlow<()>, Error>,
+    {
+        let Some(db) = &self.db else {
+            return Ok(Synonyms::default());
+        };
+        let connection = db.connection()?;
+        Synonyms::load_table(&connection, table, |term| {
+            split_term(term, |text, push_token| {
+                self.run(&TokenizeReason::Document, text, &tokenize, push_token)
+            })
+        })
+    }
+
+    /// 使用分词器对 `text` 分词，`tokenize` 对折叠后的文本切分单词并经过过滤器流水线
+    pub(crate) fn run<F, TKF>(
+        &self,
+        reason: &TokenizeReason,
+        text: &[u8],
+        tokenize: F,
+        push_token: TKF,
+    ) -> Result<(), Error>
+    where
+        F: FnOnce(&str, &mut PushToken) -> Result<ControlFlow<()>, Error>,
+        TKF: FnMut(&[u8], Range<usize>, bool) -> Result<(), Error>,
+    {
+        let text = String::from_utf8_lossy(text);
+        // 对折叠后的文本分词，token 的位置转换成原文中的位置
+        let folded = self.fold_confusables.then(|| FoldedText::new(&text));
+        let text = folded.as_ref().map_or(text.as_ref(), FoldedText::as_str);
+        self.limits.limit(
+            self.db.as_ref(),
+            |push_token| {
+                let mut push_token =
+                    |token: &[u8], range: Range<usize>, colocated: bool| match &folded {
+                        Some(folded) => push_token(token, folded.original_range(range), colocated),
+                        None => push_token(token, range, colocated),
+                    };
+                match &self.synonyms {
+                    Some(synonyms) => synonyms.expand(
+                        reason,
+                        self.index_synonyms,
+                        |push_token| tokenize(text, push_token),
+                        push_token,
+                    ),
+                    None => tokenize(text, &mut push_token),
+                }
+            },
+            push_token,
+        )
+    }
+}
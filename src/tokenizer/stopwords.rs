@@ -0,0 +1,407 @@
+//! 停词表，包括按照语言划分的内置停词表，以及通过 `stopwords=` 参数从文件或者 SQLite 表中加载的停词表
+//!
+//! 同一个文件或者同一个数据库文件中的表只会加载一次，加载的停词在进程内的全部数据库连接之间共享，
+//! 修改文件或者表之后通过 [`reload_word_lists`] 清空已经加载的停词表
+
+use crate::pinyin::has_pinyin;
+use crate::tokenizer::utils::{DbHandle, make_lowercase};
+use crate::{STOPWORD_EN, STOPWORD_ZH};
+use rusqlite::Connection;
+use rusqlite::types::ValueRef;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, LazyLock, Mutex};
+
+/// 已经加载的停词表，键是停词表的来源
+static LOADED_STOPWORDS: LazyLock<Mutex<HashMap<String, Arc<HashSet<String>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// 内置停词表的语言
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopWordLang {
+    /// 英文停词表，包括数字和符号，只用于不包含汉字的单词
+    En,
+    /// 中文停词表，只用于包含汉字的单词
+    Zh,
+}
+
+impl StopWordLang {
+    /// 全部内置停词表的语言
+    pub const ALL: [StopWordLang; 2] = [StopWordLang::En, StopWordLang::Zh];
+
+    /// 解析 `stopword_lang=` 参数中以逗号分隔的语言代码
+    pub fn parse_list(langs: &str) -> Result<Vec<StopWordLang>, crate::Error> {
+        langs
+            .split(',')
+            .map(str::trim)
+            .map(|lang| match lang.to_ascii_lowercase().as_str() {
+                "en" => Ok(StopWordLang::En),
+                "zh" => Ok(StopWordLang::Zh),
+                _ => Err(crate::Error::TokenizerArgumentIncorrect(format!(
+                    "unknown stopword language: {lang}"
+                ))),
+            })
+            .collect()
+    }
+}
+
+/// 判断单词是否是指定语言的内置停词，包含汉字的单词只使用中文停词表，其他单词只使用英文停词表
+pub fn is_builtin_stopword(word: &str, langs: &[StopWordLang]) -> bool {
+    if word.chars().any(|ch| has_pinyin(&ch)) {
+        langs.contains(&StopWordLang::Zh) && STOPWORD_ZH.contains(word)
+    } else {
+        langs.contains(&StopWordLang::En) && STOPWORD_EN.contains(word)
+    }
+}
+
+/// 一个 tokenizer 使用的停词表
+///
+/// 没有通过 `stopwords=` 指定停词表时使用全部语言的内置停词表，`stopword_lang=` 选择使用的内置停词表，
+/// `stopwords+=` 和 `stopwords-=` 在此基础上加入或者去掉停词
+#[derive(Clone, Debug, Default)]
+pub struct StopWords {
+    /// 通过 `stopword_lang=` 选择的内置停词表语言，没有指定时等同于 `StopWordLang::ALL`
+    langs: Option<Vec<StopWordLang>>,
+    /// 通过 `stopwords=` 加载的停词表，为空时使用内置的停词表
+    lists: Vec<Arc<HashSet<String>>>,
+    /// 通过 `stopwords+=` 加入的停词
+    added: HashSet<String>,
+    /// 通过 `stopwords-=` 去掉的停词
+    removed: HashSet<String>,
+}
+
+impl StopWords {
+    /// 判断归一化之后的单词是否是停词
+    pub fn contains(&self, word: &str) -> bool {
+        if self.removed.contains(word) {
+            return false;
+        }
+        if self.added.contains(word) {
+            return true;
+        }
+        match &self.langs {
+            Some(langs) if is_builtin_stopword(word, langs) => return true,
+            // 指定了停词表而没有指定语言时，不使用内置的停词表
+            None if self.lists.is_empty() && is_builtin_stopword(word, &StopWordLang::ALL) => {
+                return true;
+            }
+            _ => {}
+        }
+        self.lists.iter().any(|list| list.contains(word))
+    }
+
+    /// 设置使用的内置停词表语言
+    pub fn set_langs(&mut self, langs: Vec<StopWordLang>) {
+        self.langs = Some(langs);
+    }
+
+    /// 加入停词
+    pub fn add<I, S>(&mut self, words: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for word in words {
+            let word = normalize(word.as_ref());
+            self.removed.remove(&word);
+            self.added.insert(word);
+        }
+    }
+
+    /// 去掉停词
+    pub fn remove<I, S>(&mut self, words: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for word in words {
+            let word = normalize(word.as_ref());
+            self.added.remove(&word);
+            self.removed.insert(word);
+        }
+    }
+
+    /// 处理 `stopword_lang=`、`stopwords=`、`stopwords+=` 和 `stopwords-=` 参数，不是停词参数时返回 `false`
+    ///
+    /// `stopword_lang=` 的值是以逗号分隔的内置停词表语言，
+    /// `stopwords=` 的值是 `file:` 开头的文件路径或者 `table:` 开头的表名，可以指定多次，
+    /// `stopwords+=` 和 `stopwords-=` 的值是以逗号分隔的停词
+    pub(crate) fn apply_arg(
+        &mut self,
+        arg: &str,
+        db: Option<&DbHandle>,
+    ) -> Result<bool, crate::Error> {
+        if let Some(langs) = arg.strip_prefix("stopword_lang=") {
+            self.set_langs(StopWordLang::parse_list(langs)?);
+        } else if let Some(words) = arg.strip_prefix("stopwords+=") {
+            self.add(split_words(words));
+        } else if let Some(words) = arg.strip_prefix("stopwords-=") {
+            self.remove(split_words(words));
+        } else if let Some(source) = arg.strip_prefix("stopwords=") {
+            let list = load_list("stopwords", source, db, crate::Error::LoadStopWordsFailure)?;
+            self.lists.push(list);
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+/// 停词按照写入文档时的方式做归一化，并转换成小写
+fn normalize(word: &str) -> String {
+    let mut buf = String::new();
+    make_lowercase(word.trim(), &mut buf);
+    buf
+}
+
+pub(super) fn split_words(words: &str) -> impl Iterator<Item = &str> {
+    words.split(',').filter(|word| !word.trim().is_empty())
+}
+
+/// 加载 `file:` 开头的文件路径或者 `table:` 开头的表名指定的词表，`name` 是参数名称，
+/// `error` 创建读取文件失败时的错误，没有数据库连接时不能使用 `table:`
+pub(super) fn load_list(
+    name: &str,
+    source: &str,
+    db: Option<&DbHandle>,
+    error: fn(String) -> crate::Error,
+) -> Result<Arc<HashSet<String>>, crate::Error> {
+    if let Some(path) = source.strip_prefix("file:") {
+        load_file(path, error)
+    } else if let Some(table) = source.strip_prefix("table:") {
+        let db = db.ok_or_else(|| {
+            crate::Error::TokenizerArgumentIncorrect(format!(
+                "{name}=table: requires a database connection"
+            ))
+        })?;
+        load_table(&db.connection()?, table)
+    } else {
+        Err(crate::Error::TokenizerArgumentIncorrect(format!(
+            "{name} must start with file: or table:, got {source}"
+        )))
+    }
+}
+
+/// 获取已经加载的停词表，不存在时使用 `load` 加载
+fn get_or_load<F>(key: Option<String>, load: F) -> Result<Arc<HashSet<String>>, crate::Error>
+where
+    F: FnOnce() -> Result<HashSet<String>, crate::Error>,
+{
+    let Some(key) = key else {
+        return load().map(Arc::new);
+    };
+    if let Some(list) = lock_loaded().get(&key) {
+        return Ok(list.clone());
+    }
+    // 读取文件和表时不持有锁
+    let list = Arc::new(load()?);
+    Ok(lock_loaded().entry(key).or_insert(list).clone())
+}
+
+/// 清空已经加载的停词表和保护词表，返回清空的词表个数
+///
+/// 之后创建的 tokenizer 重新读取文件和表，已经创建的 tokenizer 继续使用原来的词表
+pub(crate) fn reload_word_lists() -> usize {
+    let mut loaded = lock_loaded();
+    let count = loaded.len();
+    loaded.clear();
+    count
+}
+
+fn lock_loaded() -> std::sync::MutexGuard<'static, HashMap<String, Arc<HashSet<String>>>> {
+    LOADED_STOPWORDS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// 从文件中加载词表，每一行是一个词语，忽略空行和以 `#` 开头的行
+fn load_file(
+    path: &str,
+    error: fn(String) -> crate::Error,
+) -> Result<Arc<HashSet<String>>, crate::Error> {
+    get_or_load(Some(format!("file:{path}")), || {
+        let content =
+            std::fs::read_to_string(path).map_err(|err| error(format!("{path}: {err}")))?;
+        Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(normalize)
+            .collect())
+    })
+}
+
+/// 从 SQLite 表中加载词表，表的第一列是词语
+///
+/// 内存数据库和临时数据库中的表不会被缓存，每次创建 tokenizer 时重新读取
+fn load_table(connection: &Connection, table: &str) -> Result<Arc<HashSet<String>>, crate::Error> {
+    let key = connection
+        .path()
+        .filter(|path| !path.is_empty())
+        .map(|path| format!("table:{path}:{table}"));
+    get_or_load(key, || {
+        let sql = format!(
+            "SELECT * FROM \"{}\"",
+            rusqlite::vtab::escape_double_quote(table)
+        );
+        let mut stmt = connection.prepare(&sql)?;
+        let mut rows = stmt.query([])?;
+        let mut words = HashSet::new();
+        while let Some(row) = rows.next()? {
+            if let ValueRef::Text(word) = row.get_ref(0)? {
+                words.insert(normalize(str::from_utf8(word)?));
+            }
+        }
+        Ok(words)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenizer::TokenizeReason;
+    use crate::tokenizer::test_utils::{count, open, tokenize, tokens, words};
+
+    #[test]
+    fn test_register_tokenizer_with_stopwords() {
+        let conn = open();
+        let path = std::env::temp_dir().join(format!("stopwords-{}.txt", std::process::id()));
+        std::fs::write(&path, "# 自定义停词\nPear\n\n世界\n").unwrap();
+        conn.execute_batch(
+            "CREATE TABLE my_stopwords(word TEXT);
+             INSERT INTO my_stopwords(word) VALUES ('foo'), ('Bar');",
+        )
+        .unwrap();
+        let file_arg = format!("stopwords=file:{}", path.display());
+        for tokenizer in ["simple", "jieba"] {
+            // 默认使用内置的停词表
+            assert_eq!(
+                vec!["pear", "foo"],
+                words(&conn, &[tokenizer], "the pear foo")
+            );
+            assert_eq!(
+                vec!["the", "pear"],
+                words(&conn, &[tokenizer, "stopwords-=the,a"], "the pear")
+            );
+            assert_eq!(
+                vec!["foo"],
+                words(
+                    &conn,
+                    &[tokenizer, "stopwords+=Pear,bar"],
+                    "the pear foo bar"
+                )
+            );
+            // 指定停词表之后不再使用内置的停词表
+            assert_eq!(
+                vec!["the", "pear"],
+                words(
+                    &conn,
+                    &[tokenizer, "stopwords=table:my_stopwords"],
+                    "the pear foo bar"
+                )
+            );
+            assert_eq!(
+                vec!["the", "foo"],
+                words(&conn, &[tokenizer, &file_arg], "the pear foo")
+            );
+            assert_eq!(
+                vec!["the"],
+                words(
+                    &conn,
+                    &[tokenizer, &file_arg, "stopwords=table:my_stopwords"],
+                    "the pear foo bar"
+                )
+            );
+            for arg in ["stopwords=other:x", "stopwords=table:missing"] {
+                let args = [tokenizer, arg];
+                assert!(tokenize(&conn, &args, TokenizeReason::Document, "x").is_err());
+            }
+        }
+
+        conn.execute_batch(&format!(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'simple ''stopwords=file:{}''');
+             CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize = 'jieba ''stopwords+=foo,bar''');
+             INSERT INTO t1(text) VALUES ('pear 世界'), ('你好');
+             INSERT INTO t2(text) VALUES ('foo bar'), ('foo baz');",
+            path.display()
+        ))
+        .unwrap();
+        assert_eq!(0, count(&conn, "t1", "pear"));
+        assert_eq!(1, count(&conn, "t1", "你好"));
+        assert_eq!(1, count(&conn, "t2", "baz"));
+        assert_eq!(0, count(&conn, "t2", "bar"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_register_tokenizer_with_stopword_lang() {
+        let conn = open();
+        let tokens = |args: &[&str]| -> Vec<String> {
+            tokens(&conn, args, TokenizeReason::Document, "他要上山 the pear 1")
+                .into_iter()
+                .map(|(token, _)| token)
+                .collect()
+        };
+        // 默认使用全部语言的内置停词表
+        assert_eq!(vec!["shan", "pear"], tokens(&["simple"]));
+        assert_eq!(
+            vec!["shan", "pear"],
+            tokens(&["simple", "stopword_lang=en,zh"])
+        );
+        assert_eq!(
+            vec!["ta", "tuo", "yao", "shang", "shan", "pear"],
+            tokens(&["simple", "stopword_lang=en"])
+        );
+        assert_eq!(
+            vec!["shan", "the", "pear", "1"],
+            tokens(&["simple", "stopword_lang=zh"])
+        );
+        assert_eq!(vec!["上山", "pear"], tokens(&["jieba"]));
+        assert_eq!(
+            vec!["他", "要", "上山", "pear"],
+            tokens(&["jieba", "stopword_lang=en"])
+        );
+        assert_eq!(
+            vec!["上山", "the", "pear", "1"],
+            tokens(&["jieba", "stopword_lang=zh"])
+        );
+        // 指定语言后，内置停词表与 stopwords+= 一起使用
+        assert_eq!(
+            vec!["他", "上山", "the", "1"],
+            tokens(&[
+                "jieba",
+                "stopword_lang=zh",
+                "stopwords+=pear",
+                "stopwords-=他"
+            ])
+        );
+        let args = ["jieba", "stopword_lang=fr"];
+        assert!(tokenize(&conn, &args, TokenizeReason::Document, "x").is_err());
+
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'jieba ''stopword_lang=en''');
+             INSERT INTO t1(text) VALUES ('我们要上山'), ('the pear');",
+        )
+        .unwrap();
+        assert_eq!(1, count(&conn, "t1", "我们"));
+        assert_eq!(0, count(&conn, "t1", "the"));
+    }
+    #[test]
+    fn test_stopwords_reload() {
+        let conn = open();
+        let path =
+            std::env::temp_dir().join(format!("stopwords-reload-{}.txt", std::process::id()));
+        std::fs::write(&path, "pear\n").unwrap();
+        let file_arg = format!("stopwords=file:{}", path.display());
+        let args = ["simple", file_arg.as_str()];
+        assert_eq!(vec!["plum"], words(&conn, &args, "pear plum"));
+
+        // 停词表文件只读取一次，修改文件之后需要清空已经加载的停词表
+        std::fs::write(&path, "plum\n").unwrap();
+        assert_eq!(vec!["plum"], words(&conn, &args, "pear plum"));
+        let cleared = conn
+            .query_row("SELECT stopwords_reload()", [], |row| row.get::<_, i64>(0))
+            .unwrap();
+        assert!(cleared >= 1);
+        assert_eq!(vec!["pear"], words(&conn, &args, "pear plum"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+//! 同义词过滤器，通过 `synonyms=` 参数从 SQLite 表中加载同义词
+//!
+//! 同义词表的前两列是互为同义词的两个词语，每个词语使用表的分词器切分成 token 序列，
+//! 分词结果中与词语的 token 序列一致的位置，其同义词作为 colocated token 写入。
+//! 由多个 token 组成的词语（例如 jieba 切分出的“笔记本 电脑”）以空格连接成一个 token，
+//! 写入文档时总是作为 colocated token 写入，使得查询时展开的多词同义词可以匹配。
+//!
+//! FTS5 查询中的一个位置只能表示长度相同的多个选择，因此只有查询词语恰好是一个多 token 词语时才展开它的同义词；
+//! 多 token 词语出现在更长的查询词语中间时（例如“苹果手机很贵”），只匹配原来的词语
+
+use crate::tokenizer::TokenizeReason;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, Error};
+use std::collections::{HashMap, VecDeque};
+use std::ops::{ControlFlow, Range};
+
+/// 多个 token 组成的词语中 token 之间的分隔符
+const TOKEN_SEPARATOR: u8 = b' ';
+
+/// 分词结果中的一个 token
+struct Token {
+    token: Vec<u8>,
+    range: Range<usize>,
+    colocated: bool,
+}
+
+/// 分词器使用的同义词表
+#[derive(Debug, Default)]
+pub struct Synonyms {
+    /// 全部词语的 token 序列
+    terms: Vec<Vec<Vec<u8>>>,
+    /// 每个词语的同义词在 `terms` 中的下标
+    synonyms: Vec<Vec<usize>>,
+    /// 以第一个 token 索引词语
+    by_first: HashMap<Vec<u8>, Vec<usize>>,
+    /// 最长的词语包含的 token 个数，展开同义词时最多缓存这么多个位置
+    max_len: usize,
+}
+
+impl Synonyms {
+    /// 加入一组同义词，`split` 将词语切分成 token 序列，切分结果为空的词语被忽略
+    pub fn add<F>(&mut self, word: &str, synonym: &str, mut split: F) -> Result<(), Error>
+    where
+        F: FnMut(&str) -> Result<Vec<Vec<u8>>, Error>,
+    {
+        let word = split(word)?;
+        let synonym = split(synonym)?;
+        if word.is_empty() || synonym.is_empty() || word == synonym {
+            return Ok(());
+        }
+        let word = self.term_index(word);
+        let synonym = self.term_index(synonym);
+        // 同义词是双向的
+        for (from, to) in [(word, synonym), (synonym, word)] {
+            if !self.synonyms[from].contains(&to) {
+                self.synonyms[from].push(to);
+            }
+        }
+        Ok(())
+    }
+
+    /// 从 SQLite 表中加载同义词，表的前两列是互为同义词的两个词语
+    pub fn load_table<F>(connection: &Connection, table: &str, mut split: F) -> Result<Self, Error>
+    where
+        F: FnMut(&str) -> Result<Vec<Vec<u8>>, Error>,
+    {
+        let sql = format!(
+            "SELECT * FROM \"{}\"",
+            rusqlite::vtab::escape_double_quote(table)
+        );
+        let mut stmt = connection.prepare(&sql)?;
+        let mut rows = stmt.query([])?;
+        let mut synonyms = Self::default();
+        while let Some(row) = rows.next()? {
+            if let (ValueRef::Text(word), ValueRef::Text(synonym)) =
+                (row.get_ref(0)?, row.get_ref(1)?)
+            {
+                synonyms.add(
+                    &String::from_utf8_lossy(word),
+                    &String::from_utf8_lossy(synonym),
+                    &mut split,
+                )?;
+            }
+        }
+        Ok(synonyms)
+    }
+
+    fn term_index(&mut self, term: Vec<Vec<u8>>) -> usize {
+        if let Some(index) = self.by_first.get(&term[0]).and_then(|indexes| {
+            indexes
+                .iter()
+                .copied()
+                .find(|&index| self.terms[index] == term)
+        }) {
+            return index;
+        }
+        let index = self.terms.len();
+        self.by_first
+            .entry(term[0].clone())
+            .or_default()
+            .push(index);
+        self.max_len = self.max_len.max(term.len());
+        self.terms.push(term);
+        self.synonyms.push(Vec::new());
+        index
+    }
+
+    /// 对分词结果展开同义词，`tokenize` 使用分词器进行分词
+    ///
+    /// 查询时总是展开同义词，写入文档时只在 `index_synonyms` 为 `true` 时展开；前缀查询不展开同义词。
+    /// 分词结果边分词边写入，最多缓存最长的词语包含的位置个数，`push_token` 返回错误或者
+    /// [`ControlFlow::Break`] 时立即停止分词，不再写入缓存的位置
+    pub fn expand<F, TKF>(
+        &self,
+        reason: &TokenizeReason,
+        index_synonyms: bool,
+        tokenize: F,
+        mut push_token: TKF,
+    ) -> Result<ControlFlow<()>, Error>
+    where
+        F: FnOnce(
+            &mut dyn FnMut(&[u8], Range<usize>, bool) -> Result<ControlFlow<()>, Error>,
+        ) -> Result<ControlFlow<()>, Error>,
+        TKF: FnMut(&[u8], Range<usize>, bool) -> Result<ControlFlow<()>, Error>,
+    {
+        let emit_synonyms = match reason {
+            TokenizeReason::Query { prefix: true } => {
+                return tokenize(&mut push_token);
+            }
+            TokenizeReason::Query { prefix: false } => true,
+            _ => index_synonyms,
+        };
+        // 每个位置由一个 token 和紧随其后的 colocated token 组成，最后一个位置可能还没有结束
+        let mut groups: VecDeque<Vec<Token>> = VecDeque::new();
+        let mut flushed = false;
+        let flow = tokenize(&mut |token, range, colocated| {
+            if !colocated || groups.is_empty() {
+                // 已经缓存的位置都已经结束，超过最长的词语后写入第一个位置，这时查询文本不可能恰好是一个词语
+                while groups.len() > self.max_len {
+                    let flow =
+                        self.push_group(groups.make_contiguous(), emit_synonyms, &mut push_token)?;
+                    if flow.is_break() {
+                        return Ok(flow);
+                    }
+                    groups.pop_front();
+                    flushed = true;
+                }
+                groups.push_back(Vec::new());
+            }
+            if let Some(group) = groups.back_mut() {
+                group.push(Token {
+                    token: token.to_vec(),
+                    range,
+                    colocated,
+                });
+            }
+            Ok(ControlFlow::Continue(()))
+        })?;
+        if flow.is_break() {
+            return Ok(flow);
+        }
+        let groups = groups.make_contiguous();
+        if let TokenizeReason::Query { .. } = reason
+            && !flushed
+            && let Some(index) = self.whole_term(groups)
+        {
+            // 查询文本恰好是一个多 token 词语时，以连接后的 token 查询，同义词与之处于同一位置
+            let range = groups[0][0].range.start..groups[groups.len() - 1][0].range.end;
+            if (push_token)(&join_tokens(&self.terms[index]), range.clone(), false)?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+            for &synonym in &self.synonyms[index] {
+                let flow = (push_token)(&join_tokens(&self.terms[synonym]), range.clone(), true)?;
+                if flow.is_break() {
+                    return Ok(flow);
+                }
+            }
+            return Ok(ControlFlow::Continue(()));
+        }
+        for i in 0..groups.len() {
+            let flow = self.push_group(&groups[i..], emit_synonyms, &mut push_token)?;
+            if flow.is_break() {
+                return Ok(flow);
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// 写入第一个位置的 token，以及从这个位置开始的词语和同义词，`groups` 是从这个位置开始的分词结果
+    fn push_group<TKF>(
+        &self,
+        groups: &[Vec<Token>],
+        emit_synonyms: bool,
+        push_token: &mut TKF,
+    ) -> Result<ControlFlow<()>, Error>
+    where
+        TKF: FnMut(&[u8], Range<usize>, bool) -> Result<ControlFlow<()>, Error>,
+    {
+        let group = &groups[0];
+        for token in group.iter() {
+            let flow = (push_token)(&token.token, token.range.clone(), token.colocated)?;
+            if flow.is_break() {
+                return Ok(flow);
+            }
+        }
+        let mut extra: Vec<(Vec<u8>, Range<usize>)> = Vec::new();
+        for token in group.iter() {
+            let Some(indexes) = self.by_first.get(strip_tokendata(&token.token)) else {
+                continue;
+            };
+            for &index in indexes {
+                let term = &self.terms[index];
+                if !self.matches(term, groups) {
+                    continue;
+                }
+                let range = group[0].range.start..groups[term.len() - 1][0].range.end;
+                if term.len() > 1 {
+                    extra.push((join_tokens(term), range.clone()));
+                }
+                if emit_synonyms {
+                    for &synonym in &self.synonyms[index] {
+                        extra.push((join_tokens(&self.terms[synonym]), range.clone()));
+                    }
+                }
+            }
+        }
+        for (index, (token, range)) in extra.iter().enumerate() {
+            let duplicated = group.iter().any(|existing| existing.token == *token)
+                || extra[..index].iter().any(|(existing, _)| existing == token);
+            if !duplicated && (push_token)(token, range.clone(), true)?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// 查找与全部位置一致的多 token 词语
+    fn whole_term(&self, groups: &[Vec<Token>]) -> Option<usize> {
+        if groups.len() < 2 {
+            return None;
+        }
+        groups[0]
+            .iter()
+            .filter_map(|token| self.by_first.get(strip_tokendata(&token.token)))
+            .flatten()
+            .copied()
+            .find(|&index| {
+                self.terms[index].len() == groups.len() && self.matches(&self.terms[index], groups)
+            })
+    }
+
+    /// 从第一个位置开始的每个位置都包含词语中对应的 token
+    fn matches(&self, term: &[Vec<u8>], groups: &[Vec<Token>]) -> bool {
+        term.len() <= groups.len()
+            && term.iter().zip(groups).all(|(expected, group)| {
+                group
+                    .iter()
+                    .any(|token| strip_tokendata(&token.token) == expected.as_slice())
+            })
+    }
+}
+
+/// 去掉 token 中以 `\0` 开头的 tokendata
+fn strip_tokendata(token: &[u8]) -> &[u8] {
+    token.split(|&byte| byte == 0).next().unwrap_or(token)
+}
+
+/// 将 token 序列以空格连接成一个 token
+fn join_tokens(term: &[Vec<u8>]) -> Vec<u8> {
+    term.join(&TOKEN_SEPARATOR)
+}
+
+/// 使用分词器将词语切分成 token 序列，只保留每个位置的第一个 token
+pub fn split_term<F>(term: &str, tokenize: F) -> Result<Vec<Vec<u8>>, Error>
+where
+    F: FnOnce(
+        &[u8],
+        &mut dyn FnMut(&[u8], Range<usize>, bool) -> Result<(), Error>,
+    ) -> Result<(), Error>,
+{
+    let mut tokens = Vec::new();
+    tokenize(term.as_bytes(), &mut |token, _, colocated| {
+        if !colocated {
+            tokens.push(strip_tokendata(token).to_vec());
+        }
+        Ok(())
+    })?;
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Synonyms;
+    use crate::tokenizer::TokenizeReason;
+    use crate::tokenizer::test_utils::{open, search, token, tokenize, tokens};
+    use std::ops::ControlFlow;
+
+    #[test]
+    fn test_expand_streaming() {
+        let mut synonyms = Synonyms::default();
+        let split = |term: &str| {
+            Ok(term
+                .split(' ')
+                .map(|token| token.as_bytes().to_vec())
+                .collect())
+        };
+        synonyms.add("rust lang", "rustlang", split).unwrap();
+        let text = "x x rust lang x ".repeat(1000);
+        let mut tokenized = 0;
+        let mut pushed = Vec::new();
+        let result = synonyms.expand(
+            &TokenizeReason::Query { prefix: false },
+            false,
+            |push_token| {
+                for (index, word) in text.split_whitespace().enumerate() {
+                    tokenized += 1;
+                    if push_token(word.as_bytes(), index..index + 1, false)?.is_break() {
+                        return Ok(ControlFlow::Break(()));
+                    }
+                }
+                Ok(ControlFlow::Continue(()))
+            },
+            |token: &[u8], _, colocated| {
+                if pushed.len() == 8 {
+                    return Ok(ControlFlow::Break(()));
+                }
+                pushed.push((String::from_utf8_lossy(token).into_owned(), colocated));
+                Ok(ControlFlow::Continue(()))
+            },
+        );
+        assert_eq!(ControlFlow::Break(()), result.unwrap());
+        // 边分词边展开，停止写入后不再继续分词
+        assert!(tokenized < 20);
+        assert_eq!(
+            vec![
+                token("x", false),
+                token("x", false),
+                token("rust", false),
+                token("rust lang", true),
+                token("rustlang", true),
+                token("lang", false),
+                token("x", false),
+                token("x", false),
+            ],
+            pushed
+        );
+    }
+
+    #[test]
+    fn test_register_tokenizer_with_synonyms() {
+        let conn = open();
+        conn.execute_batch(
+            "CREATE TABLE synonyms(word TEXT, synonym TEXT);
+             INSERT INTO synonyms VALUES ('电脑', '计算机'), ('laptop', 'notebook'),
+                 ('laptop', '手提电脑'), ('苹果手机', 'iPhone');",
+        )
+        .unwrap();
+        let query = TokenizeReason::Query { prefix: false };
+        // 查询时同义词作为 colocated token 写入，同义词按照表的分词器切分，laptops 的词干与 laptop 一致
+        assert_eq!(
+            vec![
+                token("laptop", false),
+                token("notebook", true),
+                token("手提电脑", true),
+            ],
+            tokens(
+                &conn,
+                &["jieba", "synonyms=synonyms"],
+                query.clone(),
+                "laptops"
+            )
+        );
+        // jieba 将苹果手机切分成两个单词，查询文本恰好是这个词语时以连接后的 token 查询
+        assert_eq!(
+            vec![token("苹果 手机", false), token("iphon", true)],
+            tokens(
+                &conn,
+                &["jieba", "synonyms=synonyms"],
+                query.clone(),
+                "苹果手机"
+            )
+        );
+        // 写入文档时总是写入多 token 词语，index_synonyms 同时写入同义词
+        assert_eq!(
+            vec![
+                token("苹果", false),
+                token("苹果 手机", true),
+                token("手机", false),
+            ],
+            tokens(
+                &conn,
+                &["jieba", "synonyms=synonyms"],
+                TokenizeReason::Document,
+                "苹果手机"
+            )
+        );
+        assert_eq!(
+            vec![
+                token("苹果", false),
+                token("苹果 手机", true),
+                token("iphon", true),
+                token("手机", false),
+            ],
+            tokens(
+                &conn,
+                &["jieba", "synonyms=synonyms", "index_synonyms"],
+                TokenizeReason::Document,
+                "苹果手机"
+            )
+        );
+        // 前缀查询不展开同义词
+        assert_eq!(
+            vec![token("laptop", false)],
+            tokens(
+                &conn,
+                &["simple", "synonyms=synonyms"],
+                TokenizeReason::Query { prefix: true },
+                "laptop"
+            )
+        );
+        let args = ["simple", "synonyms=missing"];
+        assert!(tokenize(&conn, &args, query, "x").is_err());
+
+        for tokenizer in ["simple", "jieba"] {
+            conn.execute_batch(&format!(
+                "DROP TABLE IF EXISTS t1;
+                 CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = '{tokenizer} ''synonyms=synonyms''');
+                 INSERT INTO t1(text) VALUES ('我的电脑'), ('一台计算机'), ('new laptops'), ('notebook'),
+                     ('手提电脑'), ('苹果手机很贵'), ('iPhone'), ('iPhone 很贵');"
+            ))
+            .unwrap();
+            let query = |query: &str| search(&conn, "t1", query);
+            // simple 按照单个汉字分词，手提电脑也包含电脑
+            let computers = if tokenizer == "simple" {
+                vec!["我的电脑", "一台计算机", "手提电脑"]
+            } else {
+                vec!["我的电脑", "一台计算机"]
+            };
+            assert_eq!(computers, query("电脑"));
+            assert_eq!(computers, query("计算机"));
+            assert_eq!(vec!["new laptops", "notebook", "手提电脑"], query("laptop"));
+            assert_eq!(vec!["new laptops", "手提电脑"], query("手提电脑"));
+            let phones = vec!["苹果手机很贵", "iPhone", "iPhone 很贵"];
+            assert_eq!(phones, query("iphone"));
+            assert_eq!(phones, query("苹果手机"));
+            // 多 token 词语只有恰好是一个查询词语时才展开同义词，在更长的词语中间时不展开，
+            // 因为 FTS5 的一个位置无法表示长度不同的多个选择；把这个词语单独作为一个查询词语即可匹配同义词
+            assert_eq!(vec!["苹果手机很贵"], query("苹果手机很贵"));
+            assert_eq!(vec!["苹果手机很贵", "iPhone 很贵"], query("苹果手机 很贵"));
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! 与 FTS5 内置 unicode61 分词器兼容的参数
+//!
+//! * `remove_diacritics 0|1|2`、`case_sensitive` 和 `normalization=nfc|nfkc|none` 决定单词的归一化方式，
+//!   `simple` 和 `jieba` 都支持
+//! * `tokenchars`、`separators` 和 `categories` 决定哪些字符组成单词，只有 `simple` 支持，
+//!   指定后 `simple` 按照 unicode61 的方式切分单词，代替 Unicode 标准的单词边界
+//!
+//! 与 unicode61 一样，这些参数的值是下一个参数，例如 `simple remove_diacritics 2 tokenchars '-_'`
+
+use crate::pinyin::has_pinyin;
+use crate::tokenizer::limits::InterruptCheck;
+use std::collections::HashSet;
+use unicode_general_category::get_general_category;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// 归一化方式
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Normalization {
+    /// 按照 NFC 归一化
+    Nfc,
+    /// 按照 NFKC 归一化，全角字符转换成半角字符
+    #[default]
+    Nfkc,
+    /// 不做归一化
+    None,
+}
+
+impl Normalization {
+    /// 通过名称获取归一化方式，可以是 `nfc`、`nfkc` 和 `none`，不区分大小写
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "nfc" => Some(Self::Nfc),
+            "nfkc" => Some(Self::Nfkc),
+            "none" => Some(Self::None),
+            _ => None,
+        }
+    }
+}
+
+/// 如何去掉字母上的附加符号
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RemoveDiacritics {
+    /// 没有指定 `remove_diacritics` 时，只去掉归一化后剩余的 U+0300 到 U+036F 的组合用附加符号，与之前的版本一致
+    #[default]
+    Legacy,
+    /// `remove_diacritics 0`：保留附加符号
+    Keep,
+    /// `remove_diacritics 1`：与 unicode61 一样，去掉只带有一个附加符号的字母上的附加符号
+    Single,
+    /// `remove_diacritics 2`：去掉全部附加符号，并将 ø、ł、đ、ß 等没有分解形式的字母转换成基本字母
+    All,
+}
+
+impl RemoveDiacritics {
+    /// 解析 `remove_diacritics` 的值
+    pub fn from_value(value: &str) -> Option<Self> {
+        match value {
+            "0" => Some(Self::Keep),
+            "1" => Some(Self::Single),
+            "2" => Some(Self::All),
+            _ => None,
+        }
+    }
+}
+
+/// 没有分解形式的字母与基本字母的映射，用于 `remove_diacritics 2`
+const FOLDED_LETTERS: &[(char, &str)] = &[
+    ('ø', "o"),
+    ('Ø', "O"),
+    ('ł', "l"),
+    ('Ł', "L"),
+    ('đ', "d"),
+    ('Đ', "D"),
+    ('ħ', "h"),
+    ('Ħ', "H"),
+    ('ŧ', "t"),
+    ('Ŧ', "T"),
+    ('ß', "ss"),
+    ('ẞ', "SS"),
+];
+
+/// 单词的归一化方式
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Normalizer {
+    pub normalization: Normalization,
+    pub remove_diacritics: RemoveDiacritics,
+    /// 是否区分大小写，默认转换成小写
+    pub case_sensitive: bool,
+}
+
+impl Normalizer {
+    /// 处理 `remove_diacritics`、`case_sensitive` 和 `normalization=` 参数，不是这些参数时返回 `false`
+    ///
+    /// `remove_diacritics` 的值从 `args` 中读取
+    pub(crate) fn apply_arg<'a, I>(&mut self, arg: &str, args: &mut I) -> Result<bool, crate::Error>
+    where
+        I: Iterator<Item = &'a String>,
+    {
+        if arg == "remove_diacritics" {
+            let value = next_value(arg, args)?;
+            self.remove_diacritics = RemoveDiacritics::from_value(value).ok_or_else(|| {
+                crate::Error::TokenizerArgumentIncorrect(format!(
+                    "remove_diacritics must be 0, 1 or 2, got {value}"
+                ))
+            })?;
+        } else if arg == "case_sensitive" {
+            self.case_sensitive = true;
+        } else if let Some(name) = arg.strip_prefix("normalization=") {
+            self.normalization = Normalization::from_name(name).ok_or_else(|| {
+                crate::Error::TokenizerArgumentIncorrect(format!(
+                    "normalization must be nfc, nfkc or none, got {name}"
+                ))
+            })?;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// 对单词做归一化，结果写入 `buf`
+    pub fn normalize(&self, word: &str, buf: &mut String) {
+        buf.clear();
+        match self.normalization {
+            Normalization::Nfc => self.push_chars(word.nfc(), buf),
+            Normalization::Nfkc => self.push_chars(word.nfkc(), buf),
+            Normalization::None => self.push_chars(word.chars(), buf),
+        }
+    }
+
+    fn push_chars<I>(&self, chars: I, buf: &mut String)
+    where
+        I: Iterator<Item = char>,
+    {
+        for ch in chars {
+            match self.remove_diacritics {
+                RemoveDiacritics::Legacy if is_legacy_diacritic(ch) => {}
+                RemoveDiacritics::Legacy | RemoveDiacritics::Keep => self.push_char(ch, buf),
+                RemoveDiacritics::Single => {
+                    if is_combining_mark(ch) {
+                        continue;
+                    }
+                    let mut decomposed = std::iter::once(ch).nfd();
+                    let base = decomposed.next().unwrap_or(ch);
+                    let marks = decomposed.filter(|ch| is_combining_mark(*ch)).count();
+                    self.push_char(if marks == 1 { base } else { ch }, buf);
+                }
+                RemoveDiacritics::All => {
+                    for ch in std::iter::once(ch).nfd() {
+                        if is_combining_mark(ch) {
+                            continue;
+                        }
+                        match FOLDED_LETTERS.iter().find(|(letter, _)| *letter == ch) {
+                            Some((_, folded)) => {
+                                folded.chars().for_each(|ch| self.push_char(ch, buf))
+                            }
+                            None => self.push_char(ch, buf),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn push_char(&self, ch: char, buf: &mut String) {
+        if self.case_sensitive {
+            buf.push(ch);
+        } else if ch.is_ascii() {
+            buf.push(ch.to_ascii_lowercase());
+        } else {
+            buf.extend(ch.to_lowercase());
+        }
+    }
+}
+
+/// 之前的版本在归一化后去掉的组合用附加符号
+pub(super) fn is_legacy_diacritic(ch: char) -> bool {
+    ('\u{0300}'..='\u{036f}').contains(&ch)
+}
+
+/// 读取参数的值
+fn next_value<'a, I>(name: &str, args: &mut I) -> Result<&'a str, crate::Error>
+where
+    I: Iterator<Item = &'a String>,
+{
+    args.next()
+        .map(String::as_str)
+        .ok_or_else(|| crate::Error::TokenizerArgumentIncorrect(format!("{name} requires a value")))
+}
+
+/// 按照 unicode61 的方式判断字符是否属于单词
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenChars {
+    /// 属于单词的 Unicode 类别，`L*` 表示所有以 `L` 开头的类别
+    categories: Vec<String>,
+    /// 额外属于单词的字符
+    tokenchars: HashSet<char>,
+    /// 不属于单词的字符
+    separators: HashSet<char>,
+}
+
+impl Default for TokenChars {
+    /// 与 unicode61 一样，默认由字母、数字和私用字符组成单词
+    fn default() -> Self {
+        Self {
+            categories: vec!["L*".to_owned(), "N*".to_owned(), "Co".to_owned()],
+            tokenchars: HashSet::new(),
+            separators: HashSet::new(),
+        }
+    }
+}
+
+impl TokenChars {
+    /// 处理 `tokenchars`、`separators` 和 `categories` 参数，不是这些参数时返回 `false`
+    ///
+    /// 参数的值从 `args` 中读取，`categories` 的值是以空格分隔的类别
+    pub(crate) fn apply_arg<'a, I>(
+        token_chars: &mut Option<Self>,
+        arg: &str,
+        args: &mut I,
+    ) -> Result<bool, crate::Error>
+    where
+        I: Iterator<Item = &'a String>,
+    {
+        if !matches!(arg, "tokenchars" | "separators" | "categories") {
+            return Ok(false);
+        }
+        let value = next_value(arg, args)?;
+        let token_chars = token_chars.get_or_insert_with(Self::default);
+        match arg {
+            "tokenchars" => token_chars.tokenchars.extend(value.chars()),
+            "separators" => token_chars.separators.extend(value.chars()),
+            _ => {
+                token_chars.categories = value.split_whitespace().map(str::to_owned).collect();
+                if let Some(category) = token_chars.categories.iter().find(|category| {
+                    category.len() != 2
+                        || !category
+                            .chars()
+                            .all(|ch| ch.is_ascii_alphabetic() || ch == '*')
+                }) {
+                    return Err(crate::Error::TokenizerArgumentIncorrect(format!(
+                        "invalid unicode category: {category}"
+                    )));
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// 判断字符是否属于单词
+    pub fn is_token_char(&self, ch: char) -> bool {
+        if self.separators.contains(&ch) {
+            return false;
+        }
+        if self.tokenchars.contains(&ch) {
+            return true;
+        }
+        let abbreviation = get_general_category(ch).abbreviation();
+        self.categories
+            .iter()
+            .any(|category| match category.strip_suffix('*') {
+                Some(major) => abbreviation.starts_with(major),
+                None => abbreviation == category,
+            })
+    }
+
+    /// 切分单词，返回单词的字节偏移量和单词，有拼音的汉字总是单独作为一个单词
+    ///
+    /// 数据库连接被中断时返回 `SQLITE_INTERRUPT`
+    pub(crate) fn split<'a>(
+        &self,
+        text: &'a str,
+        interrupt: &mut InterruptCheck,
+    ) -> Result<Vec<(usize, &'a str)>, rusqlite::Error> {
+        let mut words = Vec::new();
+        let mut start = None;
+        for (index, ch) in text.char_indices() {
+            interrupt.check()?;
+            let is_token = self.is_token_char(ch);
+            if let Some(begin) = start
+                && (!is_token || has_pinyin(&ch))
+            {
+                words.push((begin, &text[begin..index]));
+                start = None;
+            }
+            if is_token {
+                if has_pinyin(&ch) {
+                    words.push((index, &text[index..index + ch.len_utf8()]));
+                } else if start.is_none() {
+                    start = Some(index);
+                }
+            }
+        }
+        if let Some(begin) = start {
+            words.push((begin, &text[begin..]));
+        }
+        Ok(words)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenizer::TokenizeReason;
+    use crate::tokenizer::test_utils::{count, open, tokenize, words};
+
+    #[test]
+    fn test_register_tokenizer_with_unicode61_options() {
+        let conn = open();
+        let words = |args: &[&str], text: &str| words(&conn, args, text);
+        let text = "Østfold Łódź Café Fuß Ǘber ＡＢＣ ﬁsh";
+        // 默认与之前的版本一致
+        assert_eq!(
+            vec!["østfold", "łódź", "café", "fuß", "ǘber", "abc", "fish"],
+            words(&["simple"], text)
+        );
+        assert_eq!(
+            vec!["østfold", "łódź", "café", "fuß", "ǘber", "abc", "fish"],
+            words(&["simple", "remove_diacritics", "0"], text)
+        );
+        // 带有两个附加符号的 ǘ 保留
+        assert_eq!(
+            vec!["østfold", "łodz", "cafe", "fuß", "ǘber", "abc", "fish"],
+            words(&["simple", "remove_diacritics", "1"], text)
+        );
+        assert_eq!(
+            vec!["ostfold", "lodz", "cafe", "fuss", "uber", "abc", "fish"],
+            words(&["simple", "remove_diacritics", "2"], text)
+        );
+        assert_eq!(
+            vec!["Østfold", "Łódź", "Café", "Fuß", "Ǘber", "ABC", "fish"],
+            words(&["simple", "case_sensitive"], text)
+        );
+        assert_eq!(
+            vec!["østfold", "łódź", "café", "fuß", "ǘber", "ａｂｃ", "ﬁsh"],
+            words(&["simple", "normalization=none"], text)
+        );
+        assert_eq!(
+            vec!["ostfold", "lodz", "cafe", "fuss", "uber", "ａｂｃ", "ﬁsh"],
+            words(&["simple", "filters=nfc,remove_diacritics:2,lower"], text)
+        );
+        assert_eq!(
+            vec!["Rust", "国歌"],
+            words(&["jieba", "case_sensitive"], "Rust 国歌")
+        );
+
+        let text = "wi-fi max_len box 2024 国歌";
+        assert_eq!(
+            vec!["wi", "max_len", "box", "2024", "guo", "ge"],
+            words(&["simple"], text)
+        );
+        assert_eq!(
+            vec!["wi-fi", "max", "len", "box", "2024", "guo", "ge"],
+            words(&["simple", "tokenchars", "-"], text)
+        );
+        assert_eq!(
+            vec!["wi", "len", "2024", "guo", "ge"],
+            words(&["simple", "separators", "x"], text)
+        );
+        // 汉字总是单独作为一个单词
+        assert_eq!(
+            vec!["wi", "max", "len", "box", "guo", "ge"],
+            words(&["simple", "categories", "L*"], text)
+        );
+        for args in [
+            &["simple", "remove_diacritics", "3"][..],
+            &["simple", "remove_diacritics"],
+            &["simple", "normalization=nfd"],
+            &["simple", "categories", "Letter"],
+            &["simple", "tokenchars"],
+        ] {
+            assert!(tokenize(&conn, args, TokenizeReason::Document, "x").is_err());
+        }
+
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = \"simple remove_diacritics 2 tokenchars '-'\");
+             INSERT INTO t1(text) VALUES ('Wi-Fi in Łódź'), ('Fuß');",
+        )
+        .unwrap();
+        assert_eq!(1, count(&conn, "t1", "lodz"));
+        assert_eq!(1, count(&conn, "t1", "łódź"));
+        assert_eq!(1, count(&conn, "t1", "\"wi-fi\""));
+        assert_eq!(1, count(&conn, "t1", "fuss"));
+    }
+}
@@ -1,11 +1,218 @@
-use crate::pinyin::has_pinyin;
+use crate::pinyin::{get_word_pinyin, has_pinyin};
+use crate::tokenizer::TokenizeReason;
+use crate::tokenizer::unicode61::Normalizer;
+use rusqlite::Connection;
+use rusqlite::ffi::{sqlite3, sqlite3_is_interrupted};
 use rust_stemmers::{Algorithm, Stemmer};
-use std::sync::LazyLock;
-use unicode_normalization::UnicodeNormalization;
+use std::borrow::Cow;
 
-/// 适用于英语的词干提取器
-pub(super) static EN_STEMMER: LazyLock<Stemmer> =
-    LazyLock::new(|| Stemmer::create(Algorithm::English));
+/// 注册 tokenizer 的数据库连接的指针，用于在创建 tokenizer 时读取 SQLite 表
+#[derive(Clone)]
+pub(crate) struct DbHandle(*mut sqlite3);
+
+// 全局数据随着数据库连接一起销毁，SQLite 在调用 tokenizer 时已经持有这个连接的锁
+unsafe impl Send for DbHandle {}
+
+impl DbHandle {
+    pub(crate) fn new(connection: &Connection) -> Self {
+        Self(unsafe { connection.handle() })
+    }
+
+    /// 借用这个数据库连接，返回的 `Connection` 被销毁时不会关闭连接
+    pub(crate) fn connection(&self) -> Result<Connection, rusqlite::Error> {
+        unsafe { Connection::from_handle(self.0) }
+    }
+
+    /// 数据库连接是否被 `sqlite3_interrupt` 中断
+    pub(crate) fn is_interrupted(&self) -> bool {
+        unsafe { sqlite3_is_interrupted(self.0) != 0 }
+    }
+}
+
+/// 默认使用的词干提取算法
+pub(super) const DEFAULT_STEMMER: Option<Algorithm> = Some(Algorithm::English);
+
+/// 词干提取算法的语言名称和 ISO 639-1 语言代码
+const STEMMER_LANGUAGES: &[(&str, &str, Algorithm)] = &[
+    ("arabic", "ar", Algorithm::Arabic),
+    ("danish", "da", Algorithm::Danish),
+    ("dutch", "nl", Algorithm::Dutch),
+    ("english", "en", Algorithm::English),
+    ("finnish", "fi", Algorithm::Finnish),
+    ("french", "fr", Algorithm::French),
+    ("german", "de", Algorithm::German),
+    ("greek", "el", Algorithm::Greek),
+    ("hungarian", "hu", Algorithm::Hungarian),
+    ("italian", "it", Algorithm::Italian),
+    ("norwegian", "no", Algorithm::Norwegian),
+    ("portuguese", "pt", Algorithm::Portuguese),
+    ("romanian", "ro", Algorithm::Romanian),
+    ("russian", "ru", Algorithm::Russian),
+    ("spanish", "es", Algorithm::Spanish),
+    ("swedish", "sv", Algorithm::Swedish),
+    ("tamil", "ta", Algorithm::Tamil),
+    ("turkish", "tr", Algorithm::Turkish),
+];
+
+/// 通过语言名称或者语言代码查找词干提取算法，不区分大小写
+fn find_stemmer(language: &str) -> Option<Algorithm> {
+    STEMMER_LANGUAGES
+        .iter()
+        .find(|(name, code, _)| {
+            language.eq_ignore_ascii_case(name) || language.eq_ignore_ascii_case(code)
+        })
+        .map(|(_, _, algorithm)| *algorithm)
+}
+
+/// 解析 `stemmer=` 参数，可以是语言名称（`english`）或者语言代码（`en`），`none` 表示不做词干提取
+pub(super) fn parse_stemmer(language: &str) -> Result<Option<Algorithm>, crate::Error> {
+    if language.eq_ignore_ascii_case("none") {
+        return Ok(None);
+    }
+    find_stemmer(language).map(Some).ok_or_else(|| {
+        crate::Error::TokenizerArgumentIncorrect(format!("unsupported stemmer: {language}"))
+    })
+}
+
+/// 根据 FTS5 传入的 locale 选择词干提取算法，例如 `fr`、`de_DE`、`pt-BR.UTF-8`
+///
+/// 只使用 locale 中的语言部分，无法识别的语言返回 `None`
+pub(super) fn locale_stemmer(locale: &str) -> Option<Algorithm> {
+    let language = locale
+        .split(['_', '-', '.', '@'])
+        .next()
+        .unwrap_or_default();
+    find_stemmer(language)
+}
+
+/// 使用 `stemmer` 提取词干，`stemmer` 为 `None` 时返回原词
+fn stem(stemmer: Option<Algorithm>, word: &str) -> Cow<'_, str> {
+    match stemmer {
+        Some(algorithm) => Stemmer::create(algorithm).stem(word),
+        None => Cow::Borrowed(word),
+    }
+}
+
+/// 原词 token 的前缀，用来与词干区分，查询时以这个前缀开头的单词只匹配原词
+pub(super) const ORIGINAL_PREFIX: char = '=';
+
+/// 需要提取词干的单词写入哪些 token
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) enum WordForm {
+    /// 只写入词干
+    Stem,
+    /// 写入文档时，带有 `=` 前缀的原词作为词干的 colocated token 写入
+    StemAndOriginal,
+    /// 查询时只写入带有 `=` 前缀的原词，用于精确匹配单词的原形
+    Original,
+    /// 前缀查询的最后一个单词，不提取词干，作为前缀匹配
+    ///
+    /// 写入了原词的表，带有 `=` 前缀的原词作为 colocated token，前缀匹配以这个前缀开头的原词；
+    /// 否则这个前缀可能比以它开头的单词的词干更长，见 [`prefix_stems`]
+    Prefix {
+        /// 表是否写入了原词
+        index_original: bool,
+    },
+}
+
+impl WordForm {
+    /// 根据 tokenizer 是否写入原词、分词的原因以及单词前是否有 `=` 前缀，确定单词写入哪些 token
+    ///
+    /// `is_last` 表示这个单词是否是分词文本中的最后一个单词，前缀查询只对最后一个单词做前缀匹配
+    pub(super) fn new(
+        index_original: bool,
+        reason: &TokenizeReason,
+        is_last: bool,
+        text: &str,
+        start: usize,
+    ) -> Self {
+        let prefix = match reason {
+            TokenizeReason::Query { prefix } => *prefix && is_last,
+            _ => {
+                return if index_original {
+                    Self::StemAndOriginal
+                } else {
+                    Self::Stem
+                };
+            }
+        };
+        if index_original && text[..start].ends_with(ORIGINAL_PREFIX) {
+            Self::Original
+        } else if prefix {
+            Self::Prefix { index_original }
+        } else {
+            Self::Stem
+        }
+    }
+
+    /// 是否是前缀查询的最后一个单词，这个单词可能不是完整的单词，不做停词过滤
+    pub(super) fn is_prefix(&self) -> bool {
+        matches!(self, Self::Prefix { .. })
+    }
+}
+
+/// 得到需要提取词干的单词写入的 token，第二个返回值是作为 colocated token 写入的 token
+pub(super) fn stem_tokens(
+    word: &str,
+    stemmer: Option<Algorithm>,
+    form: WordForm,
+) -> (String, Vec<String>) {
+    let original_token = || format!("{ORIGINAL_PREFIX}{word}");
+    match form {
+        WordForm::Stem => (stem(stemmer, word).into_owned(), Vec::new()),
+        WordForm::StemAndOriginal => (stem(stemmer, word).into_owned(), vec![original_token()]),
+        WordForm::Original => (original_token(), Vec::new()),
+        WordForm::Prefix {
+            index_original: true,
+        } => (word.to_owned(), vec![original_token()]),
+        WordForm::Prefix {
+            index_original: false,
+        } => (word.to_owned(), prefix_stems(word, stemmer)),
+    }
+}
+
+/// 补全前缀时尝试的常见后缀
+const PREFIX_COMPLETIONS: &[&str] = &[
+    "s", "es", "ed", "er", "ers", "est", "ing", "ings", "ly", "ness", "ment", "ments", "ion",
+    "ions", "ation", "ations", "able", "ible", "ful", "ity", "ive", "ize", "ized", "izing", "al",
+    "ous", "ance", "ence",
+];
+
+/// 补全的候选词干至少包含的字符个数，更短的词干前缀匹配太多的单词
+const MIN_PREFIX_STEM_LEN: usize = 3;
+
+/// 没有写入原词的表中，前缀查询的最后一个单词作为 colocated token 写入的候选词干
+///
+/// 文档中只有词干，以前缀开头的单词的词干可能比前缀更短，例如 `jumping` 的词干 jump 不以 `jumpi` 开头。
+/// 候选词干包括前缀本身的词干（`jumped*` 匹配 jump），以及前缀用常见后缀补全后得到的、比前缀更短的词干：
+/// `jumpi` 补全成 `jumping` 得到 jump，`runni` 补全成 `running` 得到 run，`happines` 补全成 `happiness` 得到 happi
+fn prefix_stems(word: &str, stemmer: Option<Algorithm>) -> Vec<String> {
+    let Some(algorithm) = stemmer else {
+        return Vec::new();
+    };
+    let stemmer = Stemmer::create(algorithm);
+    let mut stems: Vec<String> = Vec::new();
+    let mut push = |stem: Cow<str>| {
+        if stem != word && !stems.iter().any(|existing| *existing == stem) {
+            stems.push(stem.into_owned());
+        }
+    };
+    push(stemmer.stem(word));
+    for suffix in PREFIX_COMPLETIONS {
+        // 前缀的结尾可能已经是后缀的开头，例如 `runni` 以 `ing` 的 i 结尾
+        for overlap in 0..suffix.len() {
+            if !word.ends_with(&suffix[..overlap]) {
+                continue;
+            }
+            let completed = format!("{word}{}", &suffix[overlap..]);
+            let stem = stemmer.stem(&completed);
+            if word.starts_with(stem.as_ref()) && stem.chars().count() >= MIN_PREFIX_STEM_LEN {
+                push(stem);
+            }
+        }
+    }
+    stems
+}
 
 /// 判断是不是由空字符、控制字符、ascii标点字符组成的字符串
 pub(super) fn is_space_or_ascii_punctuation_str(word: &str) -> bool {
@@ -31,30 +238,251 @@ pub(super) fn need_pinyin(word: &str) -> bool {
     false
 }
 
+/// 判断这个单词是否可能是拼音，只有由 ascii 字母组成的单词才可能是拼音
+///
+/// 单个字母也被视为拼音的声母，所以这里不要求单词能被完整拆分成合法拼音
+pub(super) fn is_pinyin_like(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(|ch| ch.is_ascii_lowercase())
+}
+
+/// 将 token 用双引号包裹后写入 match 语句，token 中的双引号需要转义
+pub(super) fn push_quoted(token: &str, buf: &mut String) {
+    buf.push('"');
+    buf.push_str(&token.replace('"', "\"\""));
+    buf.push('"');
+}
+
 /// 对单词做归一化，并转换成小写
 ///
-/// 如果全部都是由 ascii 字符组成的单词，并且长度超过 1，需要返回一个变量用来提示后续步骤做词干提取
+/// 如果全部都是由 ascii 字符或者汉字以外的字母组成的单词，并且长度超过 1，需要返回一个变量用来提示后续步骤做词干提取
 pub(super) fn make_lowercase(word: &str, buf: &mut String) -> bool {
-    buf.clear();
-    let mut need_stem = true;
-    for ch in word.nfkc() {
-        if is_diacritic(ch) {
-            continue;
-        }
-        if ch.is_ascii() {
-            buf.push(ch.to_ascii_lowercase());
-        } else {
-            need_stem = false;
-            buf.extend(ch.to_lowercase());
+    normalize_word(&Normalizer::default(), word, buf)
+}
+
+/// 按照 `normalizer` 对单词做归一化，返回值与 [`make_lowercase`] 相同
+pub(super) fn normalize_word(normalizer: &Normalizer, word: &str, buf: &mut String) -> bool {
+    normalizer.normalize(word, buf);
+    need_stem(buf)
+}
+
+/// 判断单词是否需要提取词干，单词由 ascii 字符或者汉字以外的字母组成，并且长度超过 1
+pub(super) fn need_stem(word: &str) -> bool {
+    // 单个字符不需要提取词干
+    word.chars().nth(1).is_some()
+        && word
+            .chars()
+            .all(|ch| ch.is_ascii() || (ch.is_alphabetic() && !has_pinyin(&ch)))
+}
+
+/// 词语的全拼和首字母，词语中有字符没有拼音时返回空
+///
+/// 只使用一个读音：多音字词语表中的词语使用表中的读音，其余汉字使用最常用的读音，
+/// 避免把多音字其他读音的组合写入索引
+pub(super) fn word_pinyin_tokens(word: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    for combination in get_word_pinyin(word, 1) {
+        let full = combination.concat();
+        let initials = combination
+            .iter()
+            .filter_map(|pinyin| pinyin.chars().next())
+            .collect::<String>();
+        for token in [full, initials] {
+            if !tokens.contains(&token) {
+                tokens.push(token);
+            }
         }
     }
-    if buf.len() <= 1 {
-        // 单个字符不需要提取词干
-        need_stem = false;
-    }
-    need_stem
+    tokens
 }
 
-fn is_diacritic(ch: char) -> bool {
-    ('\u{0300}'..='\u{036f}').contains(&ch)
+#[cfg(test)]
+mod tests {
+    use crate::tokenizer::test_utils::{count, open, search, token, tokenize, tokens};
+    use crate::tokenizer::{TokenizeReason, tokenize_with_fts5};
+
+    #[test]
+    fn test_register_tokenizer_with_stemmer() {
+        let conn = open();
+        let tokens = |args: &[&str], text: &str, locale: Option<&str>| -> Vec<String> {
+            let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+            tokenize_with_fts5(&conn, &args, TokenizeReason::Document, text, locale)
+                .unwrap()
+                .into_iter()
+                .map(|token| token.token)
+                .collect()
+        };
+        assert_eq!(
+            vec!["mang", "continuel"],
+            tokens(
+                &["simple", "stemmer=french"],
+                "Mangeaient continuellement",
+                None
+            )
+        );
+        assert_eq!(
+            vec!["haus", "haus"],
+            tokens(&["jieba", "stemmer=de"], "Hauses Haus", None)
+        );
+        assert_eq!(
+            vec!["running"],
+            tokens(&["simple", "stemmer=none"], "running", None)
+        );
+        // locale 优先于 stemmer 参数，无法识别的 locale 使用 stemmer 参数指定的语言
+        assert_eq!(
+            vec!["mang"],
+            tokens(&["simple", "stemmer=none"], "mangeaient", Some("fr_FR"))
+        );
+        assert_eq!(
+            vec!["приключен"],
+            tokens(&["simple"], "приключения", Some("ru-RU.UTF-8"))
+        );
+        assert_eq!(
+            vec!["mang"],
+            tokens(&["jieba"], "mangeaient", Some("fr-FR.UTF-8"))
+        );
+        assert_eq!(vec!["run"], tokens(&["simple"], "running", Some("zh")));
+        let args = ["simple", "stemmer=klingon"];
+        assert!(tokenize(&conn, &args, TokenizeReason::Document, "a").is_err());
+
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, locale = 1, tokenize = 'simple');
+             INSERT INTO t1(text) VALUES (fts5_locale('fr', 'Ils mangeaient')), ('running');
+             CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize = \"jieba 'stemmer=none'\");
+             INSERT INTO t2(text) VALUES ('running');",
+        )
+        .unwrap();
+        let count_sql = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(
+            1,
+            count_sql("SELECT count(*) FROM t1 WHERE t1 MATCH fts5_locale('fr', 'mangeait')")
+        );
+        assert_eq!(0, count(&conn, "t1", "mangeait"));
+        assert_eq!(1, count(&conn, "t1", "runs"));
+        assert_eq!(0, count(&conn, "t2", "runs"));
+        assert_eq!(1, count(&conn, "t2", "running"));
+        // simple_query 不提取词干，适用于不同 stemmer 配置的表
+        assert_eq!(
+            1,
+            count_sql("SELECT count(*) FROM t2 WHERE t2 MATCH simple_query('Running')")
+        );
+    }
+
+    #[test]
+    fn test_register_tokenizer_with_index_original() {
+        let conn = open();
+        for tokenizer in ["simple", "jieba"] {
+            assert_eq!(
+                vec![
+                    token("jump", false),
+                    token("=jumping", true),
+                    token("32.3", false),
+                    token("=32.3", true),
+                ],
+                tokens(
+                    &conn,
+                    &[tokenizer, "index_original"],
+                    TokenizeReason::Document,
+                    "Jumping 32.3"
+                )
+            );
+            // 查询时以 = 开头的单词只写入原词
+            assert_eq!(
+                vec![token("=jumping", false), token("jump", false)],
+                tokens(
+                    &conn,
+                    &[tokenizer, "index_original"],
+                    TokenizeReason::Query { prefix: false },
+                    "=Jumping jumping"
+                )
+            );
+        }
+
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'simple index_original');
+             CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize = 'jieba index_original');
+             CREATE VIRTUAL TABLE t3 USING fts5(text, tokenize = 'simple');
+             INSERT INTO t1(text) VALUES ('jumping'), ('jumps'), ('jump');
+             INSERT INTO t2(text) VALUES ('jumping'), ('jumps'), ('jump');
+             INSERT INTO t3(text) VALUES ('jumping'), ('jumps'), ('jump');",
+        )
+        .unwrap();
+        let count_sql = |table: &str, query: &str| -> i64 {
+            conn.query_row(
+                &format!("SELECT count(*) FROM {table} WHERE {table} MATCH {query}"),
+                [],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(3, count(&conn, "t1", "jumping"));
+        assert_eq!(1, count(&conn, "t1", "\"=jumping\""));
+        assert_eq!(1, count(&conn, "t1", "\"=jump\""));
+        assert_eq!(3, count_sql("t1", "simple_query('jumping')"));
+        assert_eq!(1, count_sql("t1", "simple_query('=jumping')"));
+        assert_eq!(3, count_sql("t2", "jieba_query('jumping')"));
+        assert_eq!(1, count_sql("t2", "jieba_query('=jumping')"));
+        // 没有启用 index_original 的表，= 开头的单词仍然按照词干匹配
+        assert_eq!(3, count(&conn, "t3", "\"=jumping\""));
+    }
+
+    #[test]
+    fn test_register_tokenizer_with_prefix_query() {
+        let conn = open();
+        let prefix = TokenizeReason::Query { prefix: true };
+        for tokenizer in ["simple", "jieba"] {
+            // 只有最后一个单词是前缀，不提取词干，词干作为 colocated token
+            assert_eq!(
+                vec![
+                    token("jump", false),
+                    token("jumped", false),
+                    token("jump", true),
+                ],
+                tokens(&conn, &[tokenizer], prefix.clone(), "jumped jumped")
+            );
+            assert_eq!(
+                vec![token("runni", false), token("=runni", true)],
+                tokens(
+                    &conn,
+                    &[tokenizer, "index_original"],
+                    prefix.clone(),
+                    "runni"
+                )
+            );
+            // 前缀用常见后缀补全后得到的更短的词干作为 colocated token，可以切分成拼音的前缀同样补全
+            assert_eq!(
+                vec![token("runni", false), token("run", true)],
+                tokens(&conn, &[tokenizer], prefix.clone(), "runni")
+            );
+            assert_eq!(
+                vec![token("jumpi", false), token("jump", true)],
+                tokens(&conn, &[tokenizer], prefix.clone(), "jumpi")
+            );
+            // 前缀可能是更长单词的一部分，不做停词过滤
+            assert_eq!(
+                vec![token("the", false)],
+                tokens(&conn, &[tokenizer], prefix.clone(), "the")
+            );
+        }
+
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE t1 USING fts5(text, tokenize = 'simple');
+             CREATE VIRTUAL TABLE t2 USING fts5(text, tokenize = 'jieba index_original');
+             INSERT INTO t1(text) VALUES ('jumped'), ('jumping'), ('happiness'), ('theory'), ('running');
+             INSERT INTO t2(text) VALUES ('jumped'), ('jumping'), ('happiness'), ('theory');",
+        )
+        .unwrap();
+        // 没有写入原词时，匹配以前缀或者前缀的词干开头的词干
+        assert_eq!(vec!["jumped", "jumping"], search(&conn, "t1", "jumped*"));
+        assert_eq!(vec!["happiness"], search(&conn, "t1", "happy*"));
+        assert_eq!(vec!["theory"], search(&conn, "t1", "the*"));
+        assert_eq!(vec!["running"], search(&conn, "t1", "runni*"));
+        assert_eq!(vec!["running"], search(&conn, "t1", "runnin*"));
+        assert_eq!(vec!["jumped", "jumping"], search(&conn, "t1", "jumpi*"));
+        assert_eq!(vec!["happiness"], search(&conn, "t1", "happines*"));
+        // 写入原词时，匹配以前缀开头的原词
+        assert_eq!(vec!["jumped"], search(&conn, "t2", "jumped*"));
+        assert_eq!(vec!["jumping"], search(&conn, "t2", "jumpi*"));
+        assert_eq!(vec!["happiness"], search(&conn, "t2", "happ*"));
+        assert_eq!(vec!["theory"], search(&conn, "t2", "the*"));
+    }
 }
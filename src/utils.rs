@@ -4,6 +4,13 @@ pub fn to_rusqlite_error(error: crate::Error) -> rusqlite::Error {
     rusqlite::Error::UserFunctionError(format!("{error:?}").into())
 }
 
+/// 获取 `word` 在 `text` 中的字节偏移量，`word` 必须是 `text` 的一个切片
+pub(crate) fn offset_in(text: &str, word: &str) -> usize {
+    let offset = word.as_ptr() as usize - text.as_ptr() as usize;
+    debug_assert!(offset + word.len() <= text.len());
+    offset
+}
+
 pub fn init_logging(default_level: log::LevelFilter) {
     const LOG_LEVEL_ENV: &str = "SQLITE_SIMPLE_TOKENIZER_LOG";
 